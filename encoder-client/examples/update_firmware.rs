@@ -0,0 +1,44 @@
+use encoder_client::{EncoderClient, bootloader};
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn main() {
+    // Load environment variables from .env file (if present)
+    dotenvy::dotenv().ok();
+
+    let target_port = env::var("PICO_ENCODER_UART").unwrap_or_else(|_| {
+        println!("Warning: PICO_ENCODER_UART not set. Defaulting to /dev/ttyACM0");
+        "/dev/ttyACM0".to_string()
+    });
+
+    let uf2 = env::args().nth(1).map(PathBuf::from).unwrap_or_else(|| {
+        PathBuf::from("encoder-firmware/target/thumbv6m-none-eabi/release/encoder-firmware.uf2")
+    });
+
+    let client = match EncoderClient::spawn(&target_port) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Failed to open serial port {}: {}", target_port, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "Rebooting device on {} into the USB bootloader...",
+        target_port
+    );
+    if let Err(e) = client.enter_bootloader() {
+        eprintln!("Failed to send bootloader command: {}", e);
+        std::process::exit(1);
+    }
+    drop(client);
+
+    match bootloader::copy_uf2_when_ready(&uf2, Duration::from_secs(15)) {
+        Ok(drive) => println!("Copied {} to {}", uf2.display(), drive.display()),
+        Err(e) => {
+            eprintln!("Failed to flash {}: {}", uf2.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Helpers for updating the firmware through the RP2040 USB bootloader.
+//!
+//! After `enter_bootloader()` the board re-enumerates as a USB mass-storage drive named
+//! `RPI-RP2`. Copying a UF2 image onto it flashes the firmware and reboots the board.
+
+use crate::EncoderError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// File present in the root of every RP2040 bootloader drive.
+const INFO_FILE: &str = "INFO_UF2.TXT";
+
+/// Interval between scans for the bootloader drive.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Directories under which removable drives are mounted on Linux and macOS.
+fn mount_roots() -> Vec<PathBuf> {
+    let mut roots = vec![PathBuf::from("/Volumes"), PathBuf::from("/media")];
+    if let Ok(user) = std::env::var("USER") {
+        roots.push(Path::new("/media").join(&user));
+        roots.push(Path::new("/run/media").join(&user));
+    }
+    roots
+}
+
+/// Returns the mount point of a connected RP2040 bootloader drive, if one is mounted.
+pub fn find_bootloader_drive() -> Option<PathBuf> {
+    find_bootloader_drive_in(&mount_roots())
+}
+
+/// Looks for an RP2040 bootloader drive directly below any of the given directories.
+fn find_bootloader_drive_in(roots: &[PathBuf]) -> Option<PathBuf> {
+    roots
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .map(|entry| entry.path())
+        .find(|path| is_bootloader_drive(path))
+}
+
+/// Checks the `INFO_UF2.TXT` marker written by the RP2040 boot ROM.
+fn is_bootloader_drive(path: &Path) -> bool {
+    fs::read_to_string(path.join(INFO_FILE)).is_ok_and(|info| info.contains("RP2040"))
+}
+
+/// Waits for the bootloader drive to be mounted and copies a UF2 image onto it.
+///
+/// Returns the mount point the image was written to.
+pub fn copy_uf2_when_ready(uf2: &Path, timeout: Duration) -> Result<PathBuf, EncoderError> {
+    copy_uf2_when_ready_in(uf2, timeout, &mount_roots())
+}
+
+fn copy_uf2_when_ready_in(
+    uf2: &Path,
+    timeout: Duration,
+    roots: &[PathBuf],
+) -> Result<PathBuf, EncoderError> {
+    let file_name = uf2
+        .file_name()
+        .ok_or_else(|| std::io::Error::other("UF2 path has no file name"))?;
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(drive) = find_bootloader_drive_in(roots) {
+            fs::copy(uf2, drive.join(file_name))?;
+            return Ok(drive);
+        }
+        if Instant::now() >= deadline {
+            return Err(EncoderError::BootloaderNotFound);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("encoder-client-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_copy_uf2_to_bootloader_drive() {
+        let root = scratch_dir("bootloader");
+        fs::create_dir_all(root.join("USB_STICK")).unwrap();
+        let drive = root.join("RPI-RP2");
+        fs::create_dir_all(&drive).unwrap();
+        fs::write(
+            drive.join(INFO_FILE),
+            "UF2 Bootloader v3.0\nModel: Raspberry Pi RP2\nBoard-ID: RPI-RP2040\n",
+        )
+        .unwrap();
+        let uf2 = root.join("encoder-firmware.uf2");
+        fs::write(&uf2, b"UF2\n").unwrap();
+
+        let target =
+            copy_uf2_when_ready_in(&uf2, Duration::ZERO, std::slice::from_ref(&root)).unwrap();
+        assert_eq!(target, drive);
+        assert_eq!(
+            fs::read(drive.join("encoder-firmware.uf2")).unwrap(),
+            b"UF2\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_missing_drive_times_out() {
+        let root = scratch_dir("no-bootloader");
+        let uf2 = root.join("encoder-firmware.uf2");
+        fs::write(&uf2, b"UF2\n").unwrap();
+
+        let result = copy_uf2_when_ready_in(&uf2, Duration::ZERO, std::slice::from_ref(&root));
+        assert!(matches!(result, Err(EncoderError::BootloaderNotFound)));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+//! Master for several boards sharing one half-duplex RS-485 bus.
+//!
+//! Boards built with the firmware's `rs485` feature stay silent until polled by address.
+//! [`EncoderBus`] polls every board in turn from a background thread and exposes each one
+//! as an [`EncoderClient`] whose commands are addressed to that board.
+
+use crate::{EncoderClient, EncoderError, SharedState, open_port};
+use encoder_protocol::{
+    Packet, SENSOR_PERIOD_MS, parse_addressed_packet, serialize_addressed_packet,
+};
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long a polled board gets to finish its reply.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Read timeout of the bus port, short enough to notice a missing reply quickly.
+const READ_TIMEOUT: Duration = Duration::from_millis(5);
+
+/// A polling master for encoder boards on a multi-drop bus.
+#[derive(Debug)]
+pub struct EncoderBus {
+    boards: Vec<EncoderClient>,
+    exit_flag: Arc<AtomicBool>,
+    worker_handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for EncoderBus {
+    fn drop(&mut self) {
+        self.exit_flag.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.worker_handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl EncoderBus {
+    /// Opens the bus and starts polling the boards at the given addresses once per sensor
+    /// period.
+    pub fn spawn(port_name: &str, addresses: &[u8]) -> Result<Self, EncoderError> {
+        let mut port = open_port(port_name)?;
+        port.set_timeout(READ_TIMEOUT)?;
+        let writer = Arc::new(Mutex::new(port.try_clone()?));
+
+        let boards: Vec<EncoderClient> = addresses
+            .iter()
+            .map(|&address| EncoderClient::on_bus(Arc::default(), Arc::clone(&writer), address))
+            .collect();
+        let polled: Vec<(u8, Arc<SharedState>)> = boards
+            .iter()
+            .filter_map(|board| Some((board.address?, Arc::clone(&board.state))))
+            .collect();
+        let exit_flag = Arc::new(AtomicBool::new(false));
+        let exit_flag_clone = Arc::clone(&exit_flag);
+
+        let worker_handle = thread::spawn(move || {
+            let mut reader = BufReader::new(port);
+            let mut responding = vec![true; polled.len()];
+            let period = Duration::from_millis(SENSOR_PERIOD_MS.into());
+
+            while !exit_flag_clone.load(Ordering::SeqCst) {
+                let cycle_start = Instant::now();
+                for ((address, state), responding) in polled.iter().zip(responding.iter_mut()) {
+                    // Holding the writer keeps commands from other threads off the bus until
+                    // the board has finished replying.
+                    let Ok(mut port) = writer.lock() else {
+                        return;
+                    };
+                    match poll_board(&mut reader, &mut *port, *address, state) {
+                        Ok(answered) => {
+                            if answered != *responding {
+                                eprintln!(
+                                    "Bus board {} {}",
+                                    address,
+                                    if answered {
+                                        "answering again"
+                                    } else {
+                                        "not answering polls"
+                                    }
+                                );
+                            }
+                            *responding = answered;
+                        }
+                        Err(e) => {
+                            eprintln!("Encoder bus error: {}", e);
+                            return;
+                        }
+                    }
+                }
+                thread::sleep(period.saturating_sub(cycle_start.elapsed()));
+            }
+        });
+
+        Ok(Self {
+            boards,
+            exit_flag,
+            worker_handle: Some(worker_handle),
+        })
+    }
+
+    /// Returns the client for the board at a bus address, if it is being polled.
+    pub fn board(&self, address: u8) -> Option<&EncoderClient> {
+        self.boards
+            .iter()
+            .find(|board| board.address == Some(address))
+    }
+
+    /// Iterates over the clients of all polled boards in polling order.
+    pub fn boards(&self) -> impl Iterator<Item = &EncoderClient> {
+        self.boards.iter()
+    }
+}
+
+/// Polls one board and applies its reply, which ends with its sensor data.
+///
+/// Returns whether the board replied in time. Packets the client wants to send back, such
+/// as restored counts after a restart, are only written once the board has released the bus.
+fn poll_board(
+    reader: &mut impl BufRead,
+    port: &mut impl Write,
+    address: u8,
+    state: &SharedState,
+) -> io::Result<bool> {
+    port.write_all(serialize_addressed_packet(address, &Packet::Poll).as_bytes())?;
+    port.flush()?;
+
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    let mut line = Vec::new();
+    let mut replies = Vec::new();
+    let mut answered = false;
+
+    while !answered && Instant::now() < deadline {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => {
+                // Collisions on the bus garble bytes; lines that are not UTF-8 are skipped.
+                let frame = std::str::from_utf8(&line).unwrap_or_default().trim_end();
+                if let Some((Some(from), packet)) = parse_addressed_packet(frame)
+                    && from == address
+                {
+                    replies.extend(state.handle_line(frame));
+                    answered = matches!(packet, Packet::SensorData(_));
+                }
+                line.clear();
+            }
+            // A partial line stays in the buffer until the rest arrives.
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    for reply in replies {
+        port.write_all(serialize_addressed_packet(address, &reply).as_bytes())?;
+    }
+    port.flush()?;
+    Ok(answered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder_protocol::SensorDataPacket;
+    use std::io::Cursor;
+
+    fn frame(address: u8, packet: Packet) -> String {
+        serialize_addressed_packet(address, &packet).to_string()
+    }
+
+    #[test]
+    fn test_poll_board_routes_reply_by_address() {
+        let state = SharedState::default();
+        let bus_traffic = [
+            frame(3, Packet::SensorData(SensorDataPacket::new(9, &[9; 8]))),
+            frame(2, Packet::Pong { timestamp: 1 }),
+            frame(
+                2,
+                Packet::SensorData(SensorDataPacket::new(5, &[1, 2, 3, 4, 5, 6, 7, 8])),
+            ),
+            frame(2, Packet::SensorData(SensorDataPacket::new(6, &[0; 8]))),
+        ]
+        .concat();
+        let mut reader = Cursor::new(bus_traffic.into_bytes());
+        let mut written = Vec::new();
+
+        assert!(poll_board(&mut reader, &mut written, 2, &state).unwrap());
+        assert_eq!(written, b"$@2:POLL*57\n");
+        assert_eq!(state.sequence(), 5);
+        assert_eq!(state.counts()[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_poll_board_without_reply() {
+        let state = SharedState::default();
+        let mut reader = Cursor::new(Vec::new());
+        let result = poll_board(&mut reader, &mut Vec::new(), 4, &state);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
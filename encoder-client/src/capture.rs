@@ -0,0 +1,142 @@
+//! Reassembly of edge captures streamed by the firmware.
+//!
+//! During a capture the firmware records every step of every encoder with a microsecond
+//! timestamp, then sends the recording in chunks alongside the regular stream. The chunks
+//! are put back together into an [`EdgeCapture`].
+
+use encoder_protocol::{CaptureChunk, EdgeSample};
+
+/// A finished edge capture: every step of every encoder in the order it was seen.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EdgeCapture {
+    pub samples: Vec<EdgeSample>,
+}
+
+impl EdgeCapture {
+    /// Time of the last recorded step in microseconds since the capture started.
+    pub fn duration_us(&self) -> u32 {
+        self.samples.last().map_or(0, |sample| sample.time_us)
+    }
+
+    /// The position of one axis after each of its steps, relative to its position when the
+    /// capture started, as `(time_us, position)` pairs.
+    pub fn positions(&self, encoder_id: u8) -> Vec<(u32, i32)> {
+        let mut position = 0;
+        self.samples
+            .iter()
+            .filter(|sample| sample.encoder_id == encoder_id)
+            .map(|sample| {
+                position += if sample.clockwise { 1 } else { -1 };
+                (sample.time_us, position)
+            })
+            .collect()
+    }
+}
+
+/// Collects capture chunks until a capture is complete.
+#[derive(Debug, Default)]
+pub(crate) struct CaptureAssembler {
+    samples: Vec<EdgeSample>,
+    /// Whether chunks are being collected; cleared when a chunk went missing.
+    receiving: bool,
+}
+
+impl CaptureAssembler {
+    /// Adds a chunk and returns the capture once its last chunk has arrived.
+    pub(crate) fn push(&mut self, chunk: &CaptureChunk) -> Option<EdgeCapture> {
+        if chunk.offset == 0 {
+            self.samples.clear();
+            self.receiving = true;
+        }
+        if !self.receiving {
+            return None;
+        }
+        if usize::from(chunk.offset) != self.samples.len() {
+            eprintln!(
+                "Capture chunk at {} missing, expected {}; discarding capture",
+                chunk.offset,
+                self.samples.len()
+            );
+            self.receiving = false;
+            return None;
+        }
+
+        self.samples.extend_from_slice(chunk.samples());
+        if !chunk.is_last() {
+            return None;
+        }
+        self.receiving = false;
+        Some(EdgeCapture {
+            samples: std::mem::take(&mut self.samples),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder_protocol::CAPTURE_CHUNK_SAMPLES;
+
+    fn chunks(samples: &[EdgeSample]) -> Vec<CaptureChunk> {
+        let total = samples.len() as u16;
+        samples
+            .chunks(CAPTURE_CHUNK_SAMPLES)
+            .enumerate()
+            .map(|(i, part)| {
+                let mut chunk = CaptureChunk {
+                    offset: (i * CAPTURE_CHUNK_SAMPLES) as u16,
+                    total,
+                    len: part.len() as u8,
+                    samples: [EdgeSample::default(); CAPTURE_CHUNK_SAMPLES],
+                };
+                chunk.samples[..part.len()].copy_from_slice(part);
+                chunk
+            })
+            .collect()
+    }
+
+    fn step(encoder_id: u8, clockwise: bool, time_us: u32) -> EdgeSample {
+        EdgeSample {
+            encoder_id,
+            clockwise,
+            time_us,
+        }
+    }
+
+    #[test]
+    fn test_reassembles_capture_into_positions() {
+        let samples: Vec<EdgeSample> = (0..20)
+            .map(|i| step(i % 2, i < 14, u32::from(i) * 10))
+            .collect();
+        let mut assembler = CaptureAssembler::default();
+        let mut finished = None;
+        for chunk in chunks(&samples) {
+            assert!(finished.is_none());
+            finished = assembler.push(&chunk);
+        }
+
+        let capture = finished.unwrap();
+        assert_eq!(capture.samples, samples);
+        assert_eq!(capture.duration_us(), 190);
+        let positions = capture.positions(1);
+        assert_eq!(positions.len(), 10);
+        assert_eq!(positions[6], (130, 7));
+        assert_eq!(positions.last(), Some(&(190, 4)));
+    }
+
+    #[test]
+    fn test_discards_capture_with_missing_chunk() {
+        let samples: Vec<EdgeSample> = (0..20).map(|i| step(0, true, i)).collect();
+        let parts = chunks(&samples);
+        let mut assembler = CaptureAssembler::default();
+        assert!(assembler.push(&parts[0]).is_none());
+        assert!(assembler.push(&parts[2]).is_none());
+
+        // The next capture starts over cleanly.
+        let mut finished = None;
+        for chunk in &parts {
+            finished = assembler.push(chunk);
+        }
+        assert_eq!(finished.map(|c| c.samples.len()), Some(20));
+    }
+}
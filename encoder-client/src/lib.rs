@@ -1,17 +1,38 @@
 //! Client library for reading the RP2040 rotary encoder states over UART.
 //!
-//! Provides a real-time, thread-safe view into the most recent count of all 8 axes.
+//! Provides a real-time, thread-safe view into the most recent count of every axis.
 
+pub mod bootloader;
+pub mod bus;
+pub mod capture;
+pub mod position;
+
+use capture::{CaptureAssembler, EdgeCapture};
+use encoder_protocol::{
+    BAUD_CONFIRM_TIMEOUT_MS, DEFAULT_BAUD, HOST_SILENCE_TIMEOUT_MS, ModuloCommand, Packet,
+    SENSOR_PERIOD_MS, SUPPORTED_BAUD_RATES, SensorDataPacket, create_home_packet,
+    create_set_count_packet, parse_packet, serialize_addressed_packet, serialize_packet,
+};
+use position::CountUnwrapper;
 use serialport::SerialPort;
-use std::io::{BufRead, BufReader};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use thiserror::Error;
-use tokio::io::{AsyncBufReadExt, BufReader as AsyncBufReader};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader, WriteHalf};
+use tokio::sync::{Mutex as AsyncMutex, Notify};
 use tokio::task::JoinHandle as AsyncJoinHandle;
-use tokio_serial::SerialPortBuilderExt;
+use tokio_serial::SerialStream;
+
+pub use encoder_protocol::{
+    ButtonEvent, ButtonGesture, CHANNEL_NAME_LEN, COMPARE_OUTPUTS, CaptureCommand, ChannelConfig,
+    CompareCommand, CompareEvent, DeviceInfo, EdgeSample, EncoderDiagnostics, IndexEvent,
+    LatchEvent, LogLevel, LogMessage, MAX_ENCODERS, ResetReason, SamplingStats, TriggerEdge,
+};
 
 #[derive(Error, Debug)]
 pub enum EncoderError {
@@ -21,15 +42,979 @@ pub enum EncoderError {
     IoError(#[from] std::io::Error),
     #[error("Failed to parse encoder output string")]
     ParseError,
+    #[error("Encoder ID {0} is out of range")]
+    InvalidEncoder(u8),
+    #[error("Encoder {0} has no index input")]
+    NoIndexInput(u8),
+    #[error("Compare output {0} is out of range")]
+    InvalidOutput(u8),
+    #[error("RP2040 bootloader drive did not appear")]
+    BootloaderNotFound,
+    #[error("No encoder board with serial number {0:016X} found")]
+    DeviceNotFound(u64),
+    #[error("Device did not answer in time")]
+    Timeout,
+    #[error("Baud rate {0} is not supported")]
+    UnsupportedBaud(u32),
+    #[error("{0} counts per revolution is out of range")]
+    InvalidCountsPerRev(u32),
+}
+
+/// How long to wait for the once-per-second `INFO` heartbeat when identifying a port.
+const IDENTIFY_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Interval between checks while waiting for the first heartbeat.
+const IDENTIFY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long `read_now` waits for the requested sensor data, a few periods of the stream.
+const READ_NOW_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Quadrature edges per count emitted by the firmware's standard-mode decoder.
+const EDGES_PER_COUNT: u64 = 4;
+
+/// Latched samples kept until taken; the oldest are discarded beyond this.
+const LATCH_BUFFER_CAPACITY: usize = 1024;
+
+/// Interval between checks while waiting for an edge capture, which arrives one chunk per
+/// sensor period.
+const CAPTURE_POLL_INTERVAL: Duration = Duration::from_millis(SENSOR_PERIOD_MS as u64);
+
+/// How long to wait for the device to acknowledge a proposed baud rate.
+const BAUD_ACK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Interval between checks while waiting for the baud rate acknowledgement. Kept short, as
+/// the device switches as soon as the acknowledgement is out.
+const BAUD_ACK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long a negotiated baud rate may go without a valid frame before falling back.
+const BAUD_CONFIRM_TIMEOUT: Duration = Duration::from_millis(BAUD_CONFIRM_TIMEOUT_MS as u64);
+
+/// Interval between pings that keep a negotiated baud rate on the device, well within its
+/// host silence timeout.
+const BAUD_KEEPALIVE_INTERVAL: Duration = Duration::from_millis(HOST_SILENCE_TIMEOUT_MS as u64 / 5);
+
+/// How long to wait for the device to report the configuration of all channels.
+const CHANNEL_REPLY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Interval between checks for channel configuration replies.
+const CHANNEL_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// `log` target of the messages forwarded by the device.
+pub const FIRMWARE_LOG_TARGET: &str = "encoder_firmware";
+
+/// Callback invoked from the background reader for every push-button event.
+pub type ButtonCallback = Box<dyn Fn(ButtonEvent) + Send + Sync>;
+
+/// Callback invoked from the background reader for every index pulse event.
+pub type IndexCallback = Box<dyn Fn(IndexEvent) + Send + Sync>;
+
+/// Callback invoked from the background reader for every sample latched by the trigger input.
+pub type LatchCallback = Box<dyn Fn(LatchEvent) + Send + Sync>;
+
+/// Callback invoked from the background reader for every compare output change.
+pub type CompareCallback = Box<dyn Fn(CompareEvent) + Send + Sync>;
+
+/// Callback invoked from the background reader when the device is found to have restarted.
+pub type RestartCallback = Box<dyn Fn(DeviceRestart) + Send + Sync>;
+
+/// Details of a detected device reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceRestart {
+    /// Session ID the device reported before the reset.
+    pub previous_session_id: u32,
+    /// Identification reported by the device after the reset.
+    pub info: DeviceInfo,
+    /// The last counts received before the reset, with the revolutions of axes counting
+    /// modulo a revolution added back on.
+    pub last_counts: [i32; MAX_ENCODERS],
+}
+
+/// State shared between a client handle and its background reader.
+#[derive(Default)]
+struct SharedState {
+    /// The current encoder counts of every axis, zero past the device's encoder count.
+    counts: RwLock<[i32; MAX_ENCODERS]>,
+    /// Whole revolutions of the axes counting modulo a revolution, 0 for the others.
+    revolutions: RwLock<[i32; MAX_ENCODERS]>,
+    /// Number of encoders the device reported, 0 until it has.
+    encoder_count: AtomicUsize,
+    /// Counts per revolution set with `set_modulo`, 0 for linear axes.
+    counts_per_rev: RwLock<[u32; MAX_ENCODERS]>,
+    /// The last configuration set or reported for each channel.
+    channels: RwLock<[Option<ChannelConfig>; MAX_ENCODERS]>,
+    /// Channel configurations reported by the device since the last request, until taken.
+    channel_replies: Mutex<[Option<ChannelConfig>; MAX_ENCODERS]>,
+    /// The total counts with the wraps of the device's 32-bit counters added back on.
+    positions: RwLock<CountUnwrapper>,
+    /// The current sequence number received from the device counter.
+    sequence: RwLock<u32>,
+    /// Number of sensor data packets received, used to wait for the next one.
+    sensor_updates: Mutex<u64>,
+    /// Signalled to blocking waiters after every sensor data packet.
+    sensor_updated: Condvar,
+    /// Signalled to async waiters after every sensor data packet.
+    sensor_notify: Notify,
+    /// Sensor data packets the device skipped because the link could not keep up.
+    skipped_frames: AtomicU64,
+    /// The most recent index event seen on each axis.
+    last_index: RwLock<[Option<IndexEvent>; MAX_ENCODERS]>,
+    /// Whether each axis has been zeroed at an index pulse since the client connected.
+    homed: RwLock<[bool; MAX_ENCODERS]>,
+    /// Samples latched by the trigger input that have not been taken yet.
+    latches: Mutex<VecDeque<LatchEvent>>,
+    /// Sequence number of the last latched sample, to detect samples dropped by the device.
+    last_latch_seq: Mutex<Option<u16>>,
+    /// The most recent compare output change reported for each axis.
+    last_compare: RwLock<[Option<CompareEvent>; MAX_ENCODERS]>,
+    /// Chunks of the edge capture currently being received.
+    capture: Mutex<CaptureAssembler>,
+    /// The last complete edge capture, until it is taken.
+    finished_capture: Mutex<Option<EdgeCapture>>,
+    /// The most recent signal-quality counters reported for each axis.
+    diagnostics: RwLock<[Option<EncoderDiagnostics>; MAX_ENCODERS]>,
+    /// The most recent Core 1 sampling rate report.
+    sampling: RwLock<Option<SamplingStats>>,
+    /// Axes currently moving faster than the sampling rate can safely track.
+    overspeed: RwLock<[bool; MAX_ENCODERS]>,
+    /// The most recent boot session heartbeat.
+    device_info: RwLock<Option<DeviceInfo>>,
+    /// Total counts received just before the sequence number went backwards, kept until the
+    /// next heartbeat confirms whether the device restarted.
+    counts_before_seq_reset: RwLock<Option<[i32; MAX_ENCODERS]>>,
+    /// Whether to send the last known counts back to the device after it restarts.
+    restore_on_restart: AtomicBool,
+    /// The rate the device acknowledged for the last baud rate proposal, until taken.
+    baud_ack: Mutex<Option<u32>>,
+    /// The negotiated rate the port runs at, while it differs from the default.
+    negotiated_baud: Mutex<Option<u32>>,
+    /// When the last valid frame arrived, to notice that a negotiated rate stopped working.
+    last_frame_at: Mutex<Option<std::time::Instant>>,
+    /// When the last ping kept the negotiated rate alive on the device.
+    last_keepalive_at: Mutex<Option<std::time::Instant>>,
+    /// Registered push-button event listeners.
+    button_callbacks: RwLock<Vec<ButtonCallback>>,
+    /// Registered index event listeners.
+    index_callbacks: RwLock<Vec<IndexCallback>>,
+    /// Registered trigger latch listeners.
+    latch_callbacks: RwLock<Vec<LatchCallback>>,
+    /// Registered compare event listeners.
+    compare_callbacks: RwLock<Vec<CompareCallback>>,
+    /// Registered device restart listeners.
+    restart_callbacks: RwLock<Vec<RestartCallback>>,
+}
+
+impl fmt::Debug for SharedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedState")
+            .field("counts", &self.counts)
+            .field("revolutions", &self.revolutions)
+            .field("encoder_count", &self.encoder_count)
+            .field("counts_per_rev", &self.counts_per_rev)
+            .field("channels", &self.channels)
+            .field("channel_replies", &self.channel_replies)
+            .field("positions", &self.positions)
+            .field("sequence", &self.sequence)
+            .field("sensor_updates", &self.sensor_updates)
+            .field("skipped_frames", &self.skipped_frames)
+            .field("last_index", &self.last_index)
+            .field("homed", &self.homed)
+            .field(
+                "latches",
+                &self.latches.lock().map(|l| l.len()).unwrap_or(0),
+            )
+            .field("last_latch_seq", &self.last_latch_seq)
+            .field("last_compare", &self.last_compare)
+            .field("capture", &self.capture)
+            .field(
+                "finished_capture",
+                &self
+                    .finished_capture
+                    .lock()
+                    .map(|c| c.as_ref().map(|c| c.samples.len()))
+                    .unwrap_or(None),
+            )
+            .field("diagnostics", &self.diagnostics)
+            .field("sampling", &self.sampling)
+            .field("overspeed", &self.overspeed)
+            .field("device_info", &self.device_info)
+            .field("counts_before_seq_reset", &self.counts_before_seq_reset)
+            .field("restore_on_restart", &self.restore_on_restart)
+            .field(
+                "button_callbacks",
+                &self.button_callbacks.read().map(|c| c.len()).unwrap_or(0),
+            )
+            .field(
+                "index_callbacks",
+                &self.index_callbacks.read().map(|c| c.len()).unwrap_or(0),
+            )
+            .field(
+                "latch_callbacks",
+                &self.latch_callbacks.read().map(|c| c.len()).unwrap_or(0),
+            )
+            .field(
+                "compare_callbacks",
+                &self.compare_callbacks.read().map(|c| c.len()).unwrap_or(0),
+            )
+            .field(
+                "restart_callbacks",
+                &self.restart_callbacks.read().map(|c| c.len()).unwrap_or(0),
+            )
+            .finish()
+    }
+}
+
+impl SharedState {
+    /// Applies a received line as it came off the port. Lines that are not UTF-8, as received
+    /// while the two sides briefly run at different baud rates, are dropped like any other
+    /// corrupt frame.
+    fn handle_raw_line(&self, line: &[u8]) -> Vec<Packet> {
+        match std::str::from_utf8(line) {
+            Ok(line) => self.handle_line(line.trim_end()),
+            Err(_) => {
+                eprintln!(
+                    "Failed to parse UART text: '{}'",
+                    String::from_utf8_lossy(line).trim_end()
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Applies a single received line to the shared state, logging frames that fail to parse.
+    ///
+    /// Returns the packets that should be sent back to the device in response.
+    fn handle_line(&self, line: &str) -> Vec<Packet> {
+        let mut replies = Vec::new();
+        let packet = parse_packet(line);
+        if packet.is_some()
+            && let Ok(mut last_frame_at) = self.last_frame_at.lock()
+        {
+            *last_frame_at = Some(std::time::Instant::now());
+        }
+        match packet {
+            Some(Packet::SensorData(mut data)) => {
+                self.encoder_count
+                    .store(usize::from(data.encoder_count), Ordering::Relaxed);
+                // Disabled channels are not reported, so they keep their last values.
+                let (prev_counts, prev_revolutions) = (self.counts(), self.revolutions());
+                for i in (0..MAX_ENCODERS).filter(|&i| !data.enabled[i]) {
+                    data.encoders[i] = prev_counts[i];
+                    if let Some(revolutions) = &mut data.revolutions {
+                        revolutions[i] = prev_revolutions[i];
+                    }
+                }
+                let prev_seq = self.sequence();
+                let prev_totals = self.total_counts();
+                if data.seq < prev_seq
+                    && let Ok(mut snapshot) = self.counts_before_seq_reset.write()
+                {
+                    snapshot.get_or_insert(prev_totals);
+                }
+                // The device numbers every sensor period, so a gap means it dropped frames
+                // rather than fall behind.
+                if data.seq > prev_seq.wrapping_add(1) && self.sensor_updates() > 0 {
+                    let skipped = u64::from(data.seq - prev_seq - 1);
+                    self.skipped_frames.fetch_add(skipped, Ordering::Relaxed);
+                }
+                // Positions wrapping around a revolution are not motion, so rates and
+                // positions are worked out from the totals.
+                let totals = data.total_counts(&self.counts_per_rev());
+                self.check_edge_rates(prev_seq, &prev_totals, data.seq, &totals);
+                if let Ok(mut c) = self.counts.write() {
+                    *c = data.encoders;
+                }
+                if let Ok(mut r) = self.revolutions.write() {
+                    *r = data.revolutions.unwrap_or_default();
+                }
+                if let Ok(mut positions) = self.positions.write() {
+                    positions.update(&totals);
+                }
+                if let Ok(mut s) = self.sequence.write() {
+                    *s = data.seq;
+                }
+                if let Ok(mut updates) = self.sensor_updates.lock() {
+                    *updates += 1;
+                }
+                self.sensor_updated.notify_all();
+                self.sensor_notify.notify_waiters();
+            }
+            Some(Packet::Button(event)) => {
+                if let Ok(callbacks) = self.button_callbacks.read() {
+                    for callback in callbacks.iter() {
+                        callback(event);
+                    }
+                }
+            }
+            Some(Packet::Index(event)) => {
+                let id = usize::from(event.encoder_id);
+                if id < MAX_ENCODERS {
+                    if let Ok(mut last) = self.last_index.write() {
+                        last[id] = Some(event);
+                    }
+                    if event.homed {
+                        if let Ok(mut homed) = self.homed.write() {
+                            homed[id] = true;
+                        }
+                        self.rebase_position(id);
+                    }
+                }
+                if let Ok(callbacks) = self.index_callbacks.read() {
+                    for callback in callbacks.iter() {
+                        callback(event);
+                    }
+                }
+            }
+            Some(Packet::Latch(event)) => {
+                if let Ok(mut last) = self.last_latch_seq.lock()
+                    && let Some(previous) = last.replace(event.seq)
+                {
+                    let missed = event.seq.wrapping_sub(previous).wrapping_sub(1);
+                    if missed > 0 {
+                        eprintln!(
+                            "Warning: device dropped {} trigger latches before #{}",
+                            missed, event.seq
+                        );
+                    }
+                }
+                if let Ok(mut latches) = self.latches.lock() {
+                    if latches.len() == LATCH_BUFFER_CAPACITY {
+                        latches.pop_front();
+                    }
+                    latches.push_back(event);
+                }
+                if let Ok(callbacks) = self.latch_callbacks.read() {
+                    for callback in callbacks.iter() {
+                        callback(event);
+                    }
+                }
+            }
+            Some(Packet::Compare(event)) => {
+                if let Ok(mut last) = self.last_compare.write()
+                    && let Some(slot) = last.get_mut(usize::from(event.encoder_id))
+                {
+                    *slot = Some(event);
+                }
+                if let Ok(callbacks) = self.compare_callbacks.read() {
+                    for callback in callbacks.iter() {
+                        callback(event);
+                    }
+                }
+            }
+            Some(Packet::CaptureData(chunk)) => {
+                let finished = self
+                    .capture
+                    .lock()
+                    .ok()
+                    .and_then(|mut capture| capture.push(&chunk));
+                if let Some(finished) = finished
+                    && let Ok(mut slot) = self.finished_capture.lock()
+                {
+                    *slot = Some(finished);
+                }
+            }
+            Some(Packet::Diagnostics(diag)) => {
+                if let Ok(mut diagnostics) = self.diagnostics.write()
+                    && let Some(slot) = diagnostics.get_mut(usize::from(diag.encoder_id))
+                {
+                    *slot = Some(diag);
+                }
+            }
+            Some(Packet::Sampling(stats)) => {
+                if let Ok(mut sampling) = self.sampling.write() {
+                    *sampling = Some(stats);
+                }
+            }
+            Some(Packet::DeviceInfo(info)) => {
+                self.encoder_count
+                    .store(usize::from(info.encoder_count), Ordering::Relaxed);
+                let previous = self
+                    .device_info
+                    .write()
+                    .ok()
+                    .and_then(|mut current| current.replace(info));
+                let snapshot = self
+                    .counts_before_seq_reset
+                    .write()
+                    .ok()
+                    .and_then(|mut snapshot| snapshot.take());
+                if let Some(previous) = previous
+                    && previous.session_id != info.session_id
+                {
+                    replies.extend(self.handle_restart(previous.session_id, info, snapshot));
+                }
+            }
+            Some(Packet::BaudAck { baud }) => {
+                if let Ok(mut ack) = self.baud_ack.lock() {
+                    *ack = Some(baud);
+                }
+            }
+            Some(Packet::Channel(config)) => {
+                self.set_channel(config);
+                if let Ok(mut replies) = self.channel_replies.lock()
+                    && let Some(slot) = replies.get_mut(usize::from(config.encoder_id))
+                {
+                    *slot = Some(config);
+                }
+            }
+            Some(Packet::Log(message)) => {
+                log::log!(target: FIRMWARE_LOG_TARGET, log_level(message.level), "{}", message.text());
+            }
+            Some(_) => {}
+            None => eprintln!("Failed to parse UART text: '{}'", line),
+        }
+        replies
+    }
+
+    /// Notifies listeners about a device reset and builds the commands that configure the
+    /// channels and the axes counting modulo a revolution again and, if enabled, restore the
+    /// last known positions.
+    fn handle_restart(
+        &self,
+        previous_session_id: u32,
+        info: DeviceInfo,
+        snapshot: Option<[i32; MAX_ENCODERS]>,
+    ) -> Vec<Packet> {
+        // Without a snapshot no packet of the new session has been seen yet, so the stored
+        // counts are still the last ones of the old session.
+        let (last_counts, new_counts) = match snapshot {
+            Some(last) => (last, self.total_counts()),
+            None => (self.total_counts(), [0; MAX_ENCODERS]),
+        };
+        if let Ok(mut c) = self.counts.write() {
+            *c = new_counts;
+        }
+        if let Ok(mut r) = self.revolutions.write() {
+            *r = [0; MAX_ENCODERS];
+        }
+        // Without a restore the device counts from zero again, so do the positions.
+        let restoring = self.restore_on_restart.load(Ordering::SeqCst) || info.counts_restored;
+        if let Ok(mut positions) = self.positions.write() {
+            positions.resync(&new_counts);
+            if !restoring {
+                *positions = CountUnwrapper::default();
+            }
+        }
+        // The new session numbers its trigger latches from the start again.
+        if let Ok(mut last) = self.last_latch_seq.lock() {
+            *last = None;
+        }
+        if let Ok(mut s) = self.sequence.write() {
+            *s = 0;
+        }
+
+        eprintln!(
+            "Device restarted (session {:08X} -> {:08X}, reason {:?})",
+            previous_session_id, info.session_id, info.reset_reason
+        );
+        let restart = DeviceRestart {
+            previous_session_id,
+            info,
+            last_counts,
+        };
+        if let Ok(callbacks) = self.restart_callbacks.read() {
+            for callback in callbacks.iter() {
+                callback(restart);
+            }
+        }
+
+        // The device forgets its configuration, so it samples every channel as wired and
+        // counts linearly until told again.
+        let mut replies: Vec<Packet> = self
+            .channels()
+            .into_iter()
+            .flatten()
+            .filter(|config| *config != ChannelConfig::new(config.encoder_id))
+            .map(Packet::SetChannel)
+            .collect();
+        replies.extend(
+            (0u8..)
+                .zip(self.counts_per_rev())
+                .filter(|(_, counts_per_rev)| *counts_per_rev != 0)
+                .map(|(encoder_id, counts_per_rev)| {
+                    Packet::SetModulo(ModuloCommand {
+                        encoder_id,
+                        counts_per_rev,
+                    })
+                }),
+        );
+
+        // Counts the firmware carried over itself must not be restored a second time.
+        if !self.restore_on_restart.load(Ordering::SeqCst) || info.counts_restored {
+            return replies;
+        }
+        // Movement seen since the reset is added on top of the restored position.
+        replies.extend(
+            (0u8..)
+                .zip(last_counts.iter().zip(new_counts.iter()))
+                .filter(|(_, (last, _))| **last != 0)
+                .map(|(id, (last, new))| create_set_count_packet(id, last.wrapping_add(*new))),
+        );
+        replies
+    }
+
+    /// Compares the edge rate implied by two consecutive packets against the sampling limit
+    /// and warns once each time an axis starts moving too fast to be tracked reliably.
+    fn check_edge_rates(
+        &self,
+        prev_seq: u32,
+        prev: &[i32; MAX_ENCODERS],
+        seq: u32,
+        counts: &[i32; MAX_ENCODERS],
+    ) {
+        let Some(max_rate) = self.max_safe_edge_rate() else {
+            return;
+        };
+        let elapsed_ms = u64::from(seq.wrapping_sub(prev_seq)) * u64::from(SENSOR_PERIOD_MS);
+        if elapsed_ms == 0 || elapsed_ms > 1000 {
+            return;
+        }
+
+        let Ok(mut overspeed) = self.overspeed.write() else {
+            return;
+        };
+        for (i, (curr, prev)) in counts.iter().zip(prev.iter()).enumerate() {
+            let edges = u64::from(curr.wrapping_sub(*prev).unsigned_abs()) * EDGES_PER_COUNT;
+            let rate = edges * 1000 / elapsed_ms;
+            let too_fast = rate > u64::from(max_rate);
+            if too_fast && !overspeed[i] {
+                eprintln!(
+                    "Warning: encoder {} edge rate {} Hz exceeds safely trackable {} Hz, counts may be lost",
+                    i, rate, max_rate
+                );
+            }
+            overspeed[i] = too_fast;
+        }
+    }
+
+    fn max_safe_edge_rate(&self) -> Option<u32> {
+        self.sampling_stats()
+            .map(|stats| stats.max_safe_edge_rate())
+            .filter(|&rate| rate > 0)
+    }
+
+    fn sampling_stats(&self) -> Option<SamplingStats> {
+        self.sampling.read().ok().and_then(|s| *s)
+    }
+
+    fn is_overspeed(&self, encoder_id: u8) -> bool {
+        self.overspeed
+            .read()
+            .map(|o| o.get(usize::from(encoder_id)).copied().unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    fn counts(&self) -> [i32; MAX_ENCODERS] {
+        if let Ok(c) = self.counts.read() {
+            *c
+        } else {
+            [0; MAX_ENCODERS]
+        }
+    }
+
+    fn sequence(&self) -> u32 {
+        if let Ok(s) = self.sequence.read() {
+            *s
+        } else {
+            0
+        }
+    }
+
+    fn sensor_updates(&self) -> u64 {
+        self.sensor_updates.lock().map(|n| *n).unwrap_or(0)
+    }
+
+    /// Blocks until a sensor data packet newer than the `seen` count arrives and returns its
+    /// counts, or `None` on timeout.
+    fn wait_for_sensor_update(&self, seen: u64, timeout: Duration) -> Option<[i32; MAX_ENCODERS]> {
+        let updates = self.sensor_updates.lock().ok()?;
+        let (_updates, result) = self
+            .sensor_updated
+            .wait_timeout_while(updates, timeout, |n| *n == seen)
+            .ok()?;
+        (!result.timed_out()).then(|| self.counts())
+    }
+
+    fn last_index(&self, encoder_id: u8) -> Option<IndexEvent> {
+        self.last_index
+            .read()
+            .ok()
+            .and_then(|last| last.get(usize::from(encoder_id)).copied().flatten())
+    }
+
+    fn revolutions(&self) -> [i32; MAX_ENCODERS] {
+        self.revolutions
+            .read()
+            .map(|r| *r)
+            .unwrap_or([0; MAX_ENCODERS])
+    }
+
+    fn counts_per_rev(&self) -> [u32; MAX_ENCODERS] {
+        self.counts_per_rev
+            .read()
+            .map(|c| *c)
+            .unwrap_or([0; MAX_ENCODERS])
+    }
+
+    fn set_counts_per_rev(&self, encoder_id: u8, counts_per_rev: u32) {
+        if let Ok(mut c) = self.counts_per_rev.write()
+            && let Some(slot) = c.get_mut(usize::from(encoder_id))
+        {
+            *slot = counts_per_rev;
+        }
+    }
+
+    /// The latest counts with the revolutions of axes counting modulo a revolution added
+    /// back on, as the device's counters hold them.
+    fn total_counts(&self) -> [i32; MAX_ENCODERS] {
+        SensorDataPacket {
+            revolutions: Some(self.revolutions()),
+            ..SensorDataPacket::new(0, &self.counts())
+        }
+        .total_counts(&self.counts_per_rev())
+    }
+
+    fn channels(&self) -> [Option<ChannelConfig>; MAX_ENCODERS] {
+        self.channels
+            .read()
+            .map(|c| *c)
+            .unwrap_or([None; MAX_ENCODERS])
+    }
+
+    fn set_channel(&self, config: ChannelConfig) {
+        if let Ok(mut channels) = self.channels.write()
+            && let Some(slot) = channels.get_mut(usize::from(config.encoder_id))
+        {
+            *slot = Some(config);
+        }
+    }
+
+    fn clear_channel_replies(&self) {
+        if let Ok(mut replies) = self.channel_replies.lock() {
+            *replies = [None; MAX_ENCODERS];
+        }
+    }
+
+    /// Takes the channel configurations reported since the last request once each of the
+    /// first `encoder_count` channels has been reported.
+    fn take_channel_replies(&self, encoder_count: usize) -> Option<Vec<ChannelConfig>> {
+        let mut replies = self.channel_replies.lock().ok()?;
+        let configs = replies[..encoder_count]
+            .iter()
+            .copied()
+            .collect::<Option<Vec<_>>>()?;
+        *replies = [None; MAX_ENCODERS];
+        Some(configs)
+    }
+
+    /// Position of an axis within its revolution as a fraction of a revolution, if it counts
+    /// modulo a revolution.
+    fn revolution_fraction(&self, encoder_id: u8) -> Option<f64> {
+        let counts_per_rev = self.counts_per_rev_of(encoder_id)?;
+        // Until the device applies the modulo, or again after it restarted, it reports the
+        // total count.
+        let count = self.counts()[usize::from(encoder_id)].rem_euclid(counts_per_rev as i32);
+        Some(f64::from(count) / f64::from(counts_per_rev))
+    }
+
+    /// Total turns of an axis counting modulo a revolution, from its 64-bit position.
+    fn turns(&self, encoder_id: u8) -> Option<f64> {
+        let counts_per_rev = self.counts_per_rev_of(encoder_id)?;
+        Some(self.positions()[usize::from(encoder_id)] as f64 / f64::from(counts_per_rev))
+    }
+
+    fn counts_per_rev_of(&self, encoder_id: u8) -> Option<u32> {
+        self.counts_per_rev()
+            .get(usize::from(encoder_id))
+            .copied()
+            .filter(|&counts_per_rev| counts_per_rev != 0)
+    }
+
+    fn positions(&self) -> [i64; MAX_ENCODERS] {
+        self.positions
+            .read()
+            .map(|positions| positions.positions())
+            .unwrap_or([0; MAX_ENCODERS])
+    }
+
+    /// Makes an axis's position follow its count again after the count was overwritten.
+    fn rebase_position(&self, encoder_id: usize) {
+        if let Ok(mut positions) = self.positions.write() {
+            positions.rebase(encoder_id);
+        }
+    }
+
+    fn is_homed(&self, encoder_id: u8) -> bool {
+        self.homed
+            .read()
+            .map(|homed| homed.get(usize::from(encoder_id)).copied().unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    fn diagnostics(&self, encoder_id: u8) -> Option<EncoderDiagnostics> {
+        self.diagnostics
+            .read()
+            .ok()
+            .and_then(|diagnostics| diagnostics.get(usize::from(encoder_id)).copied().flatten())
+    }
+
+    fn add_button_callback(&self, callback: ButtonCallback) {
+        if let Ok(mut callbacks) = self.button_callbacks.write() {
+            callbacks.push(callback);
+        }
+    }
+
+    fn add_index_callback(&self, callback: IndexCallback) {
+        if let Ok(mut callbacks) = self.index_callbacks.write() {
+            callbacks.push(callback);
+        }
+    }
+
+    fn add_latch_callback(&self, callback: LatchCallback) {
+        if let Ok(mut callbacks) = self.latch_callbacks.write() {
+            callbacks.push(callback);
+        }
+    }
+
+    fn add_compare_callback(&self, callback: CompareCallback) {
+        if let Ok(mut callbacks) = self.compare_callbacks.write() {
+            callbacks.push(callback);
+        }
+    }
+
+    fn last_compare(&self, encoder_id: u8) -> Option<CompareEvent> {
+        self.last_compare
+            .read()
+            .ok()
+            .and_then(|last| last.get(usize::from(encoder_id)).copied().flatten())
+    }
+
+    fn take_capture(&self) -> Option<EdgeCapture> {
+        self.finished_capture.lock().ok().and_then(|mut c| c.take())
+    }
+
+    fn take_latches(&self) -> Vec<LatchEvent> {
+        self.latches
+            .lock()
+            .map(|mut latches| latches.drain(..).collect())
+            .unwrap_or_default()
+    }
+
+    fn add_restart_callback(&self, callback: RestartCallback) {
+        if let Ok(mut callbacks) = self.restart_callbacks.write() {
+            callbacks.push(callback);
+        }
+    }
+
+    fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.read().ok().and_then(|info| *info)
+    }
+
+    fn take_baud_ack(&self) -> Option<u32> {
+        self.baud_ack.lock().ok().and_then(|mut ack| ack.take())
+    }
+
+    /// Records that the port was switched to `baud`, restarting the wait for a valid frame.
+    fn set_baud(&self, baud: u32) {
+        if let Ok(mut negotiated) = self.negotiated_baud.lock() {
+            *negotiated = (baud != DEFAULT_BAUD).then_some(baud);
+        }
+        if let Ok(mut last_frame_at) = self.last_frame_at.lock() {
+            *last_frame_at = Some(std::time::Instant::now());
+        }
+        if let Ok(mut last_keepalive_at) = self.last_keepalive_at.lock() {
+            *last_keepalive_at = Some(std::time::Instant::now());
+        }
+    }
+
+    /// Whether the port runs at a negotiated rate that has not carried a valid frame for
+    /// longer than the confirmation timeout, as happens when the device fell back or restarted.
+    fn baud_fallback_due(&self) -> bool {
+        let negotiated = self.negotiated_baud.lock().is_ok_and(|baud| baud.is_some());
+        negotiated
+            && self
+                .last_frame_at
+                .lock()
+                .is_ok_and(|at| at.is_none_or(|at| at.elapsed() >= BAUD_CONFIRM_TIMEOUT))
+    }
+
+    /// Whether the port runs at a negotiated rate that needs a ping to keep the device from
+    /// falling back, restarting the interval when it does.
+    fn keepalive_due(&self) -> bool {
+        if !self.negotiated_baud.lock().is_ok_and(|baud| baud.is_some()) {
+            return false;
+        }
+        let Ok(mut last_keepalive_at) = self.last_keepalive_at.lock() else {
+            return false;
+        };
+        if last_keepalive_at.is_some_and(|at| at.elapsed() < BAUD_KEEPALIVE_INTERVAL) {
+            return false;
+        }
+        *last_keepalive_at = Some(std::time::Instant::now());
+        true
+    }
+
+    /// The values of the encoders the device has, none until it reported their number.
+    fn per_encoder<T: Copy>(&self, values: [T; MAX_ENCODERS]) -> Vec<T> {
+        values[..self.encoder_count().unwrap_or(0)].to_vec()
+    }
+
+    /// Number of encoders the device reported, once it has sent a heartbeat or counts.
+    fn encoder_count(&self) -> Option<usize> {
+        match self.encoder_count.load(Ordering::Relaxed) {
+            0 => None,
+            count => Some(count),
+        }
+    }
+
+    /// Validates an encoder ID before it is sent to the device, against the device's
+    /// encoder count once it is known.
+    fn check_encoder_id(&self, encoder_id: u8) -> Result<(), EncoderError> {
+        if usize::from(encoder_id) < self.encoder_count().unwrap_or(MAX_ENCODERS) {
+            Ok(())
+        } else {
+            Err(EncoderError::InvalidEncoder(encoder_id))
+        }
+    }
+
+    /// Validates a home command before it is sent to the device, against the device's index
+    /// inputs once its heartbeat arrived.
+    fn check_home(&self, encoder_id: u8) -> Result<(), EncoderError> {
+        self.check_encoder_id(encoder_id)?;
+        match self.device_info() {
+            Some(info) if !info.has_index(encoder_id) => {
+                Err(EncoderError::NoIndexInput(encoder_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates a compare command before it is sent to the device.
+    fn check_compare_command(&self, command: &CompareCommand) -> Result<(), EncoderError> {
+        self.check_encoder_id(command.encoder_id)?;
+        if command.is_disabled() || usize::from(command.output) < COMPARE_OUTPUTS {
+            Ok(())
+        } else {
+            Err(EncoderError::InvalidOutput(command.output))
+        }
+    }
+
+    /// Validates a modulo command before it is sent to the device.
+    fn check_modulo_command(&self, command: &ModuloCommand) -> Result<(), EncoderError> {
+        self.check_encoder_id(command.encoder_id)?;
+        if i32::try_from(command.counts_per_rev).is_ok() {
+            Ok(())
+        } else {
+            Err(EncoderError::InvalidCountsPerRev(command.counts_per_rev))
+        }
+    }
+}
+
+/// Validates a baud rate before it is proposed to the device.
+fn check_baud(baud: u32) -> Result<(), EncoderError> {
+    if SUPPORTED_BAUD_RATES.contains(&baud) {
+        Ok(())
+    } else {
+        Err(EncoderError::UnsupportedBaud(baud))
+    }
+}
+
+/// Maps the level of a forwarded log message onto the `log` crate's.
+fn log_level(level: LogLevel) -> log::Level {
+    match level {
+        LogLevel::Error => log::Level::Error,
+        LogLevel::Warn => log::Level::Warn,
+        LogLevel::Info => log::Level::Info,
+        LogLevel::Debug => log::Level::Debug,
+    }
+}
+
+/// Opens a serial port at the default baud rate for reading and writing frames.
+fn open_port(port_name: &str) -> Result<Box<dyn SerialPort>, EncoderError> {
+    let mut port = serialport::new(port_name, DEFAULT_BAUD)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .or_else(|e| {
+            if cfg!(target_os = "macos") {
+                // Fallback to baud rate 0 on macOS for virtual ports (e.g. socat pseudo-terminals)
+                serialport::new(port_name, 0)
+                    .timeout(Duration::from_millis(100))
+                    .open()
+            } else {
+                Err(e)
+            }
+        })?;
+
+    // For USB CDC ACM devices (like the RP2040), DTR must be asserted for the host
+    // to receive any data stream.
+    port.write_data_terminal_ready(true).ok();
+    Ok(port)
+}
+
+/// Switches a shared serial port handle to another baud rate.
+fn set_port_baud(
+    port: &Mutex<Box<dyn SerialPort>>,
+    state: &SharedState,
+    baud: u32,
+) -> Result<(), EncoderError> {
+    port.lock()
+        .map_err(|_| std::io::Error::other("serial port lock poisoned"))?
+        .set_baud_rate(baud)?;
+    state.set_baud(baud);
+    Ok(())
+}
+
+/// Returns a port to the default baud rate once a negotiated rate stopped carrying frames.
+fn fall_back_if_silent(port: &Mutex<Box<dyn SerialPort>>, state: &SharedState) {
+    if state.baud_fallback_due() {
+        eprintln!(
+            "No valid frame at the negotiated baud rate, falling back to {}",
+            DEFAULT_BAUD
+        );
+        if let Err(e) = set_port_baud(port, state, DEFAULT_BAUD) {
+            eprintln!("Encoder client baud rate error: {}", e);
+        }
+    }
+}
+
+/// Serializes and writes a single packet through a shared blocking serial port handle,
+/// addressed to one board if the port is a multi-drop bus.
+fn write_packet(
+    writer: &Mutex<Box<dyn SerialPort>>,
+    address: Option<u8>,
+    packet: &Packet,
+) -> Result<(), EncoderError> {
+    let frame = match address {
+        Some(address) => serialize_addressed_packet(address, packet),
+        None => serialize_packet(packet),
+    };
+    let mut writer = writer
+        .lock()
+        .map_err(|_| std::io::Error::other("serial writer lock poisoned"))?;
+    writer.write_all(frame.as_bytes())?;
+    writer.flush()?;
+    Ok(())
 }
 
-/// A client for continuous background reading of the RP2040 8-axis encoder states.
+/// Serializes and writes a single packet through a shared async serial stream.
+async fn write_packet_async(
+    writer: &AsyncMutex<WriteHalf<SerialStream>>,
+    packet: &Packet,
+) -> Result<(), EncoderError> {
+    let frame = serialize_packet(packet);
+    let mut writer = writer.lock().await;
+    writer.write_all(frame.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// A client for continuous background reading of the RP2040 encoder states.
 #[derive(Debug)]
 pub struct EncoderClient {
-    /// The current encoder counts across all eight axes.
-    counts: Arc<RwLock<[i32; 8]>>,
-    /// The current sequence number received from the device counter.
-    sequence: Arc<RwLock<u32>>,
+    state: Arc<SharedState>,
+    /// Write handle used to send commands to the device.
+    writer: Arc<Mutex<Box<dyn SerialPort>>>,
+    /// Bus address of the device when it shares a multi-drop bus with other boards.
+    address: Option<u8>,
     exit_flag: Arc<AtomicBool>,
     worker_handle: Option<JoinHandle<()>>,
 }
@@ -46,35 +1031,19 @@ impl Drop for EncoderClient {
 impl EncoderClient {
     /// Starts retrieving encoder positions from the target serial device at 115,200 baud rate.
     pub fn spawn(port_name: &str) -> Result<Self, EncoderError> {
-        let mut port = serialport::new(port_name, 115_200)
-            .timeout(Duration::from_millis(100))
-            .open()
-            .or_else(|e| {
-                if cfg!(target_os = "macos") {
-                    // Fallback to baud rate 0 on macOS for virtual ports (e.g. socat pseudo-terminals)
-                    serialport::new(port_name, 0)
-                        .timeout(Duration::from_millis(100))
-                        .open()
-                } else {
-                    Err(e)
-                }
-            })?;
+        let port = open_port(port_name)?;
+        let writer = Arc::new(Mutex::new(port.try_clone()?));
 
-        // For USB CDC ACM devices (like the RP2040), DTR must be asserted for the host
-        // to receive any data stream.
-        port.write_data_terminal_ready(true).ok();
-
-        let counts = Arc::new(RwLock::new([0; 8]));
-        let sequence = Arc::new(RwLock::new(0));
+        let state = Arc::new(SharedState::default());
         let exit_flag = Arc::new(AtomicBool::new(false));
 
-        let counts_clone = Arc::clone(&counts);
-        let sequence_clone = Arc::clone(&sequence);
+        let state_clone = Arc::clone(&state);
+        let writer_clone = Arc::clone(&writer);
         let exit_flag_clone = Arc::clone(&exit_flag);
 
         let worker_handle = thread::spawn(move || {
             let mut reader = BufReader::new(port);
-            let mut line = String::new();
+            let mut line = Vec::new();
 
             loop {
                 if exit_flag_clone.load(Ordering::SeqCst) {
@@ -82,18 +1051,12 @@ impl EncoderClient {
                 }
 
                 line.clear();
-                match reader.read_line(&mut line) {
+                match reader.read_until(b'\n', &mut line) {
                     Ok(bytes_read) if bytes_read > 0 => {
-                        let trimmed = line.trim_end();
-                        if let Some((seq, parsed_encoders)) = parse_line(trimmed) {
-                            if let Ok(mut c) = counts_clone.write() {
-                                *c = parsed_encoders;
-                            }
-                            if let Ok(mut s) = sequence_clone.write() {
-                                *s = seq;
+                        for reply in state_clone.handle_raw_line(&line) {
+                            if let Err(e) = write_packet(&writer_clone, None, &reply) {
+                                eprintln!("Encoder client write error: {}", e);
                             }
-                        } else {
-                            eprintln!("Failed to parse UART text: '{}'", trimmed);
                         }
                     }
                     Ok(_) => {
@@ -107,76 +1070,441 @@ impl EncoderClient {
                         }
                     }
                 }
+                fall_back_if_silent(&writer_clone, &state_clone);
+                if state_clone.keepalive_due()
+                    && let Err(e) =
+                        write_packet(&writer_clone, None, &Packet::Ping { timestamp: 0 })
+                {
+                    eprintln!("Encoder client write error: {}", e);
+                }
             }
         });
 
-        Ok(Self {
-            counts,
-            sequence,
-            exit_flag,
-            worker_handle: Some(worker_handle),
-        })
+        Ok(Self {
+            state,
+            writer,
+            address: None,
+            exit_flag,
+            worker_handle: Some(worker_handle),
+        })
+    }
+
+    /// Creates a handle for one board on a multi-drop bus, fed by the bus poller.
+    fn on_bus(
+        state: Arc<SharedState>,
+        writer: Arc<Mutex<Box<dyn SerialPort>>>,
+        address: u8,
+    ) -> Self {
+        Self {
+            state,
+            writer,
+            address: Some(address),
+            exit_flag: Arc::new(AtomicBool::new(false)),
+            worker_handle: None,
+        }
+    }
+
+    /// Scans all serial ports and connects to the board reporting the given serial number.
+    ///
+    /// Each port is given up to one and a half heartbeat periods to identify itself.
+    pub fn spawn_by_serial(serial_number: u64) -> Result<Self, EncoderError> {
+        for port in serialport::available_ports()? {
+            let Ok(client) = Self::spawn(&port.port_name) else {
+                continue;
+            };
+            if client
+                .wait_for_device_info(IDENTIFY_TIMEOUT)
+                .is_some_and(|info| info.serial_number == serial_number)
+            {
+                return Ok(client);
+            }
+        }
+        Err(EncoderError::DeviceNotFound(serial_number))
+    }
+
+    /// Blocks until the first heartbeat arrives or the timeout expires.
+    pub fn wait_for_device_info(&self, timeout: Duration) -> Option<DeviceInfo> {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            if let Some(info) = self.state.device_info() {
+                return Some(info);
+            }
+            if std::time::Instant::now() >= deadline {
+                return None;
+            }
+            thread::sleep(IDENTIFY_POLL_INTERVAL);
+        }
+    }
+
+    /// Gets a thread-safe atomic view of the latest polled encoder orientations, one per
+    /// encoder on the device. Empty until its first heartbeat or counts arrived.
+    pub fn get_counts(&self) -> Vec<i32> {
+        self.state.per_encoder(self.state.counts())
+    }
+
+    /// Number of encoders the device reported, known once its first heartbeat or counts
+    /// arrived.
+    pub fn get_encoder_count(&self) -> Option<usize> {
+        self.state.encoder_count()
+    }
+
+    /// Gets the latest total counts as 64-bit positions that carry on where the device's
+    /// 32-bit counters wrap around. Axes counting modulo a revolution have their revolutions
+    /// added back on.
+    ///
+    /// Positions are reckoned from the last time an axis's count was set, homed or zeroed
+    /// by a device restart. Sized like [`get_counts`](Self::get_counts).
+    pub fn get_positions(&self) -> Vec<i64> {
+        self.state.per_encoder(self.state.positions())
+    }
+
+    /// Gets the whole revolutions of the axes counting modulo a revolution, 0 for the others.
+    /// Sized like [`get_counts`](Self::get_counts).
+    pub fn get_revolutions(&self) -> Vec<i32> {
+        self.state.per_encoder(self.state.revolutions())
+    }
+
+    /// Angle of an axis within its revolution in degrees, from 0 up to 360, if it counts
+    /// modulo a revolution.
+    pub fn get_angle_degrees(&self, encoder_id: u8) -> Option<f64> {
+        self.state
+            .revolution_fraction(encoder_id)
+            .map(|fraction| fraction * 360.0)
+    }
+
+    /// Angle of an axis within its revolution in radians, from 0 up to 2π, if it counts
+    /// modulo a revolution.
+    pub fn get_angle_radians(&self, encoder_id: u8) -> Option<f64> {
+        self.state
+            .revolution_fraction(encoder_id)
+            .map(|fraction| fraction * std::f64::consts::TAU)
+    }
+
+    /// Total turns of an axis counting modulo a revolution, including the fraction of the
+    /// current one.
+    pub fn get_turns(&self, encoder_id: u8) -> Option<f64> {
+        self.state.turns(encoder_id)
+    }
+
+    /// Gets a thread-safe atomic view of the latest emitted packet sequence number.
+    pub fn get_sequence(&self) -> u32 {
+        self.state.sequence()
+    }
+
+    /// Number of sensor data packets the device skipped since the client connected, because
+    /// the serial link could not keep up with the stream. Only meaningful while streaming;
+    /// polled boards skip sequence numbers between requests.
+    pub fn get_skipped_frames(&self) -> u64 {
+        self.state.skipped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Requests the current counts from the device and waits for the reply, one per encoder
+    /// on the device.
+    ///
+    /// A board built with the `polled` firmware feature answers at once; a streaming board
+    /// answers with its next periodic packet. Boards on an [`bus::EncoderBus`] are not sent a
+    /// request, as the bus master polls them continuously.
+    pub fn read_now(&self) -> Result<Vec<i32>, EncoderError> {
+        let seen = self.state.sensor_updates();
+        if self.address.is_none() {
+            self.send(&Packet::GetCounts)?;
+        }
+        self.state
+            .wait_for_sensor_update(seen, READ_NOW_TIMEOUT)
+            .map(|counts| self.state.per_encoder(counts))
+            .ok_or(EncoderError::Timeout)
+    }
+
+    /// Registers a callback invoked on the reader thread for every push-button event.
+    pub fn on_button_event<F>(&self, callback: F)
+    where
+        F: Fn(ButtonEvent) + Send + Sync + 'static,
+    {
+        self.state.add_button_callback(Box::new(callback));
+    }
+
+    /// Returns the most recent index event seen on an axis, if any.
+    pub fn get_last_index(&self, encoder_id: u8) -> Option<IndexEvent> {
+        self.state.last_index(encoder_id)
+    }
+
+    /// Whether an axis has been zeroed at an index pulse since the client connected.
+    pub fn is_homed(&self, encoder_id: u8) -> bool {
+        self.state.is_homed(encoder_id)
+    }
+
+    /// Returns the latest illegal-transition and glitch counters reported for an axis.
+    ///
+    /// Counters that keep growing while the axis moves normally point to bad cabling or noise.
+    pub fn get_diagnostics(&self, encoder_id: u8) -> Option<EncoderDiagnostics> {
+        self.state.diagnostics(encoder_id)
+    }
+
+    /// Returns the latest Core 1 sampling loop rate and worst-case loop time.
+    pub fn get_sampling_stats(&self) -> Option<SamplingStats> {
+        self.state.sampling_stats()
+    }
+
+    /// Highest per-channel edge rate in Hz the device can currently track without losing steps.
+    pub fn max_safe_edge_rate(&self) -> Option<u32> {
+        self.state.max_safe_edge_rate()
+    }
+
+    /// Whether an axis moved faster than `max_safe_edge_rate` between the last two packets.
+    pub fn is_overspeed(&self, encoder_id: u8) -> bool {
+        self.state.is_overspeed(encoder_id)
+    }
+
+    /// Registers a callback invoked on the reader thread for every index pulse event.
+    pub fn on_index_event<F>(&self, callback: F)
+    where
+        F: Fn(IndexEvent) + Send + Sync + 'static,
+    {
+        self.state.add_index_callback(Box::new(callback));
+    }
+
+    /// Asks the device to zero an axis exactly at its next index pulse.
+    ///
+    /// Completion is reported as an index event with `homed` set. Axes the device reported
+    /// without an index input are rejected.
+    pub fn home_on_next_index(&self, encoder_id: u8) -> Result<(), EncoderError> {
+        self.state.check_home(encoder_id)?;
+        self.send(&create_home_packet(encoder_id))
+    }
+
+    /// Overwrites the count of a single axis on the device.
+    pub fn set_count(&self, encoder_id: u8, count: i32) -> Result<(), EncoderError> {
+        self.state.check_encoder_id(encoder_id)?;
+        self.send(&create_set_count_packet(encoder_id, count))?;
+        self.state.rebase_position(usize::from(encoder_id));
+        Ok(())
+    }
+
+    /// Makes the device report an axis modulo `counts_per_rev`, as its position within the
+    /// revolution plus whole revolutions, or linearly again with 0.
+    ///
+    /// The setting is sent again whenever the device restarts.
+    pub fn set_modulo(&self, encoder_id: u8, counts_per_rev: u32) -> Result<(), EncoderError> {
+        let command = ModuloCommand {
+            encoder_id,
+            counts_per_rev,
+        };
+        self.state.check_modulo_command(&command)?;
+        self.send(&Packet::SetModulo(command))?;
+        self.state.set_counts_per_rev(encoder_id, counts_per_rev);
+        Ok(())
+    }
+
+    /// Enables or disables a channel, inverts its counting direction or names it.
+    ///
+    /// A disabled channel is neither sampled nor transmitted and keeps its last count here.
+    /// Inverting a channel is the same as swapping its A and B wires. The configuration is
+    /// sent again whenever the device restarts.
+    pub fn set_channel_config(&self, config: ChannelConfig) -> Result<(), EncoderError> {
+        self.state.check_encoder_id(config.encoder_id)?;
+        self.send(&Packet::SetChannel(config))?;
+        self.state.set_channel(config);
+        Ok(())
+    }
+
+    /// Returns the last configuration set or read for a channel, if any.
+    pub fn get_channel_config(&self, encoder_id: u8) -> Option<ChannelConfig> {
+        self.state
+            .channels()
+            .get(usize::from(encoder_id))
+            .copied()
+            .flatten()
+    }
+
+    /// Asks the device for the configuration of all its channels and waits for the replies.
+    pub fn read_channel_configs(&self) -> Result<Vec<ChannelConfig>, EncoderError> {
+        // The counts tell how many channels there are.
+        if self.state.encoder_count().is_none() {
+            self.read_now()?;
+        }
+        let encoder_count = self.state.encoder_count().ok_or(EncoderError::Timeout)?;
+        self.state.clear_channel_replies();
+        for encoder_id in 0..encoder_count as u8 {
+            self.send(&Packet::GetChannel { encoder_id })?;
+        }
+        // A polled board only sends its replies along with the counts.
+        if self.address.is_none() {
+            self.send(&Packet::GetCounts)?;
+        }
+
+        let deadline = std::time::Instant::now() + CHANNEL_REPLY_TIMEOUT;
+        loop {
+            if let Some(configs) = self.state.take_channel_replies(encoder_count) {
+                return Ok(configs);
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(EncoderError::Timeout);
+            }
+            thread::sleep(CHANNEL_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the samples latched by the trigger input since the last call, oldest first.
+    pub fn take_latches(&self) -> Vec<LatchEvent> {
+        self.state.take_latches()
+    }
+
+    /// Registers a callback invoked on the reader thread for every sample latched by the
+    /// trigger input.
+    pub fn on_latch_event<F>(&self, callback: F)
+    where
+        F: Fn(LatchEvent) + Send + Sync + 'static,
+    {
+        self.state.add_latch_callback(Box::new(callback));
+    }
+
+    /// Selects the edge of the trigger input that latches the counts.
+    pub fn set_trigger_edge(&self, edge: TriggerEdge) -> Result<(), EncoderError> {
+        self.send(&Packet::SetTriggerEdge(edge))
+    }
+
+    /// Sets the least severe level of log messages the device forwards, or `None` to forward
+    /// none. Forwarded messages are emitted through the `log` crate with the
+    /// `encoder_firmware` target.
+    pub fn set_log_level(&self, level: Option<LogLevel>) -> Result<(), EncoderError> {
+        self.send(&Packet::SetLogLevel(level))
+    }
+
+    /// Configures an axis's compare register on the device, replacing any previous one.
+    ///
+    /// The device drives the chosen output itself; changes are reported as compare events.
+    pub fn set_compare(&self, command: CompareCommand) -> Result<(), EncoderError> {
+        self.state.check_compare_command(&command)?;
+        self.send(&Packet::SetCompare(command))
     }
 
-    /// Gets a thread-safe atomic view of the latest polled 8 encoder orientations.
-    pub fn get_counts(&self) -> [i32; 8] {
-        if let Ok(c) = self.counts.read() {
-            *c
-        } else {
-            [0; 8]
-        }
+    /// Disables an axis's compare register.
+    pub fn clear_compare(&self, encoder_id: u8) -> Result<(), EncoderError> {
+        self.set_compare(CompareCommand::disable(encoder_id))
     }
 
-    /// Gets a thread-safe atomic view of the latest emitted packet sequence number.
-    pub fn get_sequence(&self) -> u32 {
-        if let Ok(s) = self.sequence.read() {
-            *s
-        } else {
-            0
+    /// Returns the most recent compare output change reported for an axis, if any.
+    pub fn get_last_compare(&self, encoder_id: u8) -> Option<CompareEvent> {
+        self.state.last_compare(encoder_id)
+    }
+
+    /// Starts recording every step of every axis with a microsecond timestamp on the device.
+    ///
+    /// Once recording ends, the device streams the capture alongside the regular packets, one
+    /// chunk per 10 ms. Any capture not yet taken is discarded.
+    pub fn start_capture(&self, command: CaptureCommand) -> Result<(), EncoderError> {
+        self.state.take_capture();
+        self.send(&Packet::StartCapture(command))
+    }
+
+    /// Takes the last complete edge capture, if one has arrived.
+    pub fn take_capture(&self) -> Option<EdgeCapture> {
+        self.state.take_capture()
+    }
+
+    /// Waits until a complete edge capture has arrived and takes it.
+    pub fn wait_for_capture(&self, timeout: Duration) -> Option<EdgeCapture> {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            if let Some(capture) = self.state.take_capture() {
+                return Some(capture);
+            }
+            if std::time::Instant::now() >= deadline {
+                return None;
+            }
+            thread::sleep(CAPTURE_POLL_INTERVAL);
         }
     }
-}
 
-/// Helper method to cleanly extract the string format "$Seq:E0,E1,E2,E3,E4,E5,E6,E7*XX"
-fn parse_line(line: &str) -> Option<(u32, [i32; 8])> {
-    let start_idx = line.find('$')?;
-    let slice = &line[start_idx + 1..];
+    /// Registers a callback invoked on the reader thread for every compare output change.
+    pub fn on_compare_event<F>(&self, callback: F)
+    where
+        F: Fn(CompareEvent) + Send + Sync + 'static,
+    {
+        self.state.add_compare_callback(Box::new(callback));
+    }
 
-    let star_idx = slice.find('*')?;
-    let payload = &slice[..star_idx];
+    /// Returns the latest boot session heartbeat received from the device.
+    pub fn get_device_info(&self) -> Option<DeviceInfo> {
+        self.state.device_info()
+    }
 
-    let checksum_hex = slice.get(star_idx + 1..star_idx + 3)?;
-    let expected_checksum = u8::from_str_radix(checksum_hex, 16).ok()?;
+    /// Registers a callback invoked on the reader thread when the device is found to have restarted.
+    pub fn on_device_restarted<F>(&self, callback: F)
+    where
+        F: Fn(DeviceRestart) + Send + Sync + 'static,
+    {
+        self.state.add_restart_callback(Box::new(callback));
+    }
 
-    let computed_checksum = payload.bytes().fold(0, |acc, b| acc ^ b);
-    if computed_checksum != expected_checksum {
-        return None;
+    /// Enables sending the last known counts back to the device after it restarts.
+    pub fn set_restore_on_restart(&self, enabled: bool) {
+        self.state
+            .restore_on_restart
+            .store(enabled, Ordering::SeqCst);
     }
 
-    let mut parts = payload.split(':');
-    let seq_str = parts.next()?;
-    let counts_str = parts.next()?;
+    /// Switches the link to another baud rate from [`SUPPORTED_BAUD_RATES`].
+    ///
+    /// The device acknowledges the proposal at the current rate and switches. The call returns
+    /// once sensor data arrives at the new rate. If none arrives within
+    /// [`BAUD_CONFIRM_TIMEOUT_MS`], the port goes back to [`DEFAULT_BAUD`], as does the device
+    /// when it hears nothing from the host. Only streaming boards support this.
+    pub fn negotiate_baud(&self, baud: u32) -> Result<(), EncoderError> {
+        check_baud(baud)?;
+        self.state.take_baud_ack();
+        self.send(&Packet::SetBaud { baud })?;
 
-    let seq: u32 = seq_str.parse().ok()?;
+        let deadline = std::time::Instant::now() + BAUD_ACK_TIMEOUT;
+        let acked = loop {
+            if let Some(acked) = self.state.take_baud_ack() {
+                break acked;
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(EncoderError::Timeout);
+            }
+            thread::sleep(BAUD_ACK_POLL_INTERVAL);
+        };
+        if acked != baud {
+            return Err(EncoderError::UnsupportedBaud(baud));
+        }
 
-    let mut encoders = [0i32; 8];
-    for (i, val_str) in counts_str.split(',').enumerate() {
-        if i >= 8 {
-            break;
+        set_port_baud(&self.writer, &self.state, baud)?;
+        let seen = self.state.sensor_updates();
+        if self
+            .state
+            .wait_for_sensor_update(seen, BAUD_CONFIRM_TIMEOUT)
+            .is_none()
+        {
+            set_port_baud(&self.writer, &self.state, DEFAULT_BAUD)?;
+            return Err(EncoderError::Timeout);
         }
-        encoders[i] = val_str.parse().ok()?;
+        // Any frame confirms the new rate to the device.
+        self.send(&Packet::Ping { timestamp: 0 })
+    }
+
+    /// Reboots the device into the RP2040 USB bootloader for a firmware update.
+    ///
+    /// The serial stream stops once the device resets; use
+    /// [`bootloader::copy_uf2_when_ready`] to flash the new image.
+    pub fn enter_bootloader(&self) -> Result<(), EncoderError> {
+        self.send(&Packet::EnterBootloader)
     }
 
-    Some((seq, encoders))
+    /// Serializes and writes a single packet to the device.
+    fn send(&self, packet: &Packet) -> Result<(), EncoderError> {
+        write_packet(&self.writer, self.address, packet)
+    }
 }
 
-/// A client for continuous background reading of the RP2040 8-axis encoder states asynchronously.
+/// A client for continuous background reading of the RP2040 encoder states asynchronously.
 #[derive(Debug)]
 pub struct AsyncEncoderClient {
-    /// The current encoder counts across all eight axes.
-    counts: Arc<RwLock<[i32; 8]>>,
-    /// The current sequence number received from the device counter.
-    sequence: Arc<RwLock<u32>>,
+    state: Arc<SharedState>,
+    /// Write half of the serial stream used to send commands to the device.
+    writer: Arc<AsyncMutex<WriteHalf<SerialStream>>>,
+    /// Blocking handle on the same port, used to change its baud rate.
+    control: Arc<Mutex<Box<dyn SerialPort>>>,
     exit_flag: Arc<AtomicBool>,
     worker_handle: Option<AsyncJoinHandle<()>>,
 }
@@ -193,15 +1521,15 @@ impl Drop for AsyncEncoderClient {
 impl AsyncEncoderClient {
     /// Starts retrieving encoder positions from the target serial device asynchronously.
     pub fn spawn(port_name: &str) -> Result<Self, EncoderError> {
-        let mut port = tokio_serial::new(port_name, 115_200)
+        let mut port = serialport::new(port_name, DEFAULT_BAUD)
             .timeout(Duration::from_millis(100))
-            .open_native_async()
+            .open_native()
             .or_else(|e| {
                 if cfg!(target_os = "macos") {
                     // Fallback to baud rate 0 on macOS for virtual ports (e.g. socat pseudo-terminals)
-                    tokio_serial::new(port_name, 0)
+                    serialport::new(port_name, 0)
                         .timeout(Duration::from_millis(100))
-                        .open_native_async()
+                        .open_native()
                 } else {
                     Err(e)
                 }
@@ -210,18 +1538,24 @@ impl AsyncEncoderClient {
         // For USB CDC ACM devices (like the RP2040), DTR must be asserted for the host
         // to receive any data stream.
         port.write_data_terminal_ready(true).ok();
+        // The stream cannot be cloned once it is async, so keep a blocking duplicate of the
+        // handle for port settings.
+        let control: Box<dyn SerialPort> = Box::new(port.try_clone_native()?);
+        let control = Arc::new(Mutex::new(control));
+        let (port_reader, writer) = tokio::io::split(SerialStream::try_from(port)?);
+        let writer = Arc::new(AsyncMutex::new(writer));
 
-        let counts = Arc::new(RwLock::new([0; 8]));
-        let sequence = Arc::new(RwLock::new(0));
+        let state = Arc::new(SharedState::default());
         let exit_flag = Arc::new(AtomicBool::new(false));
 
-        let counts_clone = Arc::clone(&counts);
-        let sequence_clone = Arc::clone(&sequence);
+        let state_clone = Arc::clone(&state);
+        let writer_clone = Arc::clone(&writer);
+        let control_clone = Arc::clone(&control);
         let exit_flag_clone = Arc::clone(&exit_flag);
 
         let worker_handle = tokio::spawn(async move {
-            let mut reader = AsyncBufReader::new(port);
-            let mut line = String::new();
+            let mut reader = AsyncBufReader::new(port_reader);
+            let mut line = Vec::new();
 
             loop {
                 if exit_flag_clone.load(Ordering::SeqCst) {
@@ -229,18 +1563,12 @@ impl AsyncEncoderClient {
                 }
 
                 line.clear();
-                match reader.read_line(&mut line).await {
+                match reader.read_until(b'\n', &mut line).await {
                     Ok(bytes_read) if bytes_read > 0 => {
-                        let trimmed = line.trim_end();
-                        if let Some((seq, parsed_encoders)) = parse_line(trimmed) {
-                            if let Ok(mut c) = counts_clone.write() {
-                                *c = parsed_encoders;
-                            }
-                            if let Ok(mut s) = sequence_clone.write() {
-                                *s = seq;
+                        for reply in state_clone.handle_raw_line(&line) {
+                            if let Err(e) = write_packet_async(&writer_clone, &reply).await {
+                                eprintln!("AsyncEncoder client write error: {}", e);
                             }
-                        } else {
-                            eprintln!("Failed to parse UART text: '{}'", trimmed);
                         }
                     }
                     Ok(_) => {
@@ -254,53 +1582,963 @@ impl AsyncEncoderClient {
                         }
                     }
                 }
+                fall_back_if_silent(&control_clone, &state_clone);
+                if state_clone.keepalive_due()
+                    && let Err(e) =
+                        write_packet_async(&writer_clone, &Packet::Ping { timestamp: 0 }).await
+                {
+                    eprintln!("AsyncEncoder client write error: {}", e);
+                }
             }
         });
 
         Ok(Self {
-            counts,
-            sequence,
+            state,
+            writer,
+            control,
             exit_flag,
             worker_handle: Some(worker_handle),
         })
     }
 
-    /// Gets a thread-safe atomic view of the latest polled 8 encoder orientations.
-    pub fn get_counts(&self) -> [i32; 8] {
-        if let Ok(c) = self.counts.read() {
-            *c
-        } else {
-            [0; 8]
+    /// Scans all serial ports and connects to the board reporting the given serial number.
+    ///
+    /// Each port is given up to one and a half heartbeat periods to identify itself.
+    pub async fn spawn_by_serial(serial_number: u64) -> Result<Self, EncoderError> {
+        for port in serialport::available_ports()? {
+            let Ok(client) = Self::spawn(&port.port_name) else {
+                continue;
+            };
+            if client
+                .wait_for_device_info(IDENTIFY_TIMEOUT)
+                .await
+                .is_some_and(|info| info.serial_number == serial_number)
+            {
+                return Ok(client);
+            }
+        }
+        Err(EncoderError::DeviceNotFound(serial_number))
+    }
+
+    /// Waits until the first heartbeat arrives or the timeout expires.
+    pub async fn wait_for_device_info(&self, timeout: Duration) -> Option<DeviceInfo> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(info) = self.state.device_info() {
+                return Some(info);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(IDENTIFY_POLL_INTERVAL).await;
         }
     }
 
+    /// Gets a thread-safe atomic view of the latest polled encoder orientations, one per
+    /// encoder on the device. Empty until its first heartbeat or counts arrived.
+    pub fn get_counts(&self) -> Vec<i32> {
+        self.state.per_encoder(self.state.counts())
+    }
+
+    /// Number of encoders the device reported, known once its first heartbeat or counts
+    /// arrived.
+    pub fn get_encoder_count(&self) -> Option<usize> {
+        self.state.encoder_count()
+    }
+
+    /// Gets the latest total counts as 64-bit positions that carry on where the device's
+    /// 32-bit counters wrap around. Axes counting modulo a revolution have their revolutions
+    /// added back on.
+    ///
+    /// Positions are reckoned from the last time an axis's count was set, homed or zeroed
+    /// by a device restart. Sized like [`get_counts`](Self::get_counts).
+    pub fn get_positions(&self) -> Vec<i64> {
+        self.state.per_encoder(self.state.positions())
+    }
+
+    /// Gets the whole revolutions of the axes counting modulo a revolution, 0 for the others.
+    /// Sized like [`get_counts`](Self::get_counts).
+    pub fn get_revolutions(&self) -> Vec<i32> {
+        self.state.per_encoder(self.state.revolutions())
+    }
+
+    /// Angle of an axis within its revolution in degrees, from 0 up to 360, if it counts
+    /// modulo a revolution.
+    pub fn get_angle_degrees(&self, encoder_id: u8) -> Option<f64> {
+        self.state
+            .revolution_fraction(encoder_id)
+            .map(|fraction| fraction * 360.0)
+    }
+
+    /// Angle of an axis within its revolution in radians, from 0 up to 2π, if it counts
+    /// modulo a revolution.
+    pub fn get_angle_radians(&self, encoder_id: u8) -> Option<f64> {
+        self.state
+            .revolution_fraction(encoder_id)
+            .map(|fraction| fraction * std::f64::consts::TAU)
+    }
+
+    /// Total turns of an axis counting modulo a revolution, including the fraction of the
+    /// current one.
+    pub fn get_turns(&self, encoder_id: u8) -> Option<f64> {
+        self.state.turns(encoder_id)
+    }
+
     /// Gets a thread-safe atomic view of the latest emitted packet sequence number.
     pub fn get_sequence(&self) -> u32 {
-        if let Ok(s) = self.sequence.read() {
-            *s
-        } else {
-            0
+        self.state.sequence()
+    }
+
+    /// Number of sensor data packets the device skipped since the client connected, because
+    /// the serial link could not keep up with the stream. Only meaningful while streaming;
+    /// polled boards skip sequence numbers between requests.
+    pub fn get_skipped_frames(&self) -> u64 {
+        self.state.skipped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Requests the current counts from the device and waits for the reply, one per encoder
+    /// on the device.
+    ///
+    /// A board built with the `polled` firmware feature answers at once; a streaming board
+    /// answers with its next periodic packet.
+    pub async fn read_now(&self) -> Result<Vec<i32>, EncoderError> {
+        let updated = self.state.sensor_notify.notified();
+        tokio::pin!(updated);
+        // Register before sending so a fast reply cannot slip past.
+        updated.as_mut().enable();
+        self.send(&Packet::GetCounts).await?;
+        tokio::time::timeout(READ_NOW_TIMEOUT, updated)
+            .await
+            .map_err(|_| EncoderError::Timeout)?;
+        Ok(self.state.per_encoder(self.state.counts()))
+    }
+
+    /// Registers a callback invoked on the reader task for every push-button event.
+    pub fn on_button_event<F>(&self, callback: F)
+    where
+        F: Fn(ButtonEvent) + Send + Sync + 'static,
+    {
+        self.state.add_button_callback(Box::new(callback));
+    }
+
+    /// Returns the most recent index event seen on an axis, if any.
+    pub fn get_last_index(&self, encoder_id: u8) -> Option<IndexEvent> {
+        self.state.last_index(encoder_id)
+    }
+
+    /// Whether an axis has been zeroed at an index pulse since the client connected.
+    pub fn is_homed(&self, encoder_id: u8) -> bool {
+        self.state.is_homed(encoder_id)
+    }
+
+    /// Returns the latest illegal-transition and glitch counters reported for an axis.
+    ///
+    /// Counters that keep growing while the axis moves normally point to bad cabling or noise.
+    pub fn get_diagnostics(&self, encoder_id: u8) -> Option<EncoderDiagnostics> {
+        self.state.diagnostics(encoder_id)
+    }
+
+    /// Returns the latest Core 1 sampling loop rate and worst-case loop time.
+    pub fn get_sampling_stats(&self) -> Option<SamplingStats> {
+        self.state.sampling_stats()
+    }
+
+    /// Highest per-channel edge rate in Hz the device can currently track without losing steps.
+    pub fn max_safe_edge_rate(&self) -> Option<u32> {
+        self.state.max_safe_edge_rate()
+    }
+
+    /// Whether an axis moved faster than `max_safe_edge_rate` between the last two packets.
+    pub fn is_overspeed(&self, encoder_id: u8) -> bool {
+        self.state.is_overspeed(encoder_id)
+    }
+
+    /// Registers a callback invoked on the reader task for every index pulse event.
+    pub fn on_index_event<F>(&self, callback: F)
+    where
+        F: Fn(IndexEvent) + Send + Sync + 'static,
+    {
+        self.state.add_index_callback(Box::new(callback));
+    }
+
+    /// Asks the device to zero an axis exactly at its next index pulse.
+    ///
+    /// Completion is reported as an index event with `homed` set. Axes the device reported
+    /// without an index input are rejected.
+    pub async fn home_on_next_index(&self, encoder_id: u8) -> Result<(), EncoderError> {
+        self.state.check_home(encoder_id)?;
+        self.send(&create_home_packet(encoder_id)).await
+    }
+
+    /// Overwrites the count of a single axis on the device.
+    pub async fn set_count(&self, encoder_id: u8, count: i32) -> Result<(), EncoderError> {
+        self.state.check_encoder_id(encoder_id)?;
+        self.send(&create_set_count_packet(encoder_id, count))
+            .await?;
+        self.state.rebase_position(usize::from(encoder_id));
+        Ok(())
+    }
+
+    /// Makes the device report an axis modulo `counts_per_rev`, as its position within the
+    /// revolution plus whole revolutions, or linearly again with 0.
+    ///
+    /// The setting is sent again whenever the device restarts.
+    pub async fn set_modulo(
+        &self,
+        encoder_id: u8,
+        counts_per_rev: u32,
+    ) -> Result<(), EncoderError> {
+        let command = ModuloCommand {
+            encoder_id,
+            counts_per_rev,
+        };
+        self.state.check_modulo_command(&command)?;
+        self.send(&Packet::SetModulo(command)).await?;
+        self.state.set_counts_per_rev(encoder_id, counts_per_rev);
+        Ok(())
+    }
+
+    /// Enables or disables a channel, inverts its counting direction or names it.
+    ///
+    /// A disabled channel is neither sampled nor transmitted and keeps its last count here.
+    /// Inverting a channel is the same as swapping its A and B wires. The configuration is
+    /// sent again whenever the device restarts.
+    pub async fn set_channel_config(&self, config: ChannelConfig) -> Result<(), EncoderError> {
+        self.state.check_encoder_id(config.encoder_id)?;
+        self.send(&Packet::SetChannel(config)).await?;
+        self.state.set_channel(config);
+        Ok(())
+    }
+
+    /// Returns the last configuration set or read for a channel, if any.
+    pub fn get_channel_config(&self, encoder_id: u8) -> Option<ChannelConfig> {
+        self.state
+            .channels()
+            .get(usize::from(encoder_id))
+            .copied()
+            .flatten()
+    }
+
+    /// Asks the device for the configuration of all its channels and waits for the replies.
+    pub async fn read_channel_configs(&self) -> Result<Vec<ChannelConfig>, EncoderError> {
+        // The counts tell how many channels there are.
+        if self.state.encoder_count().is_none() {
+            self.read_now().await?;
+        }
+        let encoder_count = self.state.encoder_count().ok_or(EncoderError::Timeout)?;
+        self.state.clear_channel_replies();
+        for encoder_id in 0..encoder_count as u8 {
+            self.send(&Packet::GetChannel { encoder_id }).await?;
+        }
+        // A polled board only sends its replies along with the counts.
+        self.send(&Packet::GetCounts).await?;
+
+        let deadline = tokio::time::Instant::now() + CHANNEL_REPLY_TIMEOUT;
+        loop {
+            if let Some(configs) = self.state.take_channel_replies(encoder_count) {
+                return Ok(configs);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(EncoderError::Timeout);
+            }
+            tokio::time::sleep(CHANNEL_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Returns the samples latched by the trigger input since the last call, oldest first.
+    pub fn take_latches(&self) -> Vec<LatchEvent> {
+        self.state.take_latches()
+    }
+
+    /// Registers a callback invoked on the reader task for every sample latched by the
+    /// trigger input.
+    pub fn on_latch_event<F>(&self, callback: F)
+    where
+        F: Fn(LatchEvent) + Send + Sync + 'static,
+    {
+        self.state.add_latch_callback(Box::new(callback));
+    }
+
+    /// Selects the edge of the trigger input that latches the counts.
+    pub async fn set_trigger_edge(&self, edge: TriggerEdge) -> Result<(), EncoderError> {
+        self.send(&Packet::SetTriggerEdge(edge)).await
+    }
+
+    /// Sets the least severe level of log messages the device forwards, or `None` to forward
+    /// none. Forwarded messages are emitted through the `log` crate with the
+    /// `encoder_firmware` target.
+    pub async fn set_log_level(&self, level: Option<LogLevel>) -> Result<(), EncoderError> {
+        self.send(&Packet::SetLogLevel(level)).await
+    }
+
+    /// Configures an axis's compare register on the device, replacing any previous one.
+    ///
+    /// The device drives the chosen output itself; changes are reported as compare events.
+    pub async fn set_compare(&self, command: CompareCommand) -> Result<(), EncoderError> {
+        self.state.check_compare_command(&command)?;
+        self.send(&Packet::SetCompare(command)).await
+    }
+
+    /// Disables an axis's compare register.
+    pub async fn clear_compare(&self, encoder_id: u8) -> Result<(), EncoderError> {
+        self.set_compare(CompareCommand::disable(encoder_id)).await
+    }
+
+    /// Returns the most recent compare output change reported for an axis, if any.
+    pub fn get_last_compare(&self, encoder_id: u8) -> Option<CompareEvent> {
+        self.state.last_compare(encoder_id)
+    }
+
+    /// Starts recording every step of every axis with a microsecond timestamp on the device.
+    ///
+    /// Once recording ends, the device streams the capture alongside the regular packets, one
+    /// chunk per 10 ms. Any capture not yet taken is discarded.
+    pub async fn start_capture(&self, command: CaptureCommand) -> Result<(), EncoderError> {
+        self.state.take_capture();
+        self.send(&Packet::StartCapture(command)).await
+    }
+
+    /// Takes the last complete edge capture, if one has arrived.
+    pub fn take_capture(&self) -> Option<EdgeCapture> {
+        self.state.take_capture()
+    }
+
+    /// Waits until a complete edge capture has arrived and takes it.
+    pub async fn wait_for_capture(&self, timeout: Duration) -> Option<EdgeCapture> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(capture) = self.state.take_capture() {
+                return Some(capture);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(CAPTURE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Registers a callback invoked on the reader task for every compare output change.
+    pub fn on_compare_event<F>(&self, callback: F)
+    where
+        F: Fn(CompareEvent) + Send + Sync + 'static,
+    {
+        self.state.add_compare_callback(Box::new(callback));
+    }
+
+    /// Returns the latest boot session heartbeat received from the device.
+    pub fn get_device_info(&self) -> Option<DeviceInfo> {
+        self.state.device_info()
+    }
+
+    /// Registers a callback invoked on the reader task when the device is found to have restarted.
+    pub fn on_device_restarted<F>(&self, callback: F)
+    where
+        F: Fn(DeviceRestart) + Send + Sync + 'static,
+    {
+        self.state.add_restart_callback(Box::new(callback));
+    }
+
+    /// Enables sending the last known counts back to the device after it restarts.
+    pub fn set_restore_on_restart(&self, enabled: bool) {
+        self.state
+            .restore_on_restart
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// Switches the link to another baud rate from [`SUPPORTED_BAUD_RATES`].
+    ///
+    /// The device acknowledges the proposal at the current rate and switches. The call returns
+    /// once sensor data arrives at the new rate. If none arrives within
+    /// [`BAUD_CONFIRM_TIMEOUT_MS`], the port goes back to [`DEFAULT_BAUD`], as does the device
+    /// when it hears nothing from the host.
+    pub async fn negotiate_baud(&self, baud: u32) -> Result<(), EncoderError> {
+        check_baud(baud)?;
+        self.state.take_baud_ack();
+        self.send(&Packet::SetBaud { baud }).await?;
+
+        let deadline = tokio::time::Instant::now() + BAUD_ACK_TIMEOUT;
+        let acked = loop {
+            if let Some(acked) = self.state.take_baud_ack() {
+                break acked;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(EncoderError::Timeout);
+            }
+            tokio::time::sleep(BAUD_ACK_POLL_INTERVAL).await;
+        };
+        if acked != baud {
+            return Err(EncoderError::UnsupportedBaud(baud));
+        }
+
+        let updated = self.state.sensor_notify.notified();
+        tokio::pin!(updated);
+        updated.as_mut().enable();
+        set_port_baud(&self.control, &self.state, baud)?;
+        if tokio::time::timeout(BAUD_CONFIRM_TIMEOUT, updated)
+            .await
+            .is_err()
+        {
+            set_port_baud(&self.control, &self.state, DEFAULT_BAUD)?;
+            return Err(EncoderError::Timeout);
         }
+        // Any frame confirms the new rate to the device.
+        self.send(&Packet::Ping { timestamp: 0 }).await
+    }
+
+    /// Reboots the device into the RP2040 USB bootloader for a firmware update.
+    ///
+    /// The serial stream stops once the device resets; use
+    /// [`bootloader::copy_uf2_when_ready`] to flash the new image.
+    pub async fn enter_bootloader(&self) -> Result<(), EncoderError> {
+        self.send(&Packet::EnterBootloader).await
+    }
+
+    /// Serializes and writes a single packet to the device.
+    async fn send(&self, packet: &Packet) -> Result<(), EncoderError> {
+        write_packet_async(&self.writer, packet).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use encoder_protocol::{SensorDataPacket, create_sensor_packet};
 
     #[test]
     fn test_parse_line() {
         let line = "$123:1,-2,3,-4,5,-6,7,-8*2E";
-        let (seq, counts) = parse_line(line).unwrap();
-        assert_eq!(seq, 123);
-        assert_eq!(counts, [1, -2, 3, -4, 5, -6, 7, -8]);
+        let Some(Packet::SensorData(data)) = parse_packet(line) else {
+            panic!("expected sensor data");
+        };
+        assert_eq!(data.seq, 123);
+        assert_eq!(data.encoders[..8], [1, -2, 3, -4, 5, -6, 7, -8]);
     }
 
     #[test]
     fn test_parse_corrupt_line() {
-        assert!(parse_line("bad_data").is_none());
-        assert!(parse_line("$123:0,1,2,abc,4,5,6,7*XX").is_none()); // Bad hex checksum
-        assert!(parse_line("$123:0,1,2,3,4,5,6,7*00").is_none()); // Wrong checksum
-        assert!(parse_line("123:0,1,2,3,4,5,6,7").is_none()); // Missing $ and *
+        assert!(parse_packet("bad_data").is_none());
+        assert!(parse_packet("$123:0,1,2,abc,4,5,6,7*XX").is_none()); // Bad hex checksum
+        assert!(parse_packet("$123:0,1,2,3,4,5,6,7*00").is_none()); // Wrong checksum
+        assert!(parse_packet("123:0,1,2,3,4,5,6,7").is_none()); // Missing $ and *
+    }
+
+    #[test]
+    fn test_non_utf8_lines_are_dropped() {
+        let state = SharedState::default();
+        let traffic = b"\xF8\x80$12\xFF:3*00\n$123:1,-2,3,-4,5,-6,7,-8*2E\r\n";
+        let mut reader = BufReader::new(&traffic[..]);
+        let mut line = Vec::new();
+        let mut lines = 0;
+        while reader.read_until(b'\n', &mut line).unwrap() > 0 {
+            assert!(state.handle_raw_line(&line).is_empty());
+            line.clear();
+            lines += 1;
+        }
+        assert_eq!(lines, 2);
+        assert_eq!(state.sequence(), 123);
+        assert_eq!(state.counts()[..8], [1, -2, 3, -4, 5, -6, 7, -8]);
+    }
+
+    #[test]
+    fn test_handle_line_updates_state() {
+        let state = SharedState::default();
+        state.handle_line("$123:1,-2,3,-4,5,-6,7,-8*2E");
+        assert_eq!(state.sequence(), 123);
+        assert_eq!(state.counts()[..8], [1, -2, 3, -4, 5, -6, 7, -8]);
+    }
+
+    #[test]
+    fn test_positions_unwrap_counter_wraps() {
+        let state = SharedState::default();
+        let mut counts = [0; 8];
+        counts[0] = i32::MAX - 1;
+        counts[1] = 7;
+        state.handle_line(&serialize_packet(&Packet::SensorData(
+            SensorDataPacket::new(1, &counts),
+        )));
+        counts[0] = i32::MIN + 1;
+        state.handle_line(&serialize_packet(&Packet::SensorData(
+            SensorDataPacket::new(2, &counts),
+        )));
+        assert_eq!(state.counts()[0], i32::MIN + 1);
+        assert_eq!(state.positions()[0], i64::from(i32::MAX) + 2);
+        assert_eq!(state.positions()[1], 7);
+
+        // Zeroing at the index starts the position over.
+        let event = IndexEvent {
+            encoder_id: 0,
+            count: i32::MIN + 1,
+            timestamp: 0,
+            homed: true,
+        };
+        state.handle_line(&serialize_packet(&Packet::Index(event)));
+        counts[0] = 0;
+        state.handle_line(&serialize_packet(&Packet::SensorData(
+            SensorDataPacket::new(3, &counts),
+        )));
+        assert_eq!(state.positions()[0], 0);
+
+        // Setting a count more than 2^31 away is not taken for a wrap.
+        state.rebase_position(1);
+        counts[1] = i32::MIN;
+        state.handle_line(&serialize_packet(&Packet::SensorData(
+            SensorDataPacket::new(4, &counts),
+        )));
+        assert_eq!(state.positions()[1], i64::from(i32::MIN));
+    }
+
+    #[test]
+    fn test_wait_for_sensor_update() {
+        let state = Arc::new(SharedState::default());
+        let seen = state.sensor_updates();
+        assert_eq!(
+            state.wait_for_sensor_update(seen, Duration::from_millis(1)),
+            None
+        );
+
+        let state_clone = Arc::clone(&state);
+        let feeder = thread::spawn(move || {
+            state_clone.handle_line("$123:1,-2,3,-4,5,-6,7,-8*2E");
+        });
+        assert_eq!(
+            state
+                .wait_for_sensor_update(seen, Duration::from_secs(5))
+                .map(|counts| counts[..8].to_vec()),
+            Some(vec![1, -2, 3, -4, 5, -6, 7, -8])
+        );
+        feeder.join().unwrap();
+    }
+
+    #[test]
+    fn test_latches_are_buffered_in_order() {
+        let state = SharedState::default();
+        for seq in 1..=3 {
+            let event = LatchEvent {
+                seq,
+                timestamp_us: u32::from(seq) * 100,
+                counts: [i32::from(seq); MAX_ENCODERS],
+                encoder_count: 8,
+            };
+            state.handle_line(&serialize_packet(&Packet::Latch(event)));
+        }
+
+        let latches = state.take_latches();
+        assert_eq!(
+            latches.iter().map(|l| l.seq).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(latches[2].counts[..8], [3; 8]);
+        assert!(state.take_latches().is_empty());
+    }
+
+    /// Records the messages logged by the tests.
+    struct CaptureLogger(Mutex<Vec<(log::Level, String, String)>>);
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            self.0.lock().unwrap().push((
+                record.level(),
+                record.target().to_string(),
+                record.args().to_string(),
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger(Mutex::new(Vec::new()));
+
+    #[test]
+    fn test_log_messages_are_routed_to_log() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let state = SharedState::default();
+        let mut message = LogMessage::new(LogLevel::Error);
+        fmt::Write::write_str(&mut message, "UART write failed").unwrap();
+        state.handle_line(&serialize_packet(&Packet::Log(message)));
+
+        let logged = LOGGER.0.lock().unwrap();
+        assert!(logged.contains(&(
+            log::Level::Error,
+            FIRMWARE_LOG_TARGET.to_string(),
+            "UART write failed".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_baud_ack_and_fallback() {
+        let state = SharedState::default();
+        assert!(check_baud(921_600).is_ok());
+        assert!(matches!(
+            check_baud(9600),
+            Err(EncoderError::UnsupportedBaud(9600))
+        ));
+
+        state.handle_line(&serialize_packet(&Packet::BaudAck { baud: 921_600 }));
+        assert_eq!(state.take_baud_ack(), Some(921_600));
+        assert_eq!(state.take_baud_ack(), None);
+
+        state.set_baud(921_600);
+        assert!(!state.baud_fallback_due());
+        *state.last_frame_at.lock().unwrap() =
+            std::time::Instant::now().checked_sub(BAUD_CONFIRM_TIMEOUT);
+        assert!(state.baud_fallback_due());
+        state.handle_line("$123:1,-2,3,-4,5,-6,7,-8*2E");
+        assert!(!state.baud_fallback_due());
+
+        state.set_baud(DEFAULT_BAUD);
+        *state.last_frame_at.lock().unwrap() = None;
+        assert!(!state.baud_fallback_due());
+    }
+
+    #[test]
+    fn test_negotiated_rate_is_kept_alive() {
+        let state = SharedState::default();
+        assert!(!state.keepalive_due());
+
+        state.set_baud(921_600);
+        assert!(!state.keepalive_due());
+        *state.last_keepalive_at.lock().unwrap() =
+            std::time::Instant::now().checked_sub(BAUD_KEEPALIVE_INTERVAL);
+        assert!(state.keepalive_due());
+        assert!(!state.keepalive_due());
+
+        state.set_baud(DEFAULT_BAUD);
+        *state.last_keepalive_at.lock().unwrap() = None;
+        assert!(!state.keepalive_due());
+    }
+
+    #[test]
+    fn test_compare_command_validation() {
+        let state = SharedState::default();
+        assert!(
+            state
+                .check_compare_command(&CompareCommand::at_or_above(7, 3, 100))
+                .is_ok()
+        );
+        assert!(
+            state
+                .check_compare_command(&CompareCommand::disable(0))
+                .is_ok()
+        );
+        assert!(matches!(
+            state.check_compare_command(&CompareCommand::at_or_above(0, 4, 100)),
+            Err(EncoderError::InvalidOutput(4))
+        ));
+        assert!(matches!(
+            state.check_compare_command(&CompareCommand::window(12, 0, 0, 1)),
+            Err(EncoderError::InvalidEncoder(12))
+        ));
+
+        // Once the device reported its encoders, IDs past them are rejected too.
+        state.handle_line(&info_line(1));
+        assert!(matches!(
+            state.check_compare_command(&CompareCommand::window(8, 0, 0, 1)),
+            Err(EncoderError::InvalidEncoder(8))
+        ));
+    }
+
+    #[test]
+    fn test_sequence_gaps_count_skipped_frames() {
+        let state = SharedState::default();
+        state.handle_line(&serialize_packet(&create_sensor_packet(40, &[0; 8])));
+        state.handle_line(&serialize_packet(&create_sensor_packet(41, &[0; 8])));
+        assert_eq!(state.skipped_frames.load(Ordering::Relaxed), 0);
+        state.handle_line(&serialize_packet(&create_sensor_packet(45, &[0; 8])));
+        assert_eq!(state.skipped_frames.load(Ordering::Relaxed), 3);
+
+        // A restarted device starts over at zero.
+        state.handle_line(&serialize_packet(&create_sensor_packet(0, &[0; 8])));
+        assert_eq!(state.skipped_frames.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_button_callbacks() {
+        let state = SharedState::default();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = Arc::clone(&received);
+        state.add_button_callback(Box::new(move |event| {
+            received_clone.lock().unwrap().push(event);
+        }));
+
+        let event = ButtonEvent {
+            encoder_id: 1,
+            pressed: true,
+            timestamp: 5000,
+            gesture: ButtonGesture::LongPress,
+        };
+        state.handle_line(&serialize_packet(&Packet::Button(event)));
+
+        assert_eq!(*received.lock().unwrap(), vec![event]);
+    }
+
+    #[test]
+    fn test_index_events_track_homing() {
+        let state = SharedState::default();
+        let latch = IndexEvent {
+            encoder_id: 2,
+            count: 1234,
+            timestamp: 10,
+            homed: false,
+        };
+        state.handle_line(&serialize_packet(&Packet::Index(latch)));
+        assert_eq!(state.last_index(2), Some(latch));
+        assert!(!state.is_homed(2));
+
+        let home = IndexEvent {
+            count: 5234,
+            homed: true,
+            ..latch
+        };
+        state.handle_line(&serialize_packet(&Packet::Index(home)));
+        assert_eq!(state.last_index(2), Some(home));
+        assert!(state.is_homed(2));
+        assert_eq!(state.last_index(9), None);
+
+        // Only axes with an index input can be homed.
+        assert!(state.check_home(5).is_ok());
+        state.handle_line(&info_line(1));
+        assert!(state.check_home(1).is_ok());
+        assert!(matches!(
+            state.check_home(5),
+            Err(EncoderError::NoIndexInput(5))
+        ));
+    }
+
+    #[test]
+    fn test_diagnostics_are_stored_per_channel() {
+        let state = SharedState::default();
+        assert_eq!(state.diagnostics(5), None);
+
+        let diag = EncoderDiagnostics {
+            encoder_id: 5,
+            illegal_transitions: 3,
+            glitches: 40,
+        };
+        state.handle_line(&serialize_packet(&Packet::Diagnostics(diag)));
+        assert_eq!(state.diagnostics(5), Some(diag));
+        assert_eq!(state.diagnostics(4), None);
+    }
+
+    #[test]
+    fn test_overspeed_detection() {
+        let state = SharedState::default();
+        state.handle_line(&serialize_packet(&Packet::Sampling(SamplingStats {
+            loops_per_second: 100_000,
+            max_loop_us: 50,
+        })));
+        assert_eq!(state.max_safe_edge_rate(), Some(10_000));
+
+        // 10 counts in 10 ms is 4000 edges per second, well within the limit.
+        state.handle_line(&serialize_packet(&Packet::SensorData(
+            SensorDataPacket::new(1, &[10, 0, 0, 0, 0, 0, 0, 0]),
+        )));
+        assert!(!state.is_overspeed(0));
+
+        // 100 counts in 10 ms is 40000 edges per second.
+        state.handle_line(&serialize_packet(&Packet::SensorData(
+            SensorDataPacket::new(2, &[110, 0, 0, 0, 0, 0, 0, 0]),
+        )));
+        assert!(state.is_overspeed(0));
+        assert!(!state.is_overspeed(1));
+    }
+
+    fn info_line(session_id: u32) -> String {
+        serialize_packet(&Packet::DeviceInfo(DeviceInfo {
+            session_id,
+            reset_reason: ResetReason::PowerOn,
+            protocol_version: 2,
+            counts_restored: false,
+            serial_number: 0x1234,
+            encoder_count: 8,
+            index_inputs: 0b11,
+        }))
+        .to_string()
+    }
+
+    fn sensor_line(seq: u32, first: i32) -> String {
+        serialize_packet(&Packet::SensorData(SensorDataPacket::new(
+            seq,
+            &[first, 0, 0, 0, 0, 0, 0, 0],
+        )))
+        .to_string()
+    }
+
+    #[test]
+    fn test_restart_detected_after_new_session_data() {
+        let state = SharedState::default();
+        state.restore_on_restart.store(true, Ordering::SeqCst);
+        let restarts = Arc::new(Mutex::new(Vec::new()));
+        let restarts_clone = Arc::clone(&restarts);
+        state.add_restart_callback(Box::new(move |r| restarts_clone.lock().unwrap().push(r)));
+
+        assert!(state.handle_line(&info_line(1)).is_empty());
+        state.handle_line(&sensor_line(500, 1000));
+
+        // The device reboots and its first heartbeat is missed.
+        state.handle_line(&sensor_line(3, 5));
+        assert_eq!(state.counts()[0], 5);
+
+        let replies = state.handle_line(&info_line(2));
+        assert_eq!(replies, vec![create_set_count_packet(0, 1005)]);
+
+        let restarts = restarts.lock().unwrap();
+        assert_eq!(restarts.len(), 1);
+        assert_eq!(restarts[0].previous_session_id, 1);
+        assert_eq!(restarts[0].last_counts[0], 1000);
+    }
+
+    #[test]
+    fn test_restart_detected_from_first_heartbeat() {
+        let state = SharedState::default();
+        state.handle_line(&info_line(1));
+        state.handle_line(&sensor_line(500, -42));
+
+        // Restoring is off by default.
+        assert!(state.handle_line(&info_line(2)).is_empty());
+        assert_eq!(state.counts()[0], 0);
+        assert_eq!(state.sequence(), 0);
+
+        // The first packet of the new session is not mistaken for another reset.
+        state.handle_line(&sensor_line(1, 0));
+        assert!(state.handle_line(&info_line(2)).is_empty());
+        assert_eq!(state.device_info().map(|i| i.session_id), Some(2));
+    }
+
+    #[test]
+    fn test_modulo_axis_angles_and_restart() {
+        let state = SharedState::default();
+        state.restore_on_restart.store(true, Ordering::SeqCst);
+        state.handle_line(&info_line(1));
+        assert_eq!(state.revolution_fraction(2), None);
+        state.set_counts_per_rev(2, 400);
+
+        let mut counts_per_rev = [0; 8];
+        counts_per_rev[2] = 400;
+        let mut counts = [0; 8];
+        counts[2] = 399;
+        let data = SensorDataPacket::with_modulo(1, &counts, &counts_per_rev);
+        state.handle_line(&serialize_packet(&Packet::SensorData(data)));
+        // Crossing into the next revolution is not a jump back.
+        counts[2] = 2 * 400 + 100;
+        let data = SensorDataPacket::with_modulo(2, &counts, &counts_per_rev);
+        state.handle_line(&serialize_packet(&Packet::SensorData(data)));
+        assert_eq!(state.counts()[2], 100);
+        assert_eq!(state.revolutions()[2], 2);
+        assert_eq!(state.revolution_fraction(2), Some(0.25));
+        assert_eq!(state.turns(2), Some(2.25));
+        assert_eq!(state.positions()[2], 900);
+
+        // After a restart the modulo is configured again and the total count restored.
+        let replies = state.handle_line(&info_line(2));
+        assert_eq!(
+            replies,
+            vec![
+                Packet::SetModulo(ModuloCommand {
+                    encoder_id: 2,
+                    counts_per_rev: 400,
+                }),
+                create_set_count_packet(2, 900),
+            ]
+        );
+        assert_eq!(state.revolutions(), [0; MAX_ENCODERS]);
+    }
+
+    #[test]
+    fn test_channel_configs_and_disabled_axes() {
+        let state = SharedState::default();
+        state.handle_line(&info_line(1));
+        let mut counts = [0; 8];
+        counts[4] = 250;
+        let data = SensorDataPacket::new(1, &counts);
+        state.handle_line(&serialize_packet(&Packet::SensorData(data)));
+
+        let mut config = ChannelConfig::new(4);
+        config.enabled = false;
+        config.set_name("Feed");
+        state.set_channel(config);
+
+        // A disabled axis keeps its last count rather than dropping to zero.
+        let mut data = SensorDataPacket::new(2, &[7; 8]);
+        data.disable(4);
+        state.handle_line(&serialize_packet(&Packet::SensorData(data)));
+        assert_eq!(state.counts()[..8], [7, 7, 7, 7, 250, 7, 7, 7]);
+        assert_eq!(state.positions()[4], 250);
+
+        // Replies are only taken once every channel has been reported.
+        for encoder_id in 0..7 {
+            let reply = Packet::Channel(ChannelConfig::new(encoder_id));
+            state.handle_line(&serialize_packet(&reply));
+        }
+        assert_eq!(state.take_channel_replies(8), None);
+        state.handle_line(&serialize_packet(&Packet::Channel(ChannelConfig::new(7))));
+        let configs = state.take_channel_replies(8).unwrap();
+        assert_eq!(configs[4], ChannelConfig::new(4));
+        state.handle_line(&serialize_packet(&Packet::Channel(config)));
+        assert_eq!(state.take_channel_replies(8), None);
+        assert_eq!(state.channels()[4], Some(config));
+
+        // Only channels that differ from the defaults are configured again after a restart.
+        let replies = state.handle_line(&info_line(2));
+        assert_eq!(replies, vec![Packet::SetChannel(config)]);
+    }
+
+    #[test]
+    fn test_encoder_count_follows_the_device() {
+        let state = SharedState::default();
+        assert_eq!(state.encoder_count(), None);
+        assert!(state.per_encoder(state.counts()).is_empty());
+        assert!(state.check_encoder_id(11).is_ok());
+        assert!(matches!(
+            state.check_encoder_id(12),
+            Err(EncoderError::InvalidEncoder(12))
+        ));
+
+        let counts: Vec<i32> = (1..=12).collect();
+        let data = SensorDataPacket::new(1, &counts);
+        state.handle_line(&serialize_packet(&Packet::SensorData(data)));
+        assert_eq!(state.encoder_count(), Some(12));
+        assert_eq!(state.counts()[..12], counts[..]);
+        assert_eq!(state.per_encoder(state.counts()), counts);
+        assert_eq!(state.per_encoder(state.positions()).len(), 12);
+        assert!(state.check_encoder_id(11).is_ok());
+        assert!(matches!(
+            state.check_encoder_id(12),
+            Err(EncoderError::InvalidEncoder(12))
+        ));
+
+        for encoder_id in 0..12 {
+            let reply = Packet::Channel(ChannelConfig::new(encoder_id));
+            state.handle_line(&serialize_packet(&reply));
+        }
+        assert_eq!(state.take_channel_replies(12).map(|c| c.len()), Some(12));
+    }
+
+    #[test]
+    fn test_no_restore_when_firmware_kept_counts() {
+        let state = SharedState::default();
+        state.restore_on_restart.store(true, Ordering::SeqCst);
+        state.handle_line(&info_line(1));
+        state.handle_line(&sensor_line(500, 1000));
+
+        let info = DeviceInfo {
+            session_id: 2,
+            reset_reason: ResetReason::WatchdogTimeout,
+            protocol_version: 2,
+            counts_restored: true,
+            serial_number: 0x1234,
+            encoder_count: 8,
+            index_inputs: 0b11,
+        };
+        assert!(
+            state
+                .handle_line(&serialize_packet(&Packet::DeviceInfo(info)))
+                .is_empty()
+        );
     }
 }
@@ -0,0 +1,127 @@
+//! Reconstruction of 64-bit positions from the wrapping 32-bit counts on the wire.
+//!
+//! The firmware keeps 32-bit counters that wrap from `i32::MAX` to `i32::MIN` and back. A
+//! high-resolution encoder on a continuously turning spindle gets there within hours, so the
+//! client counts the wraps and adds them back on.
+
+use encoder_protocol::MAX_ENCODERS;
+
+/// Tracks the wraps of every counter.
+///
+/// A wrap is recognised when two consecutive counts are further than `2^31` apart, which
+/// real motion between two packets never gets close to. Commands that move a count, such as
+/// setting it, must be followed by [`rebase`](Self::rebase) so the jump is not taken for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CountUnwrapper {
+    last: [i32; MAX_ENCODERS],
+    /// Net number of wraps per counter, positive for wraps past `i32::MAX`.
+    wraps: [i64; MAX_ENCODERS],
+    /// Counters whose next count is taken as it is, because a command moved them.
+    resync: [bool; MAX_ENCODERS],
+}
+
+impl CountUnwrapper {
+    /// Takes the next received counts and returns the unwrapped positions.
+    pub fn update(&mut self, counts: &[i32; MAX_ENCODERS]) -> [i64; MAX_ENCODERS] {
+        for (i, &count) in counts.iter().enumerate() {
+            let delta = i64::from(count) - i64::from(self.last[i]);
+            if core::mem::take(&mut self.resync[i]) {
+                // The jump to the new count is no wrap, whatever its size.
+            } else if delta < i64::from(i32::MIN) {
+                self.wraps[i] += 1;
+            } else if delta > i64::from(i32::MAX) {
+                self.wraps[i] -= 1;
+            }
+            self.last[i] = count;
+        }
+        self.positions()
+    }
+
+    /// The unwrapped positions of the last counts received.
+    pub fn positions(&self) -> [i64; MAX_ENCODERS] {
+        core::array::from_fn(|i| (self.wraps[i] << 32) + i64::from(self.last[i]))
+    }
+
+    /// Forgets the wraps of one counter and takes its next count as it is, so its position
+    /// equals its count again. Used when the count was overwritten or zeroed.
+    pub fn rebase(&mut self, encoder_id: usize) {
+        if encoder_id < self.wraps.len() {
+            self.wraps[encoder_id] = 0;
+            self.resync[encoder_id] = true;
+        }
+    }
+
+    /// Takes counts that did not come from motion, such as after a device restart, without
+    /// looking for wraps.
+    pub fn resync(&mut self, counts: &[i32; MAX_ENCODERS]) {
+        self.last = *counts;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_continue_past_wraps() {
+        let mut unwrapper = CountUnwrapper::default();
+        let mut counts = [0i32; MAX_ENCODERS];
+        counts[0] = i32::MAX - 10;
+        counts[1] = i32::MIN + 10;
+        unwrapper.update(&counts);
+
+        counts[0] = (i32::MAX - 10).wrapping_add(30);
+        counts[1] = (i32::MIN + 10).wrapping_sub(30);
+        let positions = unwrapper.update(&counts);
+        assert_eq!(positions[0], i64::from(i32::MAX) + 20);
+        assert_eq!(positions[1], i64::from(i32::MIN) - 20);
+
+        // Going back across the boundary undoes the wrap.
+        counts[0] = i32::MAX;
+        assert_eq!(unwrapper.update(&counts)[0], i64::from(i32::MAX));
+
+        // Many full turns of the 32-bit counter add up.
+        for _ in 0..3 {
+            for step in [1 << 30, 1 << 30, 1 << 30, 1 << 30] {
+                counts[2] = counts[2].wrapping_add(step);
+                unwrapper.update(&counts);
+            }
+        }
+        assert_eq!(unwrapper.positions()[2], 3 << 32);
+    }
+
+    #[test]
+    fn test_rebase_after_overwritten_count() {
+        let mut unwrapper = CountUnwrapper::default();
+        let mut counts = [0i32; MAX_ENCODERS];
+        for _ in 0..4 {
+            counts[5] = counts[5].wrapping_add(1 << 30);
+            unwrapper.update(&counts);
+        }
+        assert_eq!(unwrapper.positions()[5], 1 << 32);
+
+        unwrapper.rebase(5);
+        counts[5] = 100;
+        assert_eq!(unwrapper.update(&counts)[5], 100);
+
+        unwrapper.resync(&[i32::MIN; MAX_ENCODERS]);
+        assert_eq!(unwrapper.positions()[5], i64::from(i32::MIN));
+    }
+
+    #[test]
+    fn test_rebase_takes_jumps_beyond_half_the_range() {
+        let mut unwrapper = CountUnwrapper::default();
+        let mut counts = [0i32; MAX_ENCODERS];
+        counts[3] = -1_000_000_000;
+        unwrapper.update(&counts);
+
+        // Setting the count moves it by 2.5e9, more than 2^31.
+        unwrapper.rebase(3);
+        counts[3] = 1_500_000_000;
+        assert_eq!(unwrapper.update(&counts)[3], 1_500_000_000);
+
+        // Only the first count after the rebase is taken as it is.
+        counts[3] = counts[3].wrapping_add(1 << 30);
+        assert_eq!(unwrapper.update(&counts)[3], 1_500_000_000 + (1 << 30));
+    }
+}
@@ -0,0 +1,127 @@
+// encoder-core/src/baud.rs
+
+use encoder_protocol::{
+    BAUD_CONFIRM_TIMEOUT_MS, DEFAULT_BAUD, HOST_SILENCE_TIMEOUT_MS, SUPPORTED_BAUD_RATES,
+};
+
+/// Why a negotiated baud rate was given up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaudFallback {
+    /// The host sent no valid frame within the confirmation timeout after the switch.
+    Unconfirmed,
+    /// The host stopped sending valid frames at a rate it had confirmed.
+    HostSilent,
+}
+
+/// Baud rate of the host link and the deadlines that return it to `DEFAULT_BAUD`.
+///
+/// Times are milliseconds from any fixed origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaudLink {
+    baud: u32,
+    confirmed: bool,
+    /// Time of the switch, or of the last valid frame once the rate is confirmed.
+    last_heard_ms: u64,
+}
+
+impl Default for BaudLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaudLink {
+    pub const fn new() -> Self {
+        Self {
+            baud: DEFAULT_BAUD,
+            confirmed: true,
+            last_heard_ms: 0,
+        }
+    }
+
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    /// The rate to acknowledge for a host proposal: the proposal when it is supported, the
+    /// current rate otherwise.
+    pub fn resolve(&self, requested: u32) -> u32 {
+        if SUPPORTED_BAUD_RATES.contains(&requested) {
+            requested
+        } else {
+            self.baud
+        }
+    }
+
+    /// Records that the UART was switched to `baud`. A negotiated rate then waits for the
+    /// host's first frame.
+    pub fn switch(&mut self, baud: u32, now_ms: u64) {
+        self.baud = baud;
+        self.confirmed = baud == DEFAULT_BAUD;
+        self.last_heard_ms = now_ms;
+    }
+
+    /// Records a valid frame from the host and returns whether it confirmed the rate.
+    pub fn frame_seen(&mut self, now_ms: u64) -> bool {
+        let confirms = !self.confirmed;
+        self.confirmed = true;
+        self.last_heard_ms = now_ms;
+        confirms
+    }
+
+    /// Returns to `DEFAULT_BAUD` when the host missed its deadline, telling the caller to
+    /// switch the UART back. The default rate never falls back.
+    pub fn check(&mut self, now_ms: u64) -> Option<BaudFallback> {
+        if self.baud == DEFAULT_BAUD {
+            return None;
+        }
+        let (timeout_ms, fallback) = if self.confirmed {
+            (HOST_SILENCE_TIMEOUT_MS, BaudFallback::HostSilent)
+        } else {
+            (BAUD_CONFIRM_TIMEOUT_MS, BaudFallback::Unconfirmed)
+        };
+        if now_ms.saturating_sub(self.last_heard_ms) < u64::from(timeout_ms) {
+            return None;
+        }
+        self.switch(DEFAULT_BAUD, now_ms);
+        Some(fallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unconfirmed_rate_falls_back() {
+        let mut link = BaudLink::new();
+        assert_eq!(link.resolve(9600), DEFAULT_BAUD);
+        assert_eq!(link.resolve(921_600), 921_600);
+
+        link.switch(921_600, 1000);
+        assert_eq!(link.check(1999), None);
+        assert_eq!(link.check(2000), Some(BaudFallback::Unconfirmed));
+        assert_eq!(link.baud(), DEFAULT_BAUD);
+        assert_eq!(link.check(100_000), None);
+    }
+
+    #[test]
+    fn test_silent_host_returns_board_to_default_rate() {
+        let mut link = BaudLink::new();
+        link.switch(921_600, 0);
+        assert!(link.frame_seen(50));
+        assert!(!link.frame_seen(60));
+
+        // Well past the confirmation timeout, but the host keeps pinging.
+        for now_ms in (1000..20_000).step_by(1000) {
+            assert!(!link.frame_seen(now_ms));
+            assert_eq!(link.check(now_ms + 500), None);
+        }
+        assert_eq!(link.baud(), 921_600);
+
+        // The host goes silent after its last ping at 19 s.
+        assert_eq!(link.check(23_999), None);
+        assert_eq!(link.check(24_000), Some(BaudFallback::HostSilent));
+        assert_eq!(link.baud(), 115_200);
+    }
+}
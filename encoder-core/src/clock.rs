@@ -0,0 +1,13 @@
+// encoder-core/src/clock.rs
+
+/// Source of the current time for the sampling loop.
+pub trait Clock {
+    /// Microseconds since boot.
+    fn now_us(&self) -> u64;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now_us(&self) -> u64 {
+        (**self).now_us()
+    }
+}
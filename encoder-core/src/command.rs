@@ -0,0 +1,302 @@
+// encoder-core/src/command.rs
+
+use encoder_protocol::{COMPARE_OUTPUTS, CaptureCommand, CompareCommand, Packet};
+
+use crate::state::DeviceState;
+
+/// What is left for the firmware to do after a host command was applied to the
+/// [`DeviceState`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Nothing, the command was fully applied.
+    None,
+    /// Queue a reply for the host.
+    Reply(Packet),
+    /// Reboot into the USB bootloader.
+    EnterBootloader,
+    /// Hand an edge capture request to the sampling loop.
+    StartCapture(CaptureCommand),
+    /// Hand a validated compare register configuration to the sampling loop.
+    SetCompare(CompareCommand),
+    /// Acknowledge and switch to a baud rate proposed by the host.
+    SetBaud(u32),
+    /// The packet is not a command, or refers to a nonexistent encoder, output or index
+    /// input.
+    Ignored,
+}
+
+/// Applies a single command received from the host.
+pub fn apply_command(state: &DeviceState, packet: Packet) -> Action {
+    match packet {
+        Packet::Reset(cmd) => {
+            for i in 0..state.encoder_count() {
+                if cmd.resets_all() || usize::from(cmd.encoder_id) == i {
+                    state.set_count(i, 0);
+                }
+            }
+            Action::None
+        }
+        Packet::SetCount(cmd) => {
+            state.set_count(usize::from(cmd.encoder_id), cmd.count);
+            Action::None
+        }
+        Packet::Home(cmd) => {
+            let encoder = usize::from(cmd.encoder_id);
+            if state.has_index(encoder) {
+                state.arm_home(encoder);
+                Action::None
+            } else {
+                Action::Ignored
+            }
+        }
+        Packet::SetTriggerEdge(edge) => {
+            state.set_trigger_edge(edge);
+            Action::None
+        }
+        Packet::SetLogLevel(level) => {
+            state.set_log_level(level);
+            Action::None
+        }
+        Packet::SetChannel(config) => {
+            if state.has_encoder(usize::from(config.encoder_id)) {
+                state.set_channel_config(&config);
+                Action::None
+            } else {
+                Action::Ignored
+            }
+        }
+        Packet::GetChannel { encoder_id } if state.has_encoder(usize::from(encoder_id)) => {
+            Action::Reply(Packet::Channel(
+                state.channel_config(usize::from(encoder_id)),
+            ))
+        }
+        Packet::SetModulo(cmd) => {
+            let encoder = usize::from(cmd.encoder_id);
+            if state.has_encoder(encoder) && i32::try_from(cmd.counts_per_rev).is_ok() {
+                state.set_counts_per_rev(encoder, cmd.counts_per_rev);
+                Action::None
+            } else {
+                Action::Ignored
+            }
+        }
+        Packet::EnterBootloader => Action::EnterBootloader,
+        Packet::Ping { timestamp } => Action::Reply(Packet::Pong { timestamp }),
+        Packet::SetBaud { baud } => Action::SetBaud(baud),
+        Packet::StartCapture(cmd) => Action::StartCapture(cmd),
+        Packet::SetCompare(cmd) => {
+            let valid_output = cmd.is_disabled() || usize::from(cmd.output) < COMPARE_OUTPUTS;
+            if state.has_encoder(usize::from(cmd.encoder_id)) && valid_output {
+                Action::SetCompare(cmd)
+            } else {
+                Action::Ignored
+            }
+        }
+        // While streaming, the next periodic packet answers the request.
+        Packet::GetCounts | Packet::Poll => Action::None,
+        _ => Action::Ignored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder_protocol::{
+        ChannelConfig, DEFAULT_LOG_LEVEL, HomeCommand, LogLevel, ModuloCommand, ResetCommand,
+        SetCountCommand, TriggerEdge, parse_packet, serialize_packet,
+    };
+
+    #[test]
+    fn test_counter_commands_update_state() {
+        let state = DeviceState::new();
+        for i in 0..state.encoder_count() {
+            state.set_count(i, 10);
+        }
+
+        let set = Packet::SetCount(SetCountCommand {
+            encoder_id: 2,
+            count: -5,
+        });
+        assert_eq!(apply_command(&state, set), Action::None);
+        let reset = Packet::Reset(ResetCommand { encoder_id: 4 });
+        assert_eq!(apply_command(&state, reset), Action::None);
+        assert_eq!(state.counts()[..8], [10, 10, -5, 10, 0, 10, 10, 10]);
+
+        // Commands arrive as text, so go through the wire format once.
+        let line = serialize_packet(&Packet::SetTriggerEdge(TriggerEdge::Both));
+        let packet = parse_packet(line.trim_end()).unwrap();
+        assert_eq!(apply_command(&state, packet), Action::None);
+        assert_eq!(state.trigger_edge(), TriggerEdge::Both);
+    }
+
+    #[test]
+    fn test_home_needs_an_index_input() {
+        let state = DeviceState::new();
+        let home = |encoder_id| Packet::Home(HomeCommand { encoder_id });
+        assert_eq!(apply_command(&state, home(1)), Action::Ignored);
+
+        state.set_index_inputs(0b11);
+        assert_eq!(apply_command(&state, home(1)), Action::None);
+        assert_eq!(apply_command(&state, home(2)), Action::Ignored);
+        assert_eq!(apply_command(&state, home(15)), Action::Ignored);
+    }
+
+    #[test]
+    fn test_modulo_splits_reported_counts() {
+        let state = DeviceState::new();
+        state.set_count(1, 1000);
+        state.set_count(2, -1);
+        for encoder_id in [1, 2] {
+            let modulo = Packet::SetModulo(ModuloCommand {
+                encoder_id,
+                counts_per_rev: 360,
+            });
+            assert_eq!(apply_command(&state, modulo), Action::None);
+        }
+
+        // The counter keeps the total, only the report is split.
+        assert_eq!(state.count(1), 1000);
+        let data = state.sensor_data(5, state.counts());
+        assert_eq!(data.encoders[1..3], [280, 359]);
+        assert_eq!(data.revolutions.unwrap()[1..3], [2, -1]);
+
+        for counts_per_rev in [0, 360] {
+            let bad_encoder = Packet::SetModulo(ModuloCommand {
+                encoder_id: 8,
+                counts_per_rev,
+            });
+            assert_eq!(apply_command(&state, bad_encoder), Action::Ignored);
+        }
+        let too_large = Packet::SetModulo(ModuloCommand {
+            encoder_id: 1,
+            counts_per_rev: u32::MAX,
+        });
+        assert_eq!(apply_command(&state, too_large), Action::Ignored);
+
+        for encoder_id in [1, 2] {
+            apply_command(
+                &state,
+                Packet::SetModulo(ModuloCommand {
+                    encoder_id,
+                    counts_per_rev: 0,
+                }),
+            );
+        }
+        assert_eq!(state.sensor_data(6, state.counts()).revolutions, None);
+    }
+
+    #[test]
+    fn test_channel_configs() {
+        let state = DeviceState::new();
+        state.set_count(3, 42);
+        let mut config = ChannelConfig::new(3);
+        config.enabled = false;
+        config.inverted = true;
+        config.set_name("Z axis");
+        assert_eq!(
+            apply_command(&state, Packet::SetChannel(config)),
+            Action::None
+        );
+
+        let get = Packet::GetChannel { encoder_id: 3 };
+        assert_eq!(
+            apply_command(&state, get),
+            Action::Reply(Packet::Channel(config))
+        );
+        assert_eq!(state.channel_config(0), ChannelConfig::new(0));
+
+        // A disabled channel keeps its count but is left out of reports.
+        assert_eq!(state.count(3), 42);
+        let data = state.sensor_data(1, state.counts());
+        assert!(!data.enabled[3]);
+        assert_eq!(data.encoders[3], 0);
+
+        let bad = Packet::GetChannel { encoder_id: 8 };
+        assert_eq!(apply_command(&state, bad), Action::Ignored);
+        let bad = Packet::SetChannel(ChannelConfig::new(8));
+        assert_eq!(apply_command(&state, bad), Action::Ignored);
+    }
+
+    #[test]
+    fn test_commands_are_bounded_by_encoder_count() {
+        let state = DeviceState::with_encoders(12);
+        let modulo = |encoder_id| {
+            Packet::SetModulo(ModuloCommand {
+                encoder_id,
+                counts_per_rev: 100,
+            })
+        };
+        assert_eq!(apply_command(&state, modulo(11)), Action::None);
+        assert_eq!(apply_command(&state, modulo(12)), Action::Ignored);
+        let get = |encoder_id| Packet::GetChannel { encoder_id };
+        assert!(matches!(apply_command(&state, get(11)), Action::Reply(_)));
+        assert_eq!(apply_command(&state, get(12)), Action::Ignored);
+
+        // Only the board's channels are reported.
+        state.set_count(11, 250);
+        let data = state.sensor_data(3, state.counts());
+        assert_eq!(data.counts().len(), 12);
+        assert_eq!(data.encoders[11], 50);
+        assert_eq!(data.revolutions.unwrap()[11], 2);
+        let line = serialize_packet(&Packet::SensorData(data));
+        assert_eq!(
+            parse_packet(line.trim_end()),
+            Some(Packet::SensorData(data))
+        );
+
+        let standard = DeviceState::new();
+        assert_eq!(standard.sensor_data(0, standard.counts()).counts().len(), 8);
+        assert_eq!(apply_command(&standard, modulo(8)), Action::Ignored);
+    }
+
+    #[test]
+    fn test_log_level_selects_forwarded_messages() {
+        let state = DeviceState::new();
+        assert_eq!(state.log_level(), Some(DEFAULT_LOG_LEVEL));
+        let message = state.log_message(LogLevel::Error, format_args!("UART {} failed", "write"));
+        assert_eq!(message.unwrap().text(), "UART write failed");
+        assert!(
+            state
+                .log_message(LogLevel::Info, format_args!("ok"))
+                .is_none()
+        );
+
+        let debug = Packet::SetLogLevel(Some(LogLevel::Debug));
+        assert_eq!(apply_command(&state, debug), Action::None);
+        assert!(
+            state
+                .log_message(LogLevel::Debug, format_args!("ok"))
+                .is_some()
+        );
+
+        apply_command(&state, Packet::SetLogLevel(None));
+        assert!(
+            state
+                .log_message(LogLevel::Error, format_args!("ok"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_commands_for_the_firmware() {
+        let state = DeviceState::new();
+        assert_eq!(
+            apply_command(&state, Packet::Ping { timestamp: 7 }),
+            Action::Reply(Packet::Pong { timestamp: 7 })
+        );
+        let valid = CompareCommand::window(7, 3, -10, 10);
+        assert_eq!(
+            apply_command(&state, Packet::SetCompare(valid)),
+            Action::SetCompare(valid)
+        );
+        let bad_output = CompareCommand::window(7, 4, -10, 10);
+        assert_eq!(
+            apply_command(&state, Packet::SetCompare(bad_output)),
+            Action::Ignored
+        );
+        assert_eq!(apply_command(&state, Packet::GetCounts), Action::None);
+        assert_eq!(
+            apply_command(&state, Packet::Pong { timestamp: 1 }),
+            Action::Ignored
+        );
+    }
+}
@@ -0,0 +1,23 @@
+//! Hardware-independent firmware logic for the RP2040 rotary encoder.
+//!
+//! Sampling, counting, stream scheduling and command handling only depend on `embedded-hal`
+//! pins, the [`Clock`] trait and an `embedded-io-async` writer. The firmware binary wires
+//! them to the RP2040 peripherals, while the tests here drive them with mocked pins and
+//! synthetic quadrature signals on the host.
+
+#![cfg_attr(not(test), no_std)]
+pub mod baud;
+pub mod clock;
+pub mod command;
+pub mod line;
+pub mod sampler;
+pub mod state;
+pub mod stream;
+
+pub use baud::*;
+pub use clock::*;
+pub use command::*;
+pub use line::*;
+pub use sampler::*;
+pub use state::*;
+pub use stream::*;
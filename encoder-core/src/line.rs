@@ -0,0 +1,82 @@
+// encoder-core/src/line.rs
+
+use encoder_protocol::BUFFER_SIZE;
+use heapless::{String, Vec};
+
+/// A received line did not fit the buffer and was discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineOverflow;
+
+/// Assembles newline-terminated frames from the bytes received on the UART.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    line: Vec<u8, BUFFER_SIZE>,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one received byte and returns the line it completes, without the terminator.
+    /// Carriage returns are dropped, and so are completed lines that are not UTF-8.
+    pub fn push(&mut self, byte: u8) -> Result<Option<String<BUFFER_SIZE>>, LineOverflow> {
+        match byte {
+            b'\n' => Ok(String::from_utf8(core::mem::take(&mut self.line)).ok()),
+            b'\r' => Ok(None),
+            byte => {
+                if self.line.push(byte).is_err() {
+                    self.line.clear();
+                    return Err(LineOverflow);
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder_protocol::{ChannelConfig, Packet, parse_packet, serialize_packet};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_lines_are_split_and_overflow_discarded() {
+        let mut buffer = LineBuffer::new();
+        let mut lines = Vec::new();
+        for &byte in b"$GET*47\r\n$PING:1*00\n" {
+            if let Some(line) = buffer.push(byte).unwrap() {
+                lines.push(line);
+            }
+        }
+        assert_eq!(lines, ["$GET*47", "$PING:1*00"]);
+
+        for _ in 0..BUFFER_SIZE {
+            assert_eq!(buffer.push(b'x'), Ok(None));
+        }
+        assert_eq!(buffer.push(b'x'), Err(LineOverflow));
+        assert_eq!(buffer.push(b'\n').unwrap().as_deref(), Some(""));
+
+        for &byte in b"$GET\xFF*47\n" {
+            assert_eq!(buffer.push(byte), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_utf8_names_survive_the_line_buffer() {
+        let mut config = ChannelConfig::new(2);
+        config.set_name("Spindle ë");
+        let packet = Packet::SetChannel(config);
+
+        let mut buffer = LineBuffer::new();
+        let mut lines = Vec::new();
+        for &byte in serialize_packet(&packet).as_bytes() {
+            if let Some(line) = buffer.push(byte).unwrap() {
+                lines.push(line);
+            }
+        }
+        assert_eq!(lines.len(), 1);
+        assert_eq!(parse_packet(&lines[0]), Some(packet));
+    }
+}
@@ -0,0 +1,655 @@
+// encoder-core/src/sampler.rs
+
+use embedded_hal::digital::{InputPin, OutputPin, PinState};
+use encoder_protocol::{
+    ButtonClassifier, COMPARE_OUTPUTS, CaptureChunk, CaptureCommand, Comparator, CompareCommand,
+    Debouncer, EdgeRecorder, EdgeSample, IndexEvent, LatchEvent, MAX_ENCODERS, Packet,
+    SamplingStats, Transition, TransitionMonitor, compare_output_levels,
+};
+use rotary_encoder_embedded::Direction;
+use rotary_encoder_embedded::standard::StandardMode;
+
+use crate::clock::Clock;
+use crate::state::DeviceState;
+
+/// Length of the window over which sampling passes are counted and timed.
+const STATS_WINDOW_US: u64 = 1_000_000;
+
+/// The inputs and outputs handled by the sampling loop.
+#[derive(Debug)]
+pub struct SamplerPins<P, O> {
+    /// A and B inputs of each encoder the board has, leading the array.
+    pub encoders: [Option<(P, P)>; MAX_ENCODERS],
+    /// Optional integrated push switches, active low.
+    pub buttons: [Option<P>; MAX_ENCODERS],
+    /// Optional index (Z) channels, latched on the rising edge.
+    pub index: [Option<P>; MAX_ENCODERS],
+    /// External trigger that latches all counters at once.
+    pub trigger: Option<P>,
+    /// Outputs driven by the compare registers.
+    pub compare_outputs: Option<[O; COMPARE_OUTPUTS]>,
+}
+
+impl<P, O> SamplerPins<P, O> {
+    /// The channels with an index input, bit `n` for encoder `n`. Only these can be homed.
+    pub fn index_inputs(&self) -> u16 {
+        self.index
+            .iter()
+            .enumerate()
+            .filter(|(_, z)| z.is_some())
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+}
+
+/// Queues between the sampling loop and the task that sends events to the host.
+pub trait EventSink {
+    /// Queues a button, index or compare event.
+    fn send_event(&mut self, packet: Packet);
+
+    /// Queues counts latched by the trigger input.
+    fn send_latch(&mut self, event: LatchEvent);
+
+    /// Whether another capture chunk fits the queue. Chunks are only handed out as fast as
+    /// they are sent.
+    fn can_send_chunk(&self) -> bool;
+
+    /// Queues a chunk of a finished edge capture.
+    fn send_chunk(&mut self, chunk: CaptureChunk);
+}
+
+/// Samples all inputs once per pass and updates the [`DeviceState`].
+#[derive(Debug)]
+pub struct Sampler<'a, P, O, C> {
+    pins: SamplerPins<P, O>,
+    clock: C,
+    decoders: [StandardMode; MAX_ENCODERS],
+    /// The decoder drops steps where both inputs change between samples, so the raw pin
+    /// states are watched separately to make those losses visible.
+    monitors: [TransitionMonitor; MAX_ENCODERS],
+    /// Whether each channel was sampled in the last pass, so a channel that is enabled
+    /// again starts from the levels its inputs have by then.
+    sampled: [bool; MAX_ENCODERS],
+    debouncers: [Debouncer; MAX_ENCODERS],
+    classifiers: [ButtonClassifier; MAX_ENCODERS],
+    index_levels: [bool; MAX_ENCODERS],
+    trigger_level: bool,
+    latch_seq: u16,
+    comparators: [Comparator; MAX_ENCODERS],
+    /// Set when a compare register or result changed and the outputs need updating.
+    compare_changed: bool,
+    recorder: EdgeRecorder<'a>,
+    window_start: u64,
+    last_pass: u64,
+    passes: u32,
+    max_pass_us: u32,
+}
+
+impl<'a, P, O, C> Sampler<'a, P, O, C>
+where
+    P: InputPin,
+    O: OutputPin,
+    C: Clock,
+{
+    /// Takes the initial state of every input. Call once the pull-ups have settled, or the
+    /// first pass sees spurious edges.
+    pub fn new(
+        mut pins: SamplerPins<P, O>,
+        capture_buffer: &'a mut [EdgeSample],
+        clock: C,
+    ) -> Self {
+        let levels = pins.encoders.each_mut().map(|pins| {
+            pins.as_mut()
+                .map_or((true, true), |(a, b)| (is_high(a), is_high(b)))
+        });
+
+        let decoders = levels.map(|(a, b)| primed_decoder(a, b));
+
+        let index_levels = pins
+            .index
+            .each_mut()
+            .map(|z| z.as_mut().is_some_and(is_high));
+        let trigger_level = pins.trigger.as_mut().is_some_and(is_high);
+        let now = clock.now_us();
+        Self {
+            clock,
+            decoders,
+            monitors: levels.map(|(a, b)| TransitionMonitor::new(a, b)),
+            sampled: pins.encoders.each_ref().map(Option::is_some),
+            debouncers: [Debouncer::new(); MAX_ENCODERS],
+            classifiers: core::array::from_fn(|i| ButtonClassifier::new(i as u8)),
+            index_levels,
+            trigger_level,
+            latch_seq: 0,
+            comparators: core::array::from_fn(|i| Comparator::new(i as u8)),
+            compare_changed: false,
+            recorder: EdgeRecorder::new(capture_buffer),
+            window_start: now,
+            last_pass: now,
+            passes: 0,
+            max_pass_us: 0,
+            pins,
+        }
+    }
+
+    /// The channels with an index input, bit `n` for encoder `n`.
+    pub fn index_inputs(&self) -> u16 {
+        self.pins.index_inputs()
+    }
+
+    /// Starts recording every step from the next pass on.
+    pub fn start_capture(&mut self, command: CaptureCommand) {
+        self.recorder.start(command, self.clock.now_us() as u32);
+    }
+
+    /// Applies a compare register configuration; the outputs follow in the next pass. An
+    /// output the register asserted until now is reported as released.
+    pub fn configure_compare(
+        &mut self,
+        command: CompareCommand,
+        state: &DeviceState,
+        events: &mut impl EventSink,
+    ) {
+        let encoder = usize::from(command.encoder_id);
+        let now_us = self.now_us();
+        if let Some(comparator) = self.comparators.get_mut(encoder) {
+            if let Some(event) = comparator.configure(command, state.count(encoder), now_us) {
+                events.send_event(Packet::Compare(event));
+            }
+            self.compare_changed = true;
+        }
+    }
+
+    /// Samples every input once, updates the counters and queues the resulting events.
+    pub fn pass(&mut self, state: &DeviceState, events: &mut impl EventSink) {
+        state.beat();
+        let now = self.clock.now_us();
+        let now_us = now as u32;
+
+        // The pass time bounds how quickly an edge is guaranteed to be seen, which limits
+        // the shaft speed that can be tracked.
+        self.max_pass_us = self.max_pass_us.max((now - self.last_pass) as u32);
+        self.last_pass = now;
+        self.passes += 1;
+        if now - self.window_start >= STATS_WINDOW_US {
+            state.set_sampling_stats(SamplingStats {
+                loops_per_second: self.passes,
+                max_loop_us: self.max_pass_us,
+            });
+            self.window_start = now;
+            self.passes = 0;
+            self.max_pass_us = 0;
+        }
+
+        for (i, pins) in self.pins.encoders.iter_mut().enumerate() {
+            let Some((a, b)) = pins.as_mut().filter(|_| state.is_enabled(i)) else {
+                self.sampled[i] = false;
+                continue;
+            };
+            let (a, b) = (is_high(a), is_high(b));
+            if !self.sampled[i] {
+                // The shaft may have moved while the channel was disabled.
+                self.sampled[i] = true;
+                self.decoders[i] = primed_decoder(a, b);
+                self.monitors[i] = TransitionMonitor::new(a, b);
+                continue;
+            }
+            match self.monitors[i].update(a, b, now_us) {
+                Transition::Illegal => state.record_illegal_transition(i),
+                Transition::Glitch => state.record_glitch(i),
+                Transition::Step | Transition::None => {}
+            }
+
+            let clockwise = match self.decoders[i].update(a, b) {
+                Direction::Clockwise => true,
+                Direction::Anticlockwise => false,
+                Direction::None => continue,
+            };
+            let clockwise = clockwise != state.is_inverted(i);
+            state.step(i, clockwise);
+            self.recorder.record(i as u8, clockwise, now_us);
+        }
+
+        self.recorder.poll(now_us);
+        while events.can_send_chunk() {
+            let Some(chunk) = self.recorder.next_chunk() else {
+                break;
+            };
+            events.send_chunk(chunk);
+        }
+
+        // Compare right after the counters were updated, so an output follows its count
+        // within one pass.
+        for (i, comparator) in self.comparators.iter_mut().enumerate() {
+            if !self.sampled[i] {
+                continue;
+            }
+            if let Some(event) = comparator.update(state.count(i), now_us) {
+                self.compare_changed = true;
+                events.send_event(Packet::Compare(event));
+            }
+        }
+        if self.compare_changed {
+            self.compare_changed = false;
+            let levels = compare_output_levels(&self.comparators);
+            for (output, high) in self.pins.compare_outputs.iter_mut().flatten().zip(levels) {
+                let _ = output.set_state(PinState::from(high));
+            }
+        }
+
+        // Latch right after the counters were updated, so all of them come from the same pass.
+        if let Some(trigger) = &mut self.pins.trigger {
+            let level = is_high(trigger);
+            if state.trigger_edge().matches(self.trigger_level, level) {
+                self.latch_seq = self.latch_seq.wrapping_add(1);
+                events.send_latch(LatchEvent {
+                    seq: self.latch_seq,
+                    timestamp_us: now_us,
+                    counts: state.counts(),
+                    encoder_count: state.encoder_count() as u8,
+                });
+            }
+            self.trigger_level = level;
+        }
+
+        let now_ms = (now / 1000) as u32;
+        for (i, button) in self.pins.buttons.iter_mut().enumerate() {
+            let Some(button) = button.as_mut().filter(|_| self.sampled[i]) else {
+                continue;
+            };
+            let pressed = self.debouncers[i].update(!is_high(button), now_ms);
+            if let Some(event) = self.classifiers[i].update(pressed, now_ms) {
+                events.send_event(Packet::Button(event));
+            }
+        }
+
+        for (i, z) in self.pins.index.iter_mut().enumerate() {
+            let Some(z) = z.as_mut().filter(|_| self.sampled[i]) else {
+                continue;
+            };
+            let level = is_high(z);
+            if level && !self.index_levels[i] {
+                let (count, homed) = state.latch_index(i);
+                events.send_event(Packet::Index(IndexEvent {
+                    encoder_id: i as u8,
+                    count,
+                    timestamp: now_ms,
+                    homed,
+                }));
+            }
+            self.index_levels[i] = level;
+        }
+    }
+
+    /// Microseconds since boot according to the sampler's clock, truncated like the
+    /// timestamps it reports.
+    pub fn now_us(&self) -> u32 {
+        self.clock.now_us() as u32
+    }
+}
+
+/// A decoder that has seen the inputs resting at the given levels. StandardMode starts from
+/// an asymmetric history, so a first sample of (Low, Low) would decode as an anticlockwise
+/// step; a few samples of the resting state prime it.
+fn primed_decoder(a: bool, b: bool) -> StandardMode {
+    let mut decoder = StandardMode::new();
+    for _ in 0..4 {
+        decoder.update(a, b);
+    }
+    decoder
+}
+
+/// Reads an input, treating a read error as low.
+fn is_high(pin: &mut impl InputPin) -> bool {
+    pin.is_high().unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use embedded_hal::digital::ErrorType;
+    use encoder_protocol::{ButtonGesture, CAPTURE_CHUNK_SAMPLES, DEFAULT_ENCODERS, TriggerEdge};
+    use std::vec::Vec;
+
+    #[derive(Debug, Default)]
+    struct MockClock(Cell<u64>);
+
+    impl MockClock {
+        fn advance(&self, us: u64) {
+            self.0.set(self.0.get() + us);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now_us(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    /// An input whose level the test sets through a shared cell.
+    #[derive(Debug, Clone, Copy)]
+    struct MockInput<'a>(&'a Cell<bool>);
+
+    impl ErrorType for MockInput<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockInput<'_> {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.0.get())
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.0.get())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct MockOutput<'a>(&'a Cell<bool>);
+
+    impl ErrorType for MockOutput<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockOutput<'_> {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Events {
+        events: Vec<Packet>,
+        latches: Vec<LatchEvent>,
+        chunks: Vec<CaptureChunk>,
+    }
+
+    impl EventSink for Events {
+        fn send_event(&mut self, packet: Packet) {
+            self.events.push(packet);
+        }
+
+        fn send_latch(&mut self, event: LatchEvent) {
+            self.latches.push(event);
+        }
+
+        fn can_send_chunk(&self) -> bool {
+            true
+        }
+
+        fn send_chunk(&mut self, chunk: CaptureChunk) {
+            self.chunks.push(chunk);
+        }
+    }
+
+    /// Signal levels of a simulated board, all idle high like the pulled-up inputs.
+    struct Board {
+        a: [Cell<bool>; MAX_ENCODERS],
+        b: [Cell<bool>; MAX_ENCODERS],
+        button: Cell<bool>,
+        index: Cell<bool>,
+        trigger: Cell<bool>,
+        outputs: [Cell<bool>; COMPARE_OUTPUTS],
+        clock: MockClock,
+    }
+
+    type MockSampler<'a> = Sampler<'a, MockInput<'a>, MockOutput<'a>, &'a MockClock>;
+
+    impl Board {
+        fn new() -> Self {
+            Self {
+                a: core::array::from_fn(|_| Cell::new(true)),
+                b: core::array::from_fn(|_| Cell::new(true)),
+                button: Cell::new(true),
+                index: Cell::new(false),
+                trigger: Cell::new(true),
+                outputs: core::array::from_fn(|_| Cell::new(false)),
+                clock: MockClock::default(),
+            }
+        }
+
+        /// The standard eight encoders, button 0, index 1, the trigger and all compare outputs
+        /// are connected.
+        fn sampler<'a>(&'a self, capture: &'a mut [EdgeSample]) -> MockSampler<'a> {
+            let pins = SamplerPins {
+                encoders: core::array::from_fn(|i| {
+                    (i < DEFAULT_ENCODERS).then(|| (MockInput(&self.a[i]), MockInput(&self.b[i])))
+                }),
+                buttons: core::array::from_fn(|i| (i == 0).then_some(MockInput(&self.button))),
+                index: core::array::from_fn(|i| (i == 1).then_some(MockInput(&self.index))),
+                trigger: Some(MockInput(&self.trigger)),
+                compare_outputs: Some(self.outputs.each_ref().map(MockOutput)),
+            };
+            Sampler::new(pins, capture, &self.clock)
+        }
+
+        /// Runs one pass after the given time has passed.
+        fn pass(
+            &self,
+            sampler: &mut MockSampler<'_>,
+            state: &DeviceState,
+            events: &mut Events,
+            us: u64,
+        ) {
+            self.clock.advance(us);
+            sampler.pass(state, events);
+        }
+
+        /// Drives one channel through full quadrature cycles, one pass per state, with A
+        /// leading B for clockwise motion. Negative cycles turn anticlockwise.
+        fn rotate(
+            &self,
+            sampler: &mut MockSampler<'_>,
+            state: &DeviceState,
+            events: &mut Events,
+            encoder: usize,
+            cycles: i32,
+        ) {
+            let states = [(false, true), (false, false), (true, false), (true, true)];
+            for step in 0..cycles.unsigned_abs() as usize * 4 {
+                let (a, b) = states[step % 4];
+                let (a, b) = if cycles > 0 { (a, b) } else { (b, a) };
+                self.a[encoder].set(a);
+                self.b[encoder].set(b);
+                self.pass(sampler, state, events, 200);
+            }
+        }
+    }
+
+    #[test]
+    fn test_quadrature_counts_in_both_directions() {
+        let board = Board::new();
+        let mut capture = [EdgeSample::default(); 16];
+        let mut sampler = board.sampler(&mut capture);
+        let state = DeviceState::new();
+        let mut events = Events::default();
+
+        board.pass(&mut sampler, &state, &mut events, 200);
+        assert_eq!(state.counts(), [0; MAX_ENCODERS]);
+
+        board.rotate(&mut sampler, &state, &mut events, 3, 5);
+        assert_eq!(state.count(3), 5);
+        board.rotate(&mut sampler, &state, &mut events, 3, -7);
+        assert_eq!(state.count(3), -2);
+        assert_eq!(state.diagnostics(3).illegal_transitions, 0);
+
+        // The counters wrap around, and so does the velocity derived from them.
+        state.set_count(6, i32::MAX - 1);
+        let previous = state.counts();
+        board.rotate(&mut sampler, &state, &mut events, 6, 3);
+        assert_eq!(state.count(6), i32::MIN + 1);
+        state.update_velocities(&previous, &state.counts());
+        assert_eq!(state.velocities()[6], 300);
+
+        // Both inputs flipping at once loses the step and is counted as illegal.
+        board.a[3].set(false);
+        board.b[3].set(false);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        assert_eq!(state.diagnostics(3).illegal_transitions, 1);
+        assert!(events.events.is_empty());
+    }
+
+    #[test]
+    fn test_disabled_and_inverted_channels() {
+        let board = Board::new();
+        let mut capture = [EdgeSample::default(); 16];
+        let mut sampler = board.sampler(&mut capture);
+        let state = DeviceState::new();
+        let mut events = Events::default();
+
+        let mut config = state.channel_config(2);
+        config.inverted = true;
+        state.set_channel_config(&config);
+        board.rotate(&mut sampler, &state, &mut events, 2, 3);
+        assert_eq!(state.count(2), -3);
+
+        // Motion while disabled is not counted, nor is the jump to the inputs' new levels
+        // when the channel comes back.
+        config.enabled = false;
+        state.set_channel_config(&config);
+        board.rotate(&mut sampler, &state, &mut events, 2, 5);
+        board.a[2].set(false);
+        board.b[2].set(false);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        config.enabled = true;
+        state.set_channel_config(&config);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        assert_eq!(state.count(2), -3);
+        assert_eq!(state.diagnostics(2).illegal_transitions, 0);
+
+        board.a[2].set(true);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        board.b[2].set(true);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        board.rotate(&mut sampler, &state, &mut events, 2, 2);
+        assert_eq!(state.count(2), -5);
+    }
+
+    #[test]
+    fn test_pass_statistics_and_heartbeat() {
+        let board = Board::new();
+        let mut capture = [EdgeSample::default(); 16];
+        let mut sampler = board.sampler(&mut capture);
+        let state = DeviceState::new();
+        let mut events = Events::default();
+
+        for _ in 0..100 {
+            board.pass(&mut sampler, &state, &mut events, 10_000);
+        }
+        assert_eq!(state.heartbeat(), 100);
+        assert_eq!(
+            state.sampling_stats(),
+            SamplingStats {
+                loops_per_second: 100,
+                max_loop_us: 10_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_index_homes_armed_counter() {
+        let board = Board::new();
+        let mut capture = [EdgeSample::default(); 16];
+        let mut sampler = board.sampler(&mut capture);
+        let state = DeviceState::new();
+        assert_eq!(sampler.index_inputs(), 0b10);
+        state.set_index_inputs(sampler.index_inputs());
+        let mut events = Events::default();
+        board.rotate(&mut sampler, &state, &mut events, 1, 3);
+
+        state.arm_home(1);
+        board.index.set(true);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        board.index.set(false);
+        board.pass(&mut sampler, &state, &mut events, 200);
+
+        assert_eq!(state.count(1), 0);
+        let [Packet::Index(event)] = events.events[..] else {
+            panic!("expected one index event, got {:?}", events.events);
+        };
+        assert_eq!((event.encoder_id, event.count, event.homed), (1, 3, true));
+    }
+
+    #[test]
+    fn test_button_trigger_and_compare() {
+        let board = Board::new();
+        let mut capture = [EdgeSample::default(); 16];
+        let mut sampler = board.sampler(&mut capture);
+        let state = DeviceState::new();
+        let mut events = Events::default();
+
+        sampler.configure_compare(CompareCommand::at_or_above(0, 2, 2), &state, &mut events);
+        board.rotate(&mut sampler, &state, &mut events, 0, 2);
+        assert!(board.outputs[2].get());
+        assert!(matches!(events.events[..], [Packet::Compare(e)] if e.active && e.count == 2));
+
+        // Moving the threshold away releases the output at once.
+        events.events.clear();
+        sampler.configure_compare(CompareCommand::at_or_above(0, 2, 100), &state, &mut events);
+        assert!(matches!(events.events[..], [Packet::Compare(e)] if !e.active && e.count == 2));
+        board.pass(&mut sampler, &state, &mut events, 200);
+        assert!(!board.outputs[2].get());
+        assert_eq!(events.events.len(), 1);
+        sampler.configure_compare(CompareCommand::at_or_above(0, 2, 2), &state, &mut events);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        assert!(board.outputs[2].get());
+
+        state.set_trigger_edge(TriggerEdge::Falling);
+        board.trigger.set(false);
+        board.pass(&mut sampler, &state, &mut events, 200);
+        assert_eq!(events.latches.len(), 1);
+        assert_eq!(events.latches[0].counts[0], 2);
+
+        // The button is active low and debounced.
+        events.events.clear();
+        board.button.set(false);
+        for _ in 0..10 {
+            board.pass(&mut sampler, &state, &mut events, 5_000);
+        }
+        board.button.set(true);
+        for _ in 0..100 {
+            board.pass(&mut sampler, &state, &mut events, 5_000);
+        }
+        assert!(matches!(
+            events.events[..],
+            [Packet::Button(press), Packet::Button(release)]
+                if press.pressed && !release.pressed && release.gesture == ButtonGesture::None
+        ));
+    }
+
+    #[test]
+    fn test_capture_records_synthetic_motion() {
+        let board = Board::new();
+        let mut capture = [EdgeSample::default(); 16];
+        let mut sampler = board.sampler(&mut capture);
+        let state = DeviceState::new();
+        let mut events = Events::default();
+
+        sampler.start_capture(CaptureCommand {
+            max_samples: 10,
+            duration_ms: 0,
+        });
+        board.rotate(&mut sampler, &state, &mut events, 5, 12);
+
+        let samples: Vec<EdgeSample> = events
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.samples().iter().copied())
+            .collect();
+        assert_eq!(events.chunks.len(), 10usize.div_ceil(CAPTURE_CHUNK_SAMPLES));
+        assert_eq!(samples.len(), 10);
+        assert!(samples.iter().all(|s| s.encoder_id == 5 && s.clockwise));
+        assert!(
+            samples
+                .windows(2)
+                .all(|w| w[1].time_us - w[0].time_us == 800)
+        );
+    }
+}
@@ -0,0 +1,296 @@
+// encoder-core/src/state.rs
+
+use core::fmt::{self, Write as _};
+
+use encoder_protocol::{
+    CHANNEL_NAME_LEN, ChannelConfig, DEFAULT_ENCODERS, DEFAULT_LOG_LEVEL, EncoderDiagnostics,
+    LogLevel, LogMessage, MAX_ENCODERS, SENSOR_PERIOD_MS, SamplingStats, SensorDataPacket,
+    TriggerEdge,
+};
+use portable_atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicU16, AtomicU32, Ordering};
+
+/// Counters and statistics written by the sampling loop and read by everything that reports
+/// them. All fields are atomics, so a single instance in a `static` is shared by both cores.
+#[derive(Debug)]
+pub struct DeviceState {
+    /// Number of encoder channels the board has, the leading ones of every array.
+    encoder_count: usize,
+    counts: [AtomicI32; MAX_ENCODERS],
+    /// Counts per revolution of encoders reported modulo a revolution, 0 for linear ones.
+    counts_per_rev: [AtomicU32; MAX_ENCODERS],
+    /// Whether each channel is sampled and transmitted.
+    enabled: [AtomicBool; MAX_ENCODERS],
+    /// Whether each channel counts the other way round.
+    inverted: [AtomicBool; MAX_ENCODERS],
+    /// Channel names and their lengths in bytes, only used by command handling.
+    names: [[AtomicU8; CHANNEL_NAME_LEN]; MAX_ENCODERS],
+    name_lens: [AtomicU8; MAX_ENCODERS],
+    /// Counts per second over the last sensor period.
+    velocities: [AtomicI32; MAX_ENCODERS],
+    /// Samples per channel where both A and B changed at once.
+    illegal_transitions: [AtomicU32; MAX_ENCODERS],
+    /// Single-input pulses per channel shorter than the glitch window.
+    glitches: [AtomicU32; MAX_ENCODERS],
+    /// Sampling passes completed during the last one-second window.
+    loops_per_second: AtomicU32,
+    /// Longest sampling pass in microseconds during the last one-second window.
+    max_loop_us: AtomicU32,
+    /// The channels with an index input, bit `n` for encoder `n`, as wired by the board.
+    index_inputs: AtomicU16,
+    /// Set by a home command; the sampler zeroes the counter at the next index edge and
+    /// clears it.
+    home_armed: [AtomicBool; MAX_ENCODERS],
+    /// Code of the trigger edge that latches the counters.
+    trigger_edge: AtomicU8,
+    /// Advanced by every sampling pass.
+    heartbeat: AtomicU32,
+    /// Code of the least severe forwarded log level, 0 when forwarding is off.
+    log_level: AtomicU8,
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceState {
+    pub const fn new() -> Self {
+        Self::with_encoders(DEFAULT_ENCODERS)
+    }
+
+    /// State for a board with `encoder_count` channels, at most `MAX_ENCODERS`.
+    pub const fn with_encoders(encoder_count: usize) -> Self {
+        assert!(encoder_count <= MAX_ENCODERS);
+        Self {
+            encoder_count,
+            counts: [const { AtomicI32::new(0) }; MAX_ENCODERS],
+            counts_per_rev: [const { AtomicU32::new(0) }; MAX_ENCODERS],
+            enabled: [const { AtomicBool::new(true) }; MAX_ENCODERS],
+            inverted: [const { AtomicBool::new(false) }; MAX_ENCODERS],
+            names: [const { [const { AtomicU8::new(0) }; CHANNEL_NAME_LEN] }; MAX_ENCODERS],
+            name_lens: [const { AtomicU8::new(0) }; MAX_ENCODERS],
+            velocities: [const { AtomicI32::new(0) }; MAX_ENCODERS],
+            illegal_transitions: [const { AtomicU32::new(0) }; MAX_ENCODERS],
+            glitches: [const { AtomicU32::new(0) }; MAX_ENCODERS],
+            loops_per_second: AtomicU32::new(0),
+            max_loop_us: AtomicU32::new(0),
+            index_inputs: AtomicU16::new(0),
+            home_armed: [const { AtomicBool::new(false) }; MAX_ENCODERS],
+            trigger_edge: AtomicU8::new(0),
+            heartbeat: AtomicU32::new(0),
+            log_level: AtomicU8::new(DEFAULT_LOG_LEVEL.code()),
+        }
+    }
+
+    pub fn encoder_count(&self) -> usize {
+        self.encoder_count
+    }
+
+    /// Whether the board has a channel with this encoder ID.
+    pub fn has_encoder(&self, encoder: usize) -> bool {
+        encoder < self.encoder_count
+    }
+
+    pub fn count(&self, encoder: usize) -> i32 {
+        self.counts[encoder].load(Ordering::SeqCst)
+    }
+
+    pub fn counts(&self) -> [i32; MAX_ENCODERS] {
+        self.counts.each_ref().map(|c| c.load(Ordering::SeqCst))
+    }
+
+    /// Overwrites one counter; out-of-range encoder IDs are ignored.
+    pub fn set_count(&self, encoder: usize, count: i32) {
+        if let Some(c) = self.counts.get(encoder) {
+            c.store(count, Ordering::SeqCst);
+        }
+    }
+
+    pub(crate) fn step(&self, encoder: usize, clockwise: bool) {
+        if clockwise {
+            self.counts[encoder].fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.counts[encoder].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Records which channels have an index input, from [`SamplerPins::index_inputs`].
+    ///
+    /// [`SamplerPins::index_inputs`]: crate::SamplerPins::index_inputs
+    pub fn set_index_inputs(&self, index_inputs: u16) {
+        self.index_inputs.store(index_inputs, Ordering::Relaxed);
+    }
+
+    /// Whether a channel has an index input and can be homed.
+    pub fn has_index(&self, encoder: usize) -> bool {
+        self.has_encoder(encoder) && self.index_inputs.load(Ordering::Relaxed) & (1 << encoder) != 0
+    }
+
+    pub fn counts_per_rev(&self) -> [u32; MAX_ENCODERS] {
+        self.counts_per_rev
+            .each_ref()
+            .map(|c| c.load(Ordering::Relaxed))
+    }
+
+    /// Sets how many counts make one revolution of an encoder reported modulo a revolution,
+    /// or 0 to report it linearly; out-of-range encoder IDs are ignored. The counter itself
+    /// keeps the total count, so only the reporting changes.
+    pub fn set_counts_per_rev(&self, encoder: usize, counts_per_rev: u32) {
+        if let Some(c) = self.counts_per_rev.get(encoder) {
+            c.store(counts_per_rev, Ordering::Relaxed);
+        }
+    }
+
+    /// Builds the sensor data packet for a set of counts taken from this state.
+    pub fn sensor_data(&self, seq: u32, counts: [i32; MAX_ENCODERS]) -> SensorDataPacket {
+        let counts = &counts[..self.encoder_count];
+        let mut data = SensorDataPacket::with_modulo(seq, counts, &self.counts_per_rev());
+        for encoder in 0..self.encoder_count {
+            if !self.is_enabled(encoder) {
+                data.disable(encoder);
+            }
+        }
+        data
+    }
+
+    pub fn is_enabled(&self, encoder: usize) -> bool {
+        self.enabled[encoder].load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_inverted(&self, encoder: usize) -> bool {
+        self.inverted[encoder].load(Ordering::Relaxed)
+    }
+
+    pub fn channel_config(&self, encoder: usize) -> ChannelConfig {
+        let mut config = ChannelConfig::new(encoder as u8);
+        config.enabled = self.is_enabled(encoder);
+        config.inverted = self.is_inverted(encoder);
+        let mut name = [0; CHANNEL_NAME_LEN];
+        for (byte, stored) in name.iter_mut().zip(&self.names[encoder]) {
+            *byte = stored.load(Ordering::Relaxed);
+        }
+        let len = usize::from(self.name_lens[encoder].load(Ordering::Relaxed));
+        config.set_name(core::str::from_utf8(&name[..len]).unwrap_or_default());
+        config
+    }
+
+    /// Replaces a channel's configuration; out-of-range encoder IDs are ignored. A disabled
+    /// channel keeps its count.
+    pub fn set_channel_config(&self, config: &ChannelConfig) {
+        let encoder = usize::from(config.encoder_id);
+        if !self.has_encoder(encoder) {
+            return;
+        }
+        self.enabled[encoder].store(config.enabled, Ordering::Relaxed);
+        self.inverted[encoder].store(config.inverted, Ordering::Relaxed);
+        let name = config.name().as_bytes();
+        for (stored, &byte) in self.names[encoder].iter().zip(name) {
+            stored.store(byte, Ordering::Relaxed);
+        }
+        self.name_lens[encoder].store(name.len() as u8, Ordering::Relaxed);
+    }
+
+    /// Arms one counter to be zeroed at its next index edge; out-of-range IDs are ignored.
+    pub fn arm_home(&self, encoder: usize) {
+        if let Some(armed) = self.home_armed.get(encoder) {
+            armed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Latches one counter at an index edge and, if homing was armed, zeroes it in the same
+    /// step so no count slips in between. Returns the latched count and whether it homed.
+    pub(crate) fn latch_index(&self, encoder: usize) -> (i32, bool) {
+        let homed = self.home_armed[encoder].swap(false, Ordering::SeqCst);
+        let count = if homed {
+            self.counts[encoder].swap(0, Ordering::SeqCst)
+        } else {
+            self.counts[encoder].load(Ordering::SeqCst)
+        };
+        (count, homed)
+    }
+
+    pub fn velocities(&self) -> [i32; MAX_ENCODERS] {
+        self.velocities
+            .each_ref()
+            .map(|v| v.load(Ordering::Relaxed))
+    }
+
+    /// Derives the velocities from the counts of two consecutive sensor periods.
+    pub fn update_velocities(&self, previous: &[i32; MAX_ENCODERS], counts: &[i32; MAX_ENCODERS]) {
+        for (i, velocity) in self.velocities.iter().enumerate() {
+            let delta = counts[i].wrapping_sub(previous[i]);
+            velocity.store(
+                delta.wrapping_mul((1000 / SENSOR_PERIOD_MS) as i32),
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    pub(crate) fn record_illegal_transition(&self, encoder: usize) {
+        self.illegal_transitions[encoder].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_glitch(&self, encoder: usize) {
+        self.glitches[encoder].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn diagnostics(&self, encoder: usize) -> EncoderDiagnostics {
+        EncoderDiagnostics {
+            encoder_id: encoder as u8,
+            illegal_transitions: self.illegal_transitions[encoder].load(Ordering::Relaxed),
+            glitches: self.glitches[encoder].load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn set_sampling_stats(&self, stats: SamplingStats) {
+        self.loops_per_second
+            .store(stats.loops_per_second, Ordering::Relaxed);
+        self.max_loop_us.store(stats.max_loop_us, Ordering::Relaxed);
+    }
+
+    pub fn sampling_stats(&self) -> SamplingStats {
+        SamplingStats {
+            loops_per_second: self.loops_per_second.load(Ordering::Relaxed),
+            max_loop_us: self.max_loop_us.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn trigger_edge(&self) -> TriggerEdge {
+        TriggerEdge::from_code(self.trigger_edge.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+
+    pub fn set_trigger_edge(&self, edge: TriggerEdge) {
+        self.trigger_edge.store(edge.code(), Ordering::Relaxed);
+    }
+
+    /// Number of sampling passes so far, wrapping; stops advancing if the sampler stalls.
+    pub fn heartbeat(&self) -> u32 {
+        self.heartbeat.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn beat(&self) {
+        self.heartbeat.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Least severe level of log messages forwarded to the host, `None` when forwarding is
+    /// off.
+    pub fn log_level(&self) -> Option<LogLevel> {
+        LogLevel::from_code(self.log_level.load(Ordering::Relaxed))
+    }
+
+    pub fn set_log_level(&self, level: Option<LogLevel>) {
+        self.log_level
+            .store(level.map_or(0, |l| l.code()), Ordering::Relaxed);
+    }
+
+    /// Formats a log message for the host, if messages of its level are forwarded.
+    pub fn log_message(&self, level: LogLevel, args: fmt::Arguments<'_>) -> Option<LogMessage> {
+        if self.log_level().is_none_or(|max| level > max) {
+            return None;
+        }
+        let mut message = LogMessage::new(level);
+        let _ = message.write_fmt(args);
+        Some(message)
+    }
+}
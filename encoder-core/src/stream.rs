@@ -0,0 +1,289 @@
+// encoder-core/src/stream.rs
+
+use embedded_io_async::Write;
+use encoder_protocol::{
+    CaptureChunk, DeviceInfo, LatchEvent, LogMessage, MAX_ENCODERS, Packet, serialize_packet,
+};
+
+use crate::state::DeviceState;
+
+/// Counts taken by the main loop at one sensor period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub sequence: u32,
+    pub counts: [i32; MAX_ENCODERS],
+}
+
+/// Packets waiting to be sent next to the periodic stream.
+pub trait EventSource {
+    /// Takes the next queued button, index, compare or reply packet.
+    fn next_event(&mut self) -> Option<Packet>;
+
+    /// Takes the next sample latched by the trigger input.
+    fn next_latch(&mut self) -> Option<LatchEvent>;
+
+    /// Takes the next log message forwarded to the host. Messages queue apart from events, so
+    /// a burst of them cannot crowd out replies.
+    fn next_log(&mut self) -> Option<LogMessage>;
+
+    /// Takes the next chunk of a finished edge capture.
+    fn next_chunk(&mut self) -> Option<CaptureChunk>;
+}
+
+/// Whether the activity LED is lit in a main loop cycle: one second on, one second off.
+pub fn led_on(sequence: u32) -> bool {
+    sequence % 200 < 100
+}
+
+/// Whether a packet sent at `phase` of every `period` cycles fell due in a cycle up to
+/// `sequence` since the `previous` transmitted one, so skipped cycles do not skip it.
+pub fn is_due(previous: Option<u32>, sequence: u32, period: u32, phase: u32) -> bool {
+    // Number of due cycles up to and including `s`.
+    let due_until = |s: u32| (u64::from(s) + u64::from(period - phase)) / u64::from(period);
+    match previous {
+        Some(previous) if previous < sequence => due_until(previous) != due_until(sequence),
+        _ => sequence % period == phase,
+    }
+}
+
+/// Serializes and writes a single packet.
+pub async fn write_packet<W: Write>(tx: &mut W, packet: &Packet) -> Result<(), W::Error> {
+    tx.write_all(serialize_packet(packet).as_bytes()).await
+}
+
+/// Streams one cycle of ASCII packets: the sensor data, plus heartbeat, diagnostics,
+/// sampling statistics and queued events when they are due.
+pub async fn transmit_cycle<W: Write>(
+    tx: &mut W,
+    previous: Option<u32>,
+    snapshot: Snapshot,
+    device_info: &DeviceInfo,
+    state: &DeviceState,
+    queued: &mut impl EventSource,
+) -> Result<(), W::Error> {
+    let sequence = snapshot.sequence;
+
+    // The heartbeat goes out before the first sensor packet of a session so hosts see the
+    // new session ID before any reset counts.
+    if is_due(previous, sequence, 100, 0) {
+        write_packet(tx, &Packet::DeviceInfo(*device_info)).await?;
+    }
+
+    let data = state.sensor_data(sequence, snapshot.counts);
+    write_packet(tx, &Packet::SensorData(data)).await?;
+    tx.flush().await?;
+
+    // Report one channel's signal-quality counters every 10 cycles, rotating through all.
+    if is_due(previous, sequence, 10, 5) {
+        let id = (sequence / 10) as usize % state.encoder_count();
+        if state.is_enabled(id) {
+            write_packet(tx, &Packet::Diagnostics(state.diagnostics(id))).await?;
+        }
+    }
+
+    if is_due(previous, sequence, 100, 50) {
+        write_packet(tx, &Packet::Sampling(state.sampling_stats())).await?;
+    }
+
+    while let Some(packet) = queued.next_event() {
+        write_packet(tx, &packet).await?;
+    }
+
+    while let Some(event) = queued.next_latch() {
+        write_packet(tx, &Packet::Latch(event)).await?;
+    }
+
+    while let Some(message) = queued.next_log() {
+        write_packet(tx, &Packet::Log(message)).await?;
+    }
+
+    // One capture chunk per cycle stays within the link's spare capacity.
+    if let Some(chunk) = queued.next_chunk() {
+        write_packet(tx, &Packet::CaptureData(chunk)).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use core::fmt::Write as _;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use embedded_io_async::ErrorType;
+    use encoder_protocol::{IndexEvent, LogLevel, ResetReason, parse_packet};
+    use std::collections::VecDeque;
+    use std::string::String;
+    use std::vec::Vec;
+
+    /// Runs a future that never waits, as every mock here completes immediately.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MockUart(Vec<u8>);
+
+    impl ErrorType for MockUart {
+        type Error = Infallible;
+    }
+
+    impl Write for MockUart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    impl MockUart {
+        fn take_packets(&mut self) -> Vec<Packet> {
+            let text = String::from_utf8(core::mem::take(&mut self.0)).unwrap();
+            text.lines()
+                .map(|line| parse_packet(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Queues {
+        events: VecDeque<Packet>,
+        latches: VecDeque<LatchEvent>,
+        logs: VecDeque<LogMessage>,
+        chunks: VecDeque<CaptureChunk>,
+    }
+
+    impl EventSource for Queues {
+        fn next_event(&mut self) -> Option<Packet> {
+            self.events.pop_front()
+        }
+
+        fn next_latch(&mut self) -> Option<LatchEvent> {
+            self.latches.pop_front()
+        }
+
+        fn next_log(&mut self) -> Option<LogMessage> {
+            self.logs.pop_front()
+        }
+
+        fn next_chunk(&mut self) -> Option<CaptureChunk> {
+            self.chunks.pop_front()
+        }
+    }
+
+    fn info() -> DeviceInfo {
+        DeviceInfo {
+            session_id: 0xCAFE,
+            reset_reason: ResetReason::PowerOn,
+            protocol_version: 2,
+            counts_restored: false,
+            serial_number: 42,
+            encoder_count: 8,
+            index_inputs: 0b11,
+        }
+    }
+
+    fn cycle(
+        uart: &mut MockUart,
+        previous: Option<u32>,
+        sequence: u32,
+        state: &DeviceState,
+        queues: &mut Queues,
+    ) -> Vec<Packet> {
+        let snapshot = Snapshot {
+            sequence,
+            counts: state.counts(),
+        };
+        block_on(transmit_cycle(
+            uart,
+            previous,
+            snapshot,
+            &info(),
+            state,
+            queues,
+        ))
+        .unwrap();
+        uart.take_packets()
+    }
+
+    #[test]
+    fn test_is_due_catches_up_after_skipped_cycles() {
+        assert!(is_due(None, 0, 100, 0));
+        assert!(!is_due(None, 1, 100, 0));
+        assert!(is_due(Some(98), 101, 100, 0));
+        assert!(!is_due(Some(101), 102, 100, 0));
+        assert!(is_due(Some(3), 7, 10, 5));
+        assert!(!is_due(Some(5), 14, 10, 5));
+    }
+
+    #[test]
+    fn test_first_cycle_sends_heartbeat_before_counts() {
+        let state = DeviceState::new();
+        state.set_count(0, 12);
+        let mut queues = Queues::default();
+        queues.events.push_back(Packet::Index(IndexEvent {
+            encoder_id: 1,
+            count: 3,
+            timestamp: 100,
+            homed: false,
+        }));
+        let mut uart = MockUart::default();
+
+        let packets = cycle(&mut uart, None, 0, &state, &mut queues);
+        assert!(matches!(packets[..], [
+            Packet::DeviceInfo(info),
+            Packet::SensorData(data),
+            Packet::Index(_),
+        ] if info.session_id == 0xCAFE && data.seq == 0 && data.encoders[0] == 12));
+
+        let packets = cycle(&mut uart, Some(0), 1, &state, &mut queues);
+        assert!(matches!(packets[..], [Packet::SensorData(_)]));
+    }
+
+    #[test]
+    fn test_logs_follow_events() {
+        let state = DeviceState::new();
+        let mut queues = Queues::default();
+        let mut message = LogMessage::new(LogLevel::Warn);
+        message.write_str("RX line overflow").unwrap();
+        queues.logs.push_back(message);
+        queues.events.push_back(Packet::BaudAck { baud: 921_600 });
+        let mut uart = MockUart::default();
+
+        let packets = cycle(&mut uart, Some(0), 1, &state, &mut queues);
+        assert!(matches!(&packets[..], [
+            Packet::SensorData(_),
+            Packet::BaudAck { .. },
+            Packet::Log(log),
+        ] if log.text() == "RX line overflow"));
+    }
+
+    #[test]
+    fn test_skipped_cycles_keep_periodic_packets() {
+        let state = DeviceState::new();
+        let mut queues = Queues::default();
+        let mut uart = MockUart::default();
+
+        // Cycles 45 to 54 were skipped, which covered both a diagnostics and a rate slot.
+        let packets = cycle(&mut uart, Some(44), 55, &state, &mut queues);
+        assert!(matches!(packets[..], [
+            Packet::SensorData(_),
+            Packet::Diagnostics(diag),
+            Packet::Sampling(_),
+        ] if diag.encoder_id == 5));
+    }
+
+    #[test]
+    fn test_led_blinks_once_per_two_seconds() {
+        assert!(led_on(0));
+        assert!(led_on(99));
+        assert!(!led_on(100));
+        assert!(led_on(200));
+    }
+}
@@ -1,43 +1,418 @@
-//! RP2040 firmware that continuously tracks 8 rotary encoders and streams their accumulated values over UART.
+//! RP2040 firmware that continuously tracks 8 rotary encoders, or 12 with the `encoders-12`
+//! feature, and streams their accumulated values over UART.
 
 #![no_std]
 #![no_main]
 
+#[cfg(feature = "spi-target")]
+mod spi_target;
+
 use defmt::info;
 use embassy_executor::{Executor, Spawner};
 use embassy_rp::gpio::{Input, Pull};
 use embassy_rp::pwm::{Config as PwmConfig, Pwm};
 use embassy_rp::multicore::{spawn_core1, Stack};
-use portable_atomic::{AtomicI32, Ordering};
-use rotary_encoder_embedded::{Direction, InitalizeMode, RotaryEncoder};
-use static_cell::StaticCell;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+use portable_atomic::AtomicBool;
+use portable_atomic::{AtomicU32, Ordering};
+use static_cell::{ConstStaticCell, StaticCell};
 
 use embassy_rp::bind_interrupts;
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::flash::{Blocking, Flash};
+use embassy_rp::pac;
 use embassy_rp::peripherals::UART0;
-use embassy_rp::uart::{BufferedInterruptHandler, BufferedUart, BufferedUartRx, Config};
+use embassy_rp::uart::{BufferedInterruptHandler, BufferedUart, Config};
+use embassy_rp::watchdog::Watchdog;
 use embedded_io_async::{Read, Write};
 
+use encoder_core::{led_on, Clock, DeviceState, EventSink, EventSource, Sampler, SamplerPins};
+
+#[cfg(not(feature = "modbus"))]
+use encoder_core::{apply_command, Action, LineBuffer, LineOverflow};
+#[cfg(all(any(feature = "rs485", feature = "polled"), not(feature = "modbus")))]
+use encoder_protocol::serialize_packet;
+#[cfg(all(any(feature = "rs485", feature = "polled"), not(feature = "modbus")))]
+use heapless::String;
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+use embassy_rp::uart::{BufferedUartRx, BufferedUartTx};
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+use encoder_core::{transmit_cycle, write_packet, BaudFallback, BaudLink, Snapshot};
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+use encoder_protocol::parse_packet;
+
+#[cfg(feature = "modbus")]
+use encoder_protocol::{handle_frame, ModbusDevice, MODBUS_MAX_FRAME};
+
+use embassy_rp::gpio::Output;
+#[cfg(any(feature = "rs485", feature = "compare"))]
+use embassy_rp::gpio::Level;
+#[cfg(all(any(feature = "rs485", feature = "polled"), not(feature = "modbus")))]
+use encoder_protocol::{parse_addressed_packet, serialize_addressed_packet};
+#[cfg(all(feature = "rs485", not(feature = "modbus")))]
+use encoder_protocol::BUS_BROADCAST_ADDRESS;
+
+#[cfg(all(feature = "rs485", feature = "i2c-target"))]
+compile_error!("The rs485 and i2c-target features both need GP0 and cannot be combined.");
+#[cfg(all(feature = "trigger", feature = "i2c-target"))]
+compile_error!("The trigger and i2c-target features both need GP1 and cannot be combined.");
+
+#[cfg(all(feature = "compare", feature = "spi-target"))]
+compile_error!("The compare and spi-target features both need GP18-21 and cannot be combined.");
+
+#[cfg(all(
+    feature = "encoders-12",
+    any(
+        feature = "i2c-target",
+        feature = "rs485",
+        feature = "trigger",
+        feature = "spi-target",
+        feature = "compare"
+    )
+))]
+compile_error!("The encoders-12 feature uses GP0, GP1, GP18-22 and GP28 for encoders 8-11.");
+
+#[cfg(feature = "compare")]
+use encoder_protocol::CompareCommand;
+
+#[cfg(feature = "i2c-target")]
+use embassy_rp::i2c_slave::{self, Command, I2cSlave};
+#[cfg(feature = "i2c-target")]
+use embassy_rp::{i2c, peripherals::I2C0};
+#[cfg(feature = "i2c-target")]
+use encoder_protocol::{
+    RegisterCommand, RegisterMap, I2C_TARGET_ADDRESS, REG_COMMAND, STATUS_COUNTS_RESTORED,
+};
+
 use encoder_protocol::{
-    serialize_packet, Packet, SensorDataPacket, BUFFER_SIZE, MAX_ENCODERS,
+    CaptureChunk, CaptureCommand, DeviceInfo, EdgeSample, LatchEvent, LogLevel, LogMessage,
+    Packet, ResetReason,
+    BUFFER_SIZE, CAPTURE_CAPACITY, DEFAULT_BAUD, MAX_ENCODERS, PROTOCOL_VERSION,
+    SENSOR_PERIOD_MS,
 };
+#[cfg(not(feature = "encoders-12"))]
+use encoder_protocol::DEFAULT_ENCODERS;
+#[cfg(any(feature = "i2c-target", feature = "spi-target"))]
+use encoder_protocol::REGISTER_ENCODERS;
 use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
     UART0_IRQ => BufferedInterruptHandler<UART0>;
 });
 
+#[cfg(feature = "i2c-target")]
+bind_interrupts!(struct I2cIrqs {
+    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
+});
+
 static CORE1_STACK: StaticCell<Stack<4096>> = StaticCell::new();
 static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
 
+/// Everything handed to Core 1 for sampling.
 struct Encoders {
-    encoders: [RotaryEncoder<InitalizeMode, Input<'static>, Input<'static>>; MAX_ENCODERS],
+    pins: SamplerPins<Input<'static>, Output<'static>>,
+    #[cfg(feature = "spi-target")]
+    spi: spi_target::SpiTarget,
+    /// Storage for edge captures.
+    capture: &'static mut [EdgeSample; CAPTURE_CAPACITY],
 }
 
-static ENCODER_COUNTS: [AtomicI32; MAX_ENCODERS] = [const { AtomicI32::new(0) }; MAX_ENCODERS];
+/// Number of encoders wired on this build.
+#[cfg(not(feature = "encoders-12"))]
+const ENCODER_COUNT: usize = DEFAULT_ENCODERS;
+#[cfg(feature = "encoders-12")]
+const ENCODER_COUNT: usize = 12;
+
+/// The watchdog has eight scratch registers, one per mirrored count.
+const SCRATCH_COUNTS: usize = 8;
+
+/// Counters and statistics shared by both cores.
+static STATE: DeviceState = DeviceState::with_encoders(ENCODER_COUNT);
+
+/// Size of the on-board QSPI flash of the Raspberry Pi Pico.
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// The watchdog resets the chip if it is not fed within this time.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_millis(500);
+/// Interval between liveness checks of the sampling, main and transmit loops.
+const LIVENESS_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Advanced by every completed cycle of the Core 0 main loop, which is also the sequence
+/// number of the next snapshot.
+static MAIN_HEARTBEAT: AtomicU32 = AtomicU32::new(0);
+
+/// Set by the streaming transmitter to the sequence number of every snapshot it has written
+/// out, so a transmitter stuck on the UART stops it.
+static TX_HEARTBEAT: AtomicU32 = AtomicU32::new(0);
+
+/// The newest snapshot waiting for the transmitter. A snapshot not yet taken when the next
+/// one is ready is overwritten, so a slow link skips sequence numbers instead of stalling
+/// the sampling schedule.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+static LATEST_SNAPSHOT: Signal<CriticalSectionRawMutex, Snapshot> = Signal::new();
+
+/// Baud rate proposed by the host, applied by the transmitter; 0 when none is pending.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+static PENDING_BAUD: AtomicU32 = AtomicU32::new(0);
+/// Set by the reader for every valid frame and cleared by the transmitter, which confirms a
+/// newly negotiated baud rate with it and keeps the rate while the host keeps talking.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+static HOST_FRAME_SEEN: AtomicBool = AtomicBool::new(false);
+
+/// Event and reply packets produced outside the main loop, waiting to be transmitted by Core 0.
+static OUTBOX: Channel<CriticalSectionRawMutex, Packet, 16> = Channel::new();
+
+/// Counts latched by the external trigger, waiting to be transmitted by Core 0. Kept apart
+/// from the outbox so a burst of trigger edges cannot crowd out other events.
+static LATCHES: Channel<CriticalSectionRawMutex, LatchEvent, 8> = Channel::new();
+
+/// Log messages forwarded to the host, sent after the events. Kept apart from the outbox so
+/// a burst of messages cannot crowd out replies.
+static LOGS: Channel<CriticalSectionRawMutex, LogMessage, 4> = Channel::new();
+/// Log messages dropped because the queue was full, reported once per second.
+#[cfg(not(feature = "modbus"))]
+static LOGS_DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Compare register configurations sent by the host, applied by Core 1.
+#[cfg(feature = "compare")]
+static COMPARE_CONFIG: Channel<CriticalSectionRawMutex, CompareCommand, 8> = Channel::new();
+
+/// Edge capture buffer, placed in static RAM because it is far larger than the Core 1 stack.
+static CAPTURE_BUFFER: ConstStaticCell<[EdgeSample; CAPTURE_CAPACITY]> =
+    ConstStaticCell::new(
+        [EdgeSample {
+            encoder_id: 0,
+            clockwise: false,
+            time_us: 0,
+        }; CAPTURE_CAPACITY],
+    );
+/// Capture requests from the host, started by Core 1.
+static CAPTURE_REQUEST: Channel<CriticalSectionRawMutex, CaptureCommand, 1> = Channel::new();
+/// Chunks of a finished capture, handed out by Core 1 as fast as Core 0 sends them.
+static CAPTURE_CHUNKS: Channel<CriticalSectionRawMutex, CaptureChunk, 4> = Channel::new();
+
+/// The queues between the sampling loop and the tasks that send their contents.
+struct Queues;
+
+impl EventSink for Queues {
+    fn send_event(&mut self, packet: Packet) {
+        if OUTBOX.try_send(packet).is_err() {
+            defmt::warn!("Outbox full, dropping event");
+        }
+    }
+
+    fn send_latch(&mut self, event: LatchEvent) {
+        if LATCHES.try_send(event).is_err() {
+            defmt::warn!("Latch FIFO full, dropping latch {}", event.seq);
+        }
+    }
+
+    fn can_send_chunk(&self) -> bool {
+        !CAPTURE_CHUNKS.is_full()
+    }
+
+    fn send_chunk(&mut self, chunk: CaptureChunk) {
+        if chunk.is_last() {
+            info!("Edge capture of {} steps queued", chunk.total);
+        }
+        let _ = CAPTURE_CHUNKS.try_send(chunk);
+    }
+}
+
+impl EventSource for Queues {
+    fn next_event(&mut self) -> Option<Packet> {
+        OUTBOX.try_receive().ok()
+    }
+
+    fn next_latch(&mut self) -> Option<LatchEvent> {
+        LATCHES.try_receive().ok()
+    }
+
+    fn next_log(&mut self) -> Option<LogMessage> {
+        LOGS.try_receive().ok()
+    }
+
+    fn next_chunk(&mut self) -> Option<CaptureChunk> {
+        CAPTURE_CHUNKS.try_receive().ok()
+    }
+}
+
+/// The embassy time driver as the sampling loop's clock.
+struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now_us(&self) -> u64 {
+        Instant::now().as_micros()
+    }
+}
+
+/// Logs through defmt and also forwards the message to the host if its level is enabled.
+///
+/// The format string is shared with `core::fmt`, so only plain `{}` placeholders work.
+macro_rules! host_log {
+    (Error, $($arg:tt)*) => {{
+        defmt::error!($($arg)*);
+        forward_log(LogLevel::Error, format_args!($($arg)*));
+    }};
+    (Warn, $($arg:tt)*) => {{
+        defmt::warn!($($arg)*);
+        forward_log(LogLevel::Warn, format_args!($($arg)*));
+    }};
+    (Info, $($arg:tt)*) => {{
+        defmt::info!($($arg)*);
+        forward_log(LogLevel::Info, format_args!($($arg)*));
+    }};
+}
+
+/// Queues a log message for the host if its level is forwarded. Modbus has no place for
+/// log messages, so nothing is forwarded there.
+fn forward_log(level: LogLevel, args: core::fmt::Arguments<'_>) {
+    #[cfg(not(feature = "modbus"))]
+    if let Some(message) = STATE.log_message(level, args)
+        && LOGS.try_send(message).is_err()
+    {
+        LOGS_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    #[cfg(feature = "modbus")]
+    let _ = (level, args);
+}
+
+/// Places the inputs of the channels wired on this build at the front of a per-channel array.
+fn channels<T, const N: usize>(wired: [T; N]) -> [Option<T>; MAX_ENCODERS] {
+    let mut wired = wired.into_iter();
+    core::array::from_fn(|_| wired.next())
+}
+
+/// Counts of the encoders mapped into the register and SPI snapshot layouts.
+#[cfg(any(feature = "i2c-target", feature = "spi-target"))]
+fn register_counts() -> [i32; REGISTER_ENCODERS] {
+    core::array::from_fn(|i| STATE.count(i))
+}
+
+/// Determines why the chip last came out of reset from the watchdog and chip reset registers.
+fn read_reset_reason() -> ResetReason {
+    let watchdog = pac::WATCHDOG.reason().read();
+    if watchdog.force() {
+        return ResetReason::WatchdogForced;
+    }
+    if watchdog.timer() {
+        return ResetReason::WatchdogTimeout;
+    }
+
+    let chip = pac::VREG_AND_CHIP_RESET.chip_reset().read();
+    if chip.had_psm_restart() {
+        ResetReason::Debugger
+    } else if chip.had_run() {
+        ResetReason::RunPin
+    } else if chip.had_por() {
+        ResetReason::PowerOn
+    } else {
+        ResetReason::Unknown
+    }
+}
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
+    let mut watchdog = Watchdog::new(p.WATCHDOG);
+
+    // The watchdog scratch registers survive a watchdog reset but are cleared on power-on,
+    // so counts mirrored there by the supervisor are only trusted after a timeout. Forced
+    // resets are excluded because the boot ROM reuses the scratch registers for them.
+    // With more encoders than scratch registers the host has to restore the counts.
+    let reset_reason = read_reset_reason();
+    let counts_restored =
+        reset_reason == ResetReason::WatchdogTimeout && ENCODER_COUNT <= SCRATCH_COUNTS;
+    if counts_restored {
+        for i in 0..ENCODER_COUNT {
+            STATE.set_count(i, watchdog.get_scratch(i) as i32);
+        }
+        host_log!(Warn, "Recovered from watchdog timeout, counts restored");
+    }
+
+    // The flash unique ID gives every board a stable serial number. It is read before Core 1
+    // starts, because flash commands stall any code executing from flash.
+    let mut flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
+    let mut unique_id = [0u8; 8];
+    if flash.blocking_unique_id(&mut unique_id).is_err() {
+        defmt::error!("Failed to read flash unique ID");
+    }
+    let serial_number = u64::from_be_bytes(unique_id);
+
+    // The pins are set up ahead of the heartbeat, which announces the index inputs.
+    let pins = SamplerPins {
+        encoders: channels([
+            (Input::new(p.PIN_2, Pull::Up), Input::new(p.PIN_3, Pull::Up)),
+            (Input::new(p.PIN_4, Pull::Up), Input::new(p.PIN_5, Pull::Up)),
+            (Input::new(p.PIN_6, Pull::Up), Input::new(p.PIN_7, Pull::Up)),
+            (Input::new(p.PIN_8, Pull::Up), Input::new(p.PIN_9, Pull::Up)),
+            (Input::new(p.PIN_10, Pull::Up), Input::new(p.PIN_11, Pull::Up)),
+            (Input::new(p.PIN_12, Pull::Up), Input::new(p.PIN_13, Pull::Up)),
+            (Input::new(p.PIN_14, Pull::Up), Input::new(p.PIN_15, Pull::Up)),
+            (Input::new(p.PIN_27, Pull::Up), Input::new(p.PIN_26, Pull::Up)),
+            #[cfg(feature = "encoders-12")]
+            (Input::new(p.PIN_18, Pull::Up), Input::new(p.PIN_19, Pull::Up)),
+            #[cfg(feature = "encoders-12")]
+            (Input::new(p.PIN_20, Pull::Up), Input::new(p.PIN_21, Pull::Up)),
+            #[cfg(feature = "encoders-12")]
+            (Input::new(p.PIN_22, Pull::Up), Input::new(p.PIN_28, Pull::Up)),
+            #[cfg(feature = "encoders-12")]
+            (Input::new(p.PIN_0, Pull::Up), Input::new(p.PIN_1, Pull::Up)),
+        ]),
+        #[cfg(not(any(feature = "spi-target", feature = "compare", feature = "encoders-12")))]
+        buttons: channels([
+            Input::new(p.PIN_18, Pull::Up),
+            Input::new(p.PIN_19, Pull::Up),
+            Input::new(p.PIN_20, Pull::Up),
+            Input::new(p.PIN_21, Pull::Up),
+        ]),
+        // The SPI target, the compare outputs or encoders 8-11 take over the button pins.
+        #[cfg(any(feature = "spi-target", feature = "compare", feature = "encoders-12"))]
+        buttons: [const { None }; MAX_ENCODERS],
+        #[cfg(not(feature = "encoders-12"))]
+        index: channels([
+            Input::new(p.PIN_22, Pull::Up),
+            Input::new(p.PIN_28, Pull::Up),
+        ]),
+        #[cfg(feature = "encoders-12")]
+        index: [const { None }; MAX_ENCODERS],
+        #[cfg(feature = "trigger")]
+        trigger: Some(Input::new(p.PIN_1, Pull::Up)),
+        #[cfg(not(feature = "trigger"))]
+        trigger: None,
+        #[cfg(feature = "compare")]
+        compare_outputs: Some([
+            Output::new(p.PIN_18, Level::Low),
+            Output::new(p.PIN_19, Level::Low),
+            Output::new(p.PIN_20, Level::Low),
+            Output::new(p.PIN_21, Level::Low),
+        ]),
+        #[cfg(not(feature = "compare"))]
+        compare_outputs: None,
+    };
+    STATE.set_index_inputs(pins.index_inputs());
+
+    // A fresh random ID per boot lets hosts tell a reset device from a stalled stream.
+    let device_info = DeviceInfo {
+        session_id: RoscRng.next_u32(),
+        reset_reason,
+        protocol_version: PROTOCOL_VERSION,
+        counts_restored,
+        serial_number,
+        encoder_count: ENCODER_COUNT as u8,
+        index_inputs: pins.index_inputs(),
+    };
+    info!(
+        "Serial {:016X}, session {:08X}, reset reason {}",
+        device_info.serial_number,
+        device_info.session_id,
+        device_info.reset_reason.code()
+    );
 
     let mut pwm_config: PwmConfig = Default::default();
     pwm_config.top = 20000;
@@ -52,7 +427,7 @@ async fn main(spawner: Spawner) {
     static RX_BUF: StaticCell<[u8; BUFFER_SIZE]> = StaticCell::new();
     let rx_buf = &mut RX_BUF.init([0; BUFFER_SIZE])[..];
     let mut config = Config::default();
-    config.baudrate = 115_200;
+    config.baudrate = DEFAULT_BAUD;
     
     let uart = BufferedUart::new(
         uart,
@@ -63,33 +438,38 @@ async fn main(spawner: Spawner) {
         rx_buf,
         config,
     );
-    let (mut tx, rx) = uart.split();
+    #[cfg(any(feature = "modbus", feature = "rs485", feature = "polled"))]
+    let bus = BusDriver {
+        #[cfg(feature = "rs485")]
+        driver_enable: Output::new(p.PIN_0, Level::Low),
+    };
 
+    #[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+    let (tx, rx) = uart.split();
+    #[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
     spawner.must_spawn(reader(rx));
+    #[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+    spawner.must_spawn(transmitter(tx, device_info));
+    #[cfg(feature = "modbus")]
+    spawner.must_spawn(modbus_server(uart, bus));
+    #[cfg(all(any(feature = "rs485", feature = "polled"), not(feature = "modbus")))]
+    spawner.must_spawn(polled_server(uart, bus, device_info));
+
+    // GP0 and GP1 are the only pins not taken by encoders, buttons, index inputs or the UART,
+    // unless encoders 8-11 take them along with the button and index pins.
+    #[cfg(feature = "i2c-target")]
+    {
+        let mut i2c_config = i2c_slave::Config::default();
+        i2c_config.addr = I2C_TARGET_ADDRESS.into();
+        let target = I2cSlave::new(p.I2C0, p.PIN_1, p.PIN_0, I2cIrqs, i2c_config);
+        spawner.must_spawn(i2c_target(target, counts_restored));
+    }
 
     let encoders = Encoders {
-        encoders: [
-            RotaryEncoder::new(Input::new(p.PIN_2, Pull::Up), Input::new(p.PIN_3, Pull::Up)),
-            RotaryEncoder::new(Input::new(p.PIN_4, Pull::Up), Input::new(p.PIN_5, Pull::Up)),
-            RotaryEncoder::new(Input::new(p.PIN_6, Pull::Up), Input::new(p.PIN_7, Pull::Up)),
-            RotaryEncoder::new(Input::new(p.PIN_8, Pull::Up), Input::new(p.PIN_9, Pull::Up)),
-            RotaryEncoder::new(
-                Input::new(p.PIN_10, Pull::Up),
-                Input::new(p.PIN_11, Pull::Up),
-            ),
-            RotaryEncoder::new(
-                Input::new(p.PIN_12, Pull::Up),
-                Input::new(p.PIN_13, Pull::Up),
-            ),
-            RotaryEncoder::new(
-                Input::new(p.PIN_14, Pull::Up),
-                Input::new(p.PIN_15, Pull::Up),
-            ),
-            RotaryEncoder::new(
-                Input::new(p.PIN_27, Pull::Up),
-                Input::new(p.PIN_26, Pull::Up),
-            ),
-        ],
+        pins,
+        #[cfg(feature = "spi-target")]
+        spi: spi_target::SpiTarget::new(p.SPI0, p.PIN_18, p.PIN_19, p.PIN_20, p.PIN_21),
+        capture: CAPTURE_BUFFER.take(),
     };
 
     spawn_core1(
@@ -101,38 +481,163 @@ async fn main(spawner: Spawner) {
         },
     );
 
+    watchdog.pause_on_debug(true);
+    spawner.must_spawn(supervisor(watchdog));
+
     let mut sequence = 0u32;
+    let mut previous_counts = [0i32; MAX_ENCODERS];
+    #[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+    let mut skipped_frames = 0u32;
+    // A ticker keeps the period steady regardless of how long each cycle takes.
+    let mut ticker = embassy_time::Ticker::every(Duration::from_millis(SENSOR_PERIOD_MS.into()));
 
     loop {
-        embassy_time::Timer::after_millis(10).await;
+        ticker.next().await;
 
-        let cycle_tick = sequence % 200;
-        if cycle_tick < 100 {
-            pwm_config.compare_b = max_brightness;
-        } else {
-            pwm_config.compare_b = 0;
-        }
+        pwm_config.compare_b = if led_on(sequence) { max_brightness } else { 0 };
         led_pwm.set_config(&pwm_config);
 
-        let encoder_counts = ENCODER_COUNTS.each_ref().map(|c| c.load(Ordering::SeqCst));
-        let sensor_data_packet = SensorDataPacket {
-            seq: sequence,
-            encoders: encoder_counts,
-        };
-        let packet = Packet::SensorData(sensor_data_packet);
-        let buf = serialize_packet(&packet);
+        let encoder_counts = STATE.counts();
+        STATE.update_velocities(&previous_counts, &encoder_counts);
+        previous_counts = encoder_counts;
 
         if sequence % 10 == 0 {
             info!("TX Seq: {:?} Counts: {:?}", sequence, encoder_counts);
         }
+        if sequence % 100 == 50 {
+            let stats = STATE.sampling_stats();
+            info!(
+                "Core 1: {} loops/s, worst loop {} us",
+                stats.loops_per_second, stats.max_loop_us
+            );
+        }
+
+        #[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+        {
+            if LATEST_SNAPSHOT.signaled() {
+                skipped_frames += 1;
+            }
+            LATEST_SNAPSHOT.signal(Snapshot {
+                sequence,
+                counts: encoder_counts,
+            });
+            if sequence % 100 == 99 && skipped_frames > 0 {
+                host_log!(Warn, "Link too slow, {} frames skipped in the last second", skipped_frames);
+                skipped_frames = 0;
+            }
+        }
 
-        if let Err(_e) = tx.write_all(buf.as_bytes()).await {
-            defmt::error!("UART write failed");
+        #[cfg(not(feature = "modbus"))]
+        if sequence % 100 == 99 {
+            let dropped = LOGS_DROPPED.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                host_log!(Warn, "Log queue full, {} messages dropped in the last second", dropped);
+            }
         }
-        if let Err(_e) = tx.flush().await {
-            defmt::error!("UART flush failed");
+
+        // Events have no place in the Modbus register map.
+        #[cfg(feature = "modbus")]
+        {
+            while OUTBOX.try_receive().is_ok() {}
+            while LATCHES.try_receive().is_ok() {}
+            while CAPTURE_CHUNKS.try_receive().is_ok() {}
         }
+
         sequence += 1;
+        MAIN_HEARTBEAT.store(sequence, Ordering::Relaxed);
+    }
+}
+
+/// Sends the newest snapshot whenever the UART has caught up with the previous one.
+///
+/// Snapshots overwritten while the link was busy are never sent, so the host sees a gap in
+/// the sequence numbers.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+#[embassy_executor::task]
+async fn transmitter(mut tx: BufferedUartTx, device_info: DeviceInfo) {
+    let mut previous = None;
+    let mut link = BaudLink::new();
+
+    loop {
+        let snapshot = LATEST_SNAPSHOT.wait().await;
+        if let Err(_e) =
+            transmit_cycle(&mut tx, previous, snapshot, &device_info, &STATE, &mut Queues).await
+        {
+            host_log!(Error, "UART write failed");
+        }
+        previous = Some(snapshot.sequence);
+        TX_HEARTBEAT.store(snapshot.sequence, Ordering::Relaxed);
+
+        let requested = PENDING_BAUD.swap(0, Ordering::Relaxed);
+        if requested != 0 {
+            let new_baud = link.resolve(requested);
+            if new_baud != requested {
+                host_log!(Warn, "Unsupported baud rate {} requested", requested);
+            }
+            // The acknowledgement still goes out at the old rate.
+            if let Err(_e) = write_packet(&mut tx, &Packet::BaudAck { baud: new_baud }).await {
+                host_log!(Error, "UART write failed");
+            }
+            if new_baud != link.baud() {
+                switch_baud(&mut tx, new_baud).await;
+                HOST_FRAME_SEEN.store(false, Ordering::Relaxed);
+                link.switch(new_baud, Instant::now().as_millis());
+            }
+        }
+
+        let now_ms = Instant::now().as_millis();
+        if HOST_FRAME_SEEN.swap(false, Ordering::Relaxed) && link.frame_seen(now_ms) {
+            host_log!(Info, "Baud rate {} confirmed by host", link.baud());
+        }
+        let baud = link.baud();
+        match link.check(now_ms) {
+            None => {}
+            Some(BaudFallback::Unconfirmed) => {
+                host_log!(Warn, "No frame from host at {} baud, falling back", baud);
+                switch_baud(&mut tx, DEFAULT_BAUD).await;
+            }
+            Some(BaudFallback::HostSilent) => {
+                host_log!(Warn, "Host silent at {} baud, falling back", baud);
+                switch_baud(&mut tx, DEFAULT_BAUD).await;
+            }
+        }
+    }
+}
+
+/// Switches the UART to a new baud rate once everything queued has been sent.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+async fn switch_baud(tx: &mut BufferedUartTx, baud: u32) {
+    if let Err(_e) = tx.flush().await {
+        host_log!(Error, "UART flush failed");
+    }
+    wait_for_tx_idle().await;
+    set_uart_baudrate(baud);
+    host_log!(Info, "UART switched to {} baud", baud);
+}
+
+/// Programs the UART0 baud rate divisors the same way the HAL does at initialization.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+fn set_uart_baudrate(baud: u32) {
+    let clk = embassy_rp::clocks::clk_peri_freq();
+    let divisor = 8 * clk / baud + 1;
+    let (ibrd, fbrd) = match divisor >> 7 {
+        0 => (1, 0),
+        ibrd if ibrd >= 65535 => (65535, 0),
+        ibrd => (ibrd, (divisor & 0x7F) >> 1),
+    };
+    let uart = pac::UART0;
+    uart.uartibrd().write_value(pac::uart::regs::Uartibrd(ibrd));
+    uart.uartfbrd().write_value(pac::uart::regs::Uartfbrd(fbrd));
+    // The divisors only take effect with the next write to the line control register.
+    uart.uartlcr_h().modify(|_| {});
+}
+
+/// Waits until the last byte has left the UART shift register. A flush only waits for the
+/// TX buffer to drain.
+#[cfg(any(feature = "rs485", not(any(feature = "modbus", feature = "polled"))))]
+async fn wait_for_tx_idle() {
+    while pac::UART0.uartfr().read().busy() {
+        embassy_time::Timer::after_micros(10).await;
     }
 }
 
@@ -145,45 +650,476 @@ async fn core1_task(encoders: Encoders) {
     // before taking the initial reading. This prevents spurious initial counts.
     embassy_time::Timer::after_millis(10).await;
 
-    let mut encoders = encoders.encoders.map(|e| e.into_standard_mode());
+    #[cfg(feature = "spi-target")]
+    let mut spi = encoders.spi;
+    let mut sampler = Sampler::new(encoders.pins, encoders.capture, EmbassyClock);
+
+    loop {
+        if let Ok(command) = CAPTURE_REQUEST.try_receive() {
+            info!("Edge capture started");
+            sampler.start_capture(command);
+        }
+        #[cfg(feature = "compare")]
+        while let Ok(command) = COMPARE_CONFIG.try_receive() {
+            sampler.configure_compare(command, &STATE, &mut Queues);
+        }
+
+        sampler.pass(&STATE, &mut Queues);
+
+        #[cfg(feature = "spi-target")]
+        spi.poll(register_counts, sampler.now_us());
+    }
+}
+
+/// Feeds the hardware watchdog only while the Core 1 sampling loop, the Core 0 main loop
+/// and, when streaming, the transmitter keep making progress, mirroring the counts into the
+/// watchdog scratch registers at every feed so they survive a watchdog reset.
+///
+/// Bus and Modbus servers only transmit when asked, so silence on their side is not a hang.
+#[embassy_executor::task]
+async fn supervisor(mut watchdog: Watchdog) {
+    const STREAMING: bool =
+        cfg!(not(any(feature = "modbus", feature = "rs485", feature = "polled")));
+    watchdog.start(WATCHDOG_TIMEOUT);
+    let mut last_core1 = STATE.heartbeat();
+    let mut last_main = MAIN_HEARTBEAT.load(Ordering::Relaxed);
+    let mut last_tx = TX_HEARTBEAT.load(Ordering::Relaxed);
+
+    loop {
+        embassy_time::Timer::after(LIVENESS_CHECK_PERIOD).await;
+
+        let core1 = STATE.heartbeat();
+        let main = MAIN_HEARTBEAT.load(Ordering::Relaxed);
+        let tx = TX_HEARTBEAT.load(Ordering::Relaxed);
+        let core1_alive = core1 != last_core1;
+        let main_alive = main != last_main;
+        let tx_alive = tx != last_tx || !STREAMING;
+        last_core1 = core1;
+        last_main = main;
+        last_tx = tx;
 
-    // The rotary-encoder-embedded crate's StandardMode initializes its internal
-    // history buffer asymmetrically ([0xFF, 2]). If the starting pin state is (Low, Low),
-    // the very first update() call interprets the history transition as an Anticlockwise movement.
-    // To 'prime' the history buffer, we perform a few dummy reads and discard their results 
-    // before we start accumulating real counts.
-    for _ in 0..4 {
-        for en in encoders.iter_mut() {
-            en.update();
+        if core1_alive && main_alive && tx_alive {
+            for (i, count) in STATE.counts().into_iter().enumerate().take(SCRATCH_COUNTS) {
+                watchdog.set_scratch(i, count as u32);
+            }
+            watchdog.feed();
+        } else {
+            defmt::warn!(
+                "Liveness check failed (core 1: {}, main: {}, tx: {}), withholding watchdog feed",
+                core1_alive,
+                main_alive,
+                tx_alive
+            );
         }
     }
+}
+
+/// Address of this board on an RS-485 bus or as a Modbus server, set at build time through
+/// the `ENCODER_ADDRESS` environment variable.
+#[cfg(any(feature = "modbus", feature = "rs485", feature = "polled"))]
+const DEVICE_ADDRESS: u8 = match option_env!("ENCODER_ADDRESS") {
+    Some(address) => parse_address(address),
+    None => 1,
+};
+
+#[cfg(any(feature = "modbus", feature = "rs485", feature = "polled"))]
+const fn parse_address(text: &str) -> u8 {
+    let digits = text.as_bytes();
+    let mut value = 0u32;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(digits[i].is_ascii_digit(), "ENCODER_ADDRESS must be a decimal number");
+        value = value * 10 + (digits[i] - b'0') as u32;
+        assert!(value <= 247, "ENCODER_ADDRESS must be between 1 and 247");
+        i += 1;
+    }
+    assert!(value >= 1, "ENCODER_ADDRESS must be between 1 and 247");
+    value as u8
+}
+
+/// Owns the UART transmitter on a shared bus. With the `rs485` feature it drives the DE/RE
+/// pin of the transceiver on GP0, which is only raised while a reply is being sent.
+#[cfg(any(feature = "modbus", feature = "rs485", feature = "polled"))]
+struct BusDriver {
+    #[cfg(feature = "rs485")]
+    driver_enable: Output<'static>,
+}
+
+#[cfg(any(feature = "modbus", feature = "rs485", feature = "polled"))]
+impl BusDriver {
+    /// Takes the bus before transmitting.
+    fn acquire(&mut self) {
+        #[cfg(feature = "rs485")]
+        self.driver_enable.set_high();
+    }
+
+    /// Waits until the last byte has left the UART and hands the bus back.
+    async fn release(&mut self, uart: &mut BufferedUart) {
+        if let Err(_e) = uart.flush().await {
+            host_log!(Error, "UART flush failed");
+        }
+        #[cfg(feature = "rs485")]
+        {
+            wait_for_tx_idle().await;
+            self.driver_enable.set_low();
+        }
+    }
+}
+
+/// Serializes a reply frame, addressed from this board when it sits on an RS-485 bus.
+#[cfg(all(any(feature = "rs485", feature = "polled"), not(feature = "modbus")))]
+fn reply_frame(packet: &Packet) -> String<BUFFER_SIZE> {
+    if cfg!(feature = "rs485") {
+        serialize_addressed_packet(DEVICE_ADDRESS, packet)
+    } else {
+        serialize_packet(packet)
+    }
+}
+
+/// Answers requests from the host instead of streaming.
+///
+/// `GET` and `POLL` are answered with a heartbeat if one is due (at most once per second),
+/// then any queued events, then the current counts, which mark the end of the reply. On an
+/// RS-485 bus, commands are only accepted when addressed to this board or broadcast, and
+/// only requests addressed to this board are answered.
+#[cfg(all(any(feature = "rs485", feature = "polled"), not(feature = "modbus")))]
+#[embassy_executor::task]
+async fn polled_server(mut uart: BufferedUart, mut bus: BusDriver, device_info: DeviceInfo) {
+    #[cfg(feature = "rs485")]
+    info!("RS-485 bus address {}", DEVICE_ADDRESS);
+    let mut line = LineBuffer::new();
+    let mut last_heartbeat: Option<Instant> = None;
 
     loop {
-        for (i, en) in encoders.iter_mut().enumerate() {
-            match en.update() {
-                Direction::Clockwise => {
-                    ENCODER_COUNTS[i].fetch_add(1, Ordering::SeqCst);
+        let mut buf = [0; 1];
+        if let Err(_e) = uart.read_exact(&mut buf).await {
+            host_log!(Error, "UART read failed");
+            embassy_time::Timer::after_millis(10).await;
+            continue;
+        }
+        let line = match line.push(buf[0]) {
+            Ok(Some(line)) => line,
+            Ok(None) => continue,
+            Err(LineOverflow) => {
+                host_log!(Warn, "RX line overflow, discarding");
+                continue;
+            }
+        };
+
+        let frame = parse_addressed_packet(&line);
+        let Some((address, packet)) = frame else {
+            host_log!(Warn, "Discarding malformed frame");
+            continue;
+        };
+        let is_request = matches!(packet, Packet::Poll | Packet::GetCounts);
+
+        #[cfg(feature = "rs485")]
+        {
+            // Unaddressed frames and other boards' traffic are not for us, and broadcasts are
+            // never answered.
+            let Some(address) = address else { continue };
+            if address != DEVICE_ADDRESS && address != BUS_BROADCAST_ADDRESS {
+                continue;
+            }
+            if is_request && address == BUS_BROADCAST_ADDRESS {
+                continue;
+            }
+        }
+        #[cfg(not(feature = "rs485"))]
+        let _ = address;
+
+        if !is_request {
+            handle_command(packet);
+            continue;
+        }
+
+        bus.acquire();
+        let now = Instant::now();
+        if last_heartbeat.is_none_or(|at| now - at >= Duration::from_secs(1)) {
+            last_heartbeat = Some(now);
+            let buf = reply_frame(&Packet::DeviceInfo(device_info));
+            if let Err(_e) = uart.write_all(buf.as_bytes()).await {
+                host_log!(Error, "UART write failed");
+            }
+        }
+        while let Some(packet) = Queues.next_event() {
+            let buf = reply_frame(&packet);
+            if let Err(_e) = uart.write_all(buf.as_bytes()).await {
+                host_log!(Error, "UART write failed");
+            }
+        }
+        while let Some(event) = Queues.next_latch() {
+            let buf = reply_frame(&Packet::Latch(event));
+            if let Err(_e) = uart.write_all(buf.as_bytes()).await {
+                host_log!(Error, "UART write failed");
+            }
+        }
+        while let Some(message) = Queues.next_log() {
+            let buf = reply_frame(&Packet::Log(message));
+            if let Err(_e) = uart.write_all(buf.as_bytes()).await {
+                host_log!(Error, "UART write failed");
+            }
+        }
+        if let Some(chunk) = Queues.next_chunk() {
+            let buf = reply_frame(&Packet::CaptureData(chunk));
+            if let Err(_e) = uart.write_all(buf.as_bytes()).await {
+                host_log!(Error, "UART write failed");
+            }
+        }
+        let data = STATE.sensor_data(MAIN_HEARTBEAT.load(Ordering::Relaxed), STATE.counts());
+        let buf = reply_frame(&Packet::SensorData(data));
+        if let Err(_e) = uart.write_all(buf.as_bytes()).await {
+            host_log!(Error, "UART write failed");
+        }
+        bus.release(&mut uart).await;
+    }
+}
+
+/// Silence on the line that terminates an RTU frame, fixed by the Modbus spec for baud
+/// rates above 19200.
+#[cfg(feature = "modbus")]
+const MODBUS_FRAME_GAP: Duration = Duration::from_micros(1750);
+
+/// Device state served to Modbus masters, backed by the shared counters.
+#[cfg(feature = "modbus")]
+#[derive(Debug, Clone, Copy)]
+struct ModbusRegisters {
+    address: u8,
+}
+
+#[cfg(feature = "modbus")]
+impl ModbusDevice for ModbusRegisters {
+    fn count(&self, encoder: usize) -> i32 {
+        STATE.count(encoder)
+    }
+
+    fn velocity(&self, encoder: usize) -> i32 {
+        STATE.velocities()[encoder]
+    }
+
+    fn set_count(&mut self, encoder: usize, count: i32) {
+        STATE.set_count(encoder, count);
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn set_address(&mut self, address: u8) {
+        info!("Modbus server address changed to {}", address);
+        self.address = address;
+    }
+}
+
+/// Serves Modbus RTU requests on the UART in place of the ASCII stream.
+///
+/// Frames are delimited by line silence: bytes are collected until no new byte arrives for
+/// `MODBUS_FRAME_GAP`, then the frame is handled and any reply sent.
+#[cfg(feature = "modbus")]
+#[embassy_executor::task]
+async fn modbus_server(mut uart: BufferedUart, mut bus: BusDriver) {
+    let mut registers = ModbusRegisters {
+        address: DEVICE_ADDRESS,
+    };
+    info!("Modbus RTU server at address {}", registers.address);
+    let mut frame: heapless::Vec<u8, MODBUS_MAX_FRAME> = heapless::Vec::new();
+    let mut response = [0u8; MODBUS_MAX_FRAME];
+
+    loop {
+        let mut buf = [0u8; 32];
+        let read = if frame.is_empty() {
+            Ok(uart.read(&mut buf).await)
+        } else {
+            embassy_time::with_timeout(MODBUS_FRAME_GAP, uart.read(&mut buf)).await
+        };
+
+        match read {
+            Ok(Ok(n)) => {
+                if frame.extend_from_slice(&buf[..n]).is_err() {
+                    defmt::warn!("Modbus frame too long, discarding");
+                    frame.clear();
                 }
-                Direction::Anticlockwise => {
-                    ENCODER_COUNTS[i].fetch_sub(1, Ordering::SeqCst);
+            }
+            Ok(Err(_e)) => {
+                defmt::error!("UART read failed");
+                frame.clear();
+            }
+            Err(_timeout) => {
+                if let Some(len) = handle_frame(&mut registers, &frame, &mut response) {
+                    bus.acquire();
+                    if let Err(_e) = uart.write_all(&response[..len]).await {
+                        defmt::error!("UART write failed");
+                    }
+                    bus.release(&mut uart).await;
+                }
+                frame.clear();
+            }
+        }
+    }
+}
+
+/// Serves the register map to an I2C controller.
+///
+/// The controller writes a register address, optionally followed by data, and reads from
+/// the last address written. The image is refreshed from the live counters on every access.
+#[cfg(feature = "i2c-target")]
+#[embassy_executor::task]
+async fn i2c_target(mut target: I2cSlave<'static, I2C0>, counts_restored: bool) {
+    info!("I2C target listening at {:#04x}", I2C_TARGET_ADDRESS);
+    let mut map = RegisterMap::new(PROTOCOL_VERSION);
+    map.set_status(STATUS_COUNTS_RESTORED, counts_restored);
+    let mut address = 0u8;
+
+    loop {
+        let mut buf = [0u8; 8];
+        let command = match target.listen(&mut buf).await {
+            Ok(command) => command,
+            Err(_e) => {
+                defmt::warn!("I2C target error");
+                continue;
+            }
+        };
+
+        let uptime_ms = Instant::now().as_millis() as u32;
+        let counts = register_counts();
+        let velocities = STATE.velocities();
+        map.update_live(&counts, &core::array::from_fn(|i| velocities[i]), uptime_ms);
+
+        let written = match command {
+            Command::Write(len) | Command::WriteRead(len) => &buf[..len],
+            Command::Read | Command::GeneralCall(_) => &[],
+        };
+        if let Some((&register, data)) = written.split_first() {
+            address = register;
+            if register == REG_COMMAND {
+                for &value in data {
+                    match RegisterCommand::from_byte(value) {
+                        Some(RegisterCommand::LatchSnapshot) => {
+                            map.latch_snapshot(&counts, uptime_ms);
+                        }
+                        Some(RegisterCommand::ResetAll) => {
+                            for i in 0..ENCODER_COUNT {
+                                STATE.set_count(i, 0);
+                            }
+                        }
+                        Some(RegisterCommand::Reset(id)) => {
+                            STATE.set_count(usize::from(id), 0);
+                        }
+                        None => defmt::warn!("Unknown I2C command {:#04x}", value),
+                    }
                 }
-                Direction::None => {}
             }
         }
+
+        if matches!(command, Command::Read | Command::WriteRead(_))
+            && let Err(_e) = target.respond_and_fill(map.read_from(address), 0xFF).await
+        {
+            defmt::warn!("I2C read aborted");
+        }
     }
 }
 
-/// Reads data from the UART, currently only used to drain the receive buffer to prevent overflow.
+/// Reads newline-terminated command frames from the UART and dispatches them.
+#[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
 #[embassy_executor::task]
 async fn reader(mut rx: BufferedUartRx) {
     info!("Reading...");
+    let mut line = LineBuffer::new();
     loop {
         let mut buf = [0; 1];
         if let Err(_e) = rx.read_exact(&mut buf).await {
-            defmt::error!("UART read failed");
+            host_log!(Error, "UART read failed");
             embassy_time::Timer::after_millis(10).await;
             continue;
         }
-        info!("RX {:?}", buf);
+        match line.push(buf[0]) {
+            Ok(Some(line)) => match parse_packet(&line) {
+                Some(packet) => {
+                    HOST_FRAME_SEEN.store(true, Ordering::Relaxed);
+                    handle_command(packet);
+                }
+                None => host_log!(Warn, "Discarding malformed frame: {}", line.as_str()),
+            },
+            Ok(None) => {}
+            Err(LineOverflow) => host_log!(Warn, "RX line overflow, discarding"),
+        }
+    }
+}
+
+/// Applies a single command received from the host and carries out what is left for the
+/// firmware to do.
+#[cfg(not(feature = "modbus"))]
+fn handle_command(packet: Packet) {
+    let action = apply_command(&STATE, packet);
+    match packet {
+        Packet::Reset(cmd) => host_log!(Info, "Reset encoder {}", cmd.encoder_id),
+        Packet::SetCount(cmd) => {
+            host_log!(Info, "Set encoder {} to {}", cmd.encoder_id, cmd.count)
+        }
+        Packet::Home(cmd) => host_log!(Info, "Home encoder {} on next index", cmd.encoder_id),
+        Packet::SetTriggerEdge(edge) => host_log!(Info, "Trigger edge set to {}", edge.code()),
+        Packet::SetLogLevel(level) => {
+            host_log!(Info, "Log level set to {}", level.map_or(0, |l| l.code()))
+        }
+        Packet::SetModulo(cmd) => host_log!(
+            Info,
+            "Encoder {} reports modulo {} counts",
+            cmd.encoder_id,
+            cmd.counts_per_rev
+        ),
+        Packet::SetChannel(config) => host_log!(
+            Info,
+            "Encoder {} enabled {} inverted {} named {}",
+            config.encoder_id,
+            config.enabled,
+            config.inverted,
+            config.name()
+        ),
+        _ => {}
+    }
+
+    match action {
+        Action::None => {}
+        Action::Reply(reply) => {
+            if OUTBOX.try_send(reply).is_err() {
+                defmt::warn!("Outbox full, dropping reply");
+            }
+        }
+        Action::EnterBootloader => {
+            info!("Rebooting into USB bootloader");
+            // Leave the activity LED alone and expose both the mass-storage and PICOBOOT interfaces.
+            embassy_rp::rom_data::reset_to_usb_boot(0, 0);
+        }
+        #[cfg(not(any(feature = "modbus", feature = "rs485", feature = "polled")))]
+        Action::SetBaud(baud) => {
+            host_log!(Info, "Host proposes {} baud", baud);
+            PENDING_BAUD.store(baud, Ordering::Relaxed);
+        }
+        Action::StartCapture(cmd) => {
+            host_log!(
+                Info,
+                "Capture requested: {} steps, {} ms",
+                cmd.max_samples,
+                cmd.duration_ms
+            );
+            if CAPTURE_REQUEST.try_send(cmd).is_err() {
+                host_log!(Warn, "Capture already pending, dropping request");
+            }
+        }
+        #[cfg(feature = "compare")]
+        Action::SetCompare(cmd) => {
+            host_log!(
+                Info,
+                "Compare encoder {} on output {}: {} to {}",
+                cmd.encoder_id,
+                cmd.output,
+                cmd.low,
+                cmd.high
+            );
+            if COMPARE_CONFIG.try_send(cmd).is_err() {
+                host_log!(Warn, "Compare queue full, dropping command");
+            }
+        }
+        _ => host_log!(Warn, "Ignoring unexpected packet from host"),
     }
 }
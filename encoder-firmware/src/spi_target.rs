@@ -0,0 +1,147 @@
+//! SPI target (slave) interface that clocks out a snapshot of all counters per chip select.
+//!
+//! embassy-rp only drives the SPI block as a controller, so the PL022 is configured for
+//! target mode through the PAC. The interface is polled from the Core 1 sampling loop:
+//! that is the only place that can latch all counts between two updates, and its short
+//! loop time keeps the TX FIFO topped up.
+
+use embassy_rp::pac;
+use embassy_rp::peripherals::{PIN_18, PIN_19, PIN_20, PIN_21, SPI0};
+use embassy_rp::Peri;
+use encoder_protocol::{
+    SnapshotFrame, REGISTER_ENCODERS, SNAPSHOT_FRAME_SIZE, SNAPSHOT_HEADER_SIZE,
+};
+
+const SCK_PIN: usize = 18;
+const TX_PIN: usize = 19;
+const RX_PIN: usize = 20;
+const CS_PIN: usize = 21;
+
+/// IO_BANK0 function select for the SPI blocks.
+const FUNCSEL_SPI: u8 = 1;
+
+/// SPI0 in target mode on GP18 (SCK), GP19 (MISO), GP20 (MOSI) and GP21 (CS).
+pub struct SpiTarget {
+    _spi: Peri<'static, SPI0>,
+    _pins: (
+        Peri<'static, PIN_18>,
+        Peri<'static, PIN_19>,
+        Peri<'static, PIN_20>,
+        Peri<'static, PIN_21>,
+    ),
+    frame: [u8; SNAPSHOT_FRAME_SIZE],
+    /// Index of the next frame byte to queue.
+    next: usize,
+    selected: bool,
+    seq: u32,
+}
+
+impl core::fmt::Debug for SpiTarget {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SpiTarget")
+            .field("seq", &self.seq)
+            .field("selected", &self.selected)
+            .finish()
+    }
+}
+
+impl SpiTarget {
+    pub fn new(
+        spi: Peri<'static, SPI0>,
+        sck: Peri<'static, PIN_18>,
+        tx: Peri<'static, PIN_19>,
+        rx: Peri<'static, PIN_20>,
+        cs: Peri<'static, PIN_21>,
+    ) -> Self {
+        for pin in [SCK_PIN, TX_PIN, RX_PIN, CS_PIN] {
+            pac::PADS_BANK0.gpio(pin).write(|w| {
+                w.set_ie(true);
+                w.set_schmitt(true);
+                // Keep the target deselected while the controller is disconnected.
+                w.set_pue(pin == CS_PIN);
+            });
+            pac::IO_BANK0.gpio(pin).ctrl().write(|w| w.set_funcsel(FUNCSEL_SPI));
+        }
+
+        let mut target = Self {
+            _spi: spi,
+            _pins: (sck, tx, rx, cs),
+            frame: [0; SNAPSHOT_FRAME_SIZE],
+            next: SNAPSHOT_HEADER_SIZE,
+            selected: false,
+            seq: 0,
+        };
+        target.restart();
+        target
+    }
+
+    /// Resets the SPI block, which is the only way to empty its TX FIFO, and queues the
+    /// constant frame header for the next transfer.
+    fn restart(&mut self) {
+        pac::RESETS.reset().modify(|w| w.set_spi0(true));
+        pac::RESETS.reset().modify(|w| w.set_spi0(false));
+        while !pac::RESETS.reset_done().read().spi0() {}
+
+        let spi = pac::SPI0;
+        spi.cr0().write(|w| {
+            // 8-bit Motorola frames in mode 1. With SPH set the controller may hold CS low
+            // for the whole frame; with SPH clear the PL022 needs CS toggled per byte.
+            w.set_dss(0b0111);
+            w.set_frf(0);
+            w.set_spo(false);
+            w.set_sph(true);
+        });
+        spi.cpsr().write(|w| w.set_cpsdvsr(2));
+        spi.cr1().write(|w| {
+            w.set_ms(true);
+            w.set_sse(true);
+        });
+
+        for byte in SnapshotFrame::HEADER {
+            spi.dr().write(|w| w.set_data(byte.into()));
+        }
+        self.next = SNAPSHOT_HEADER_SIZE;
+    }
+
+    /// Latches a snapshot on chip select and keeps the TX FIFO fed. Must be called on every
+    /// pass of the sampling loop, after the counters were updated.
+    pub fn poll(&mut self, counts: impl FnOnce() -> [i32; REGISTER_ENCODERS], now_us: u32) {
+        let spi = pac::SPI0;
+        let selected = !pac::IO_BANK0.gpio(CS_PIN).status().read().infrompad();
+
+        if selected && !self.selected {
+            self.seq = self.seq.wrapping_add(1);
+            let snapshot = SnapshotFrame {
+                seq: self.seq,
+                timestamp_us: now_us,
+                counts: counts(),
+            };
+            self.frame = snapshot.to_bytes();
+        } else if !selected && self.selected {
+            // A transfer shorter than a frame leaves bytes queued that would shift every
+            // following frame.
+            if self.next < SNAPSHOT_FRAME_SIZE || !spi.sr().read().tfe() {
+                defmt::warn!("SPI transfer aborted after {} bytes", self.next);
+                self.restart();
+            } else {
+                for byte in SnapshotFrame::HEADER {
+                    spi.dr().write(|w| w.set_data(byte.into()));
+                }
+                self.next = SNAPSHOT_HEADER_SIZE;
+            }
+        }
+        self.selected = selected;
+
+        // Bytes from the controller are ignored, but must be drained to avoid an overrun.
+        while spi.sr().read().rne() {
+            let _ = spi.dr().read();
+        }
+
+        if selected {
+            while self.next < SNAPSHOT_FRAME_SIZE && spi.sr().read().tnf() {
+                spi.dr().write(|w| w.set_data(self.frame[self.next].into()));
+                self.next += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// encoder-sim/src/board.rs
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use encoder_core::{Clock, DeviceState, EventSink, EventSource, Sampler, SamplerPins};
+use encoder_protocol::{
+    CAPTURE_CAPACITY, CaptureChunk, CaptureCommand, CompareCommand, EdgeSample, LatchEvent,
+    LogMessage, MAX_ENCODERS, Packet, SENSOR_PERIOD_MS,
+};
+
+/// Sampling passes per sensor period while no channel needs more to step through its motion.
+const MIN_PASSES_PER_CYCLE: u64 = 10;
+/// Upper bound of quadrature edges per channel and sensor period. Faster motion lags behind
+/// and catches up later.
+const MAX_EDGES_PER_CYCLE: u64 = 10_000;
+
+/// A and B levels of the four quadrature states, in clockwise order from the pulled-up rest.
+const QUADRATURE: [(bool, bool); 4] = [(true, true), (false, true), (false, false), (true, false)];
+
+/// Simulated time, advanced by the board between sampling passes.
+#[derive(Debug, Default)]
+struct SimClock(Cell<u64>);
+
+impl Clock for SimClock {
+    fn now_us(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// An encoder input driven by the simulation.
+#[derive(Debug, Clone, Copy)]
+struct SimInput<'a>(&'a Cell<bool>);
+
+impl ErrorType for SimInput<'_> {
+    type Error = Infallible;
+}
+
+impl InputPin for SimInput<'_> {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.0.get())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.0.get())
+    }
+}
+
+/// Stands in for the compare outputs, which have nothing to drive here.
+#[derive(Debug, Clone, Copy)]
+struct Unconnected;
+
+impl ErrorType for Unconnected {
+    type Error = Infallible;
+}
+
+impl OutputPin for Unconnected {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Events waiting for the next transmit cycle, in place of the firmware's channels.
+#[derive(Debug, Default)]
+pub struct Queues {
+    events: VecDeque<Packet>,
+    latches: VecDeque<LatchEvent>,
+    logs: VecDeque<LogMessage>,
+    chunks: VecDeque<CaptureChunk>,
+}
+
+impl Queues {
+    /// Queues a reply to a host command.
+    pub fn reply(&mut self, packet: Packet) {
+        self.events.push_back(packet);
+    }
+
+    /// Queues a log message for the host.
+    pub fn log(&mut self, message: LogMessage) {
+        self.logs.push_back(message);
+    }
+}
+
+impl EventSink for Queues {
+    fn send_event(&mut self, packet: Packet) {
+        self.events.push_back(packet);
+    }
+
+    fn send_latch(&mut self, event: LatchEvent) {
+        self.latches.push_back(event);
+    }
+
+    fn can_send_chunk(&self) -> bool {
+        // Like the firmware, hand out no more than one period's worth ahead of the link.
+        self.chunks.is_empty()
+    }
+
+    fn send_chunk(&mut self, chunk: CaptureChunk) {
+        self.chunks.push_back(chunk);
+    }
+}
+
+impl EventSource for Queues {
+    fn next_event(&mut self) -> Option<Packet> {
+        self.events.pop_front()
+    }
+
+    fn next_latch(&mut self) -> Option<LatchEvent> {
+        self.latches.pop_front()
+    }
+
+    fn next_log(&mut self) -> Option<LogMessage> {
+        self.logs.pop_front()
+    }
+
+    fn next_chunk(&mut self) -> Option<CaptureChunk> {
+        self.chunks.pop_front()
+    }
+}
+
+/// Signal levels and time of the simulated board.
+#[derive(Debug)]
+pub struct Signals {
+    a: [Cell<bool>; MAX_ENCODERS],
+    b: [Cell<bool>; MAX_ENCODERS],
+    clock: SimClock,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self {
+            a: core::array::from_fn(|_| Cell::new(true)),
+            b: core::array::from_fn(|_| Cell::new(true)),
+            clock: SimClock::default(),
+        }
+    }
+}
+
+type SimSampler<'a> = Sampler<'a, SimInput<'a>, Unconnected, &'a SimClock>;
+
+/// The sampling side of the firmware, fed with quadrature signals generated from the
+/// channels' positions instead of GPIO inputs.
+#[derive(Debug)]
+pub struct Board<'a> {
+    signals: &'a Signals,
+    sampler: SimSampler<'a>,
+    /// Quadrature edges each channel has moved since the start, four per count.
+    edges: [i64; MAX_ENCODERS],
+}
+
+impl<'a> Board<'a> {
+    /// A board with the first `encoder_count` channels connected.
+    pub fn new(
+        signals: &'a Signals,
+        capture_buffer: &'a mut [EdgeSample; CAPTURE_CAPACITY],
+        encoder_count: usize,
+    ) -> Self {
+        let pins = SamplerPins {
+            encoders: core::array::from_fn(|i| {
+                (i < encoder_count).then(|| (SimInput(&signals.a[i]), SimInput(&signals.b[i])))
+            }),
+            buttons: [None; MAX_ENCODERS],
+            index: [None; MAX_ENCODERS],
+            trigger: None,
+            compare_outputs: None,
+        };
+        Self {
+            signals,
+            sampler: Sampler::new(pins, capture_buffer, &signals.clock),
+            edges: [0; MAX_ENCODERS],
+        }
+    }
+
+    /// The channels with an index input; the simulated encoders have none.
+    pub fn index_inputs(&self) -> u16 {
+        self.sampler.index_inputs()
+    }
+
+    pub fn start_capture(&mut self, command: CaptureCommand) {
+        self.sampler.start_capture(command);
+    }
+
+    pub fn configure_compare(
+        &mut self,
+        command: CompareCommand,
+        state: &DeviceState,
+        queues: &mut Queues,
+    ) {
+        self.sampler.configure_compare(command, state, queues);
+    }
+
+    /// Runs the sampling passes of one sensor period while moving every channel to its
+    /// target position in counts, with the steps spread evenly over the period.
+    pub fn run_cycle(
+        &mut self,
+        targets: &[f64; MAX_ENCODERS],
+        state: &DeviceState,
+        queues: &mut Queues,
+    ) {
+        let start = self.edges;
+        let deltas: [i64; MAX_ENCODERS] = core::array::from_fn(|i| {
+            let limit = MAX_EDGES_PER_CYCLE as i64;
+            ((targets[i] * 4.0).round() as i64 - start[i]).clamp(-limit, limit)
+        });
+        let passes = deltas
+            .iter()
+            .map(|d| d.unsigned_abs())
+            .max()
+            .unwrap_or_default()
+            .max(MIN_PASSES_PER_CYCLE);
+
+        let period_us = u64::from(SENSOR_PERIOD_MS) * 1000;
+        let cycle_start = self.signals.clock.now_us();
+        for pass in 1..=passes {
+            for (i, delta) in deltas.iter().enumerate() {
+                // At most one edge per pass, so the decoder sees every state.
+                self.edges[i] = start[i] + delta * pass as i64 / passes as i64;
+                let (a, b) = QUADRATURE[self.edges[i].rem_euclid(4) as usize];
+                self.signals.a[i].set(a);
+                self.signals.b[i].set(b);
+            }
+            self.signals
+                .clock
+                .0
+                .set(cycle_start + period_us * pass / passes);
+            self.sampler.pass(state, queues);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder_protocol::DEFAULT_ENCODERS;
+
+    #[test]
+    fn test_counts_follow_the_targets() {
+        let signals = Signals::default();
+        let mut capture = [EdgeSample::default(); CAPTURE_CAPACITY];
+        let mut board = Board::new(&signals, &mut capture, 10);
+        let state = DeviceState::with_encoders(10);
+        let mut queues = Queues::default();
+
+        let mut targets = [0.0; MAX_ENCODERS];
+        targets[0] = 25.0;
+        targets[3] = -7.2;
+        targets[9] = 3.0;
+        // Channels the board does not have stay put.
+        targets[10] = 9.0;
+        board.run_cycle(&targets, &state, &mut queues);
+        assert_eq!(state.counts(), [25, 0, 0, -7, 0, 0, 0, 0, 0, 3, 0, 0]);
+
+        // A quarter count forward and back again leaves the count where it was.
+        targets[0] = 25.25;
+        board.run_cycle(&targets, &state, &mut queues);
+        targets[0] = 25.0;
+        board.run_cycle(&targets, &state, &mut queues);
+        assert_eq!(state.count(0), 25);
+        assert_eq!(state.diagnostics(0).illegal_transitions, 0);
+
+        // A host command moves the counter without moving the shaft.
+        state.set_count(3, 100);
+        targets[3] = -8.0;
+        board.run_cycle(&targets, &state, &mut queues);
+        assert_eq!(state.count(3), 99);
+        assert!(state.heartbeat() >= 4 * MIN_PASSES_PER_CYCLE as u32);
+    }
+
+    #[test]
+    fn test_fast_motion_is_limited_per_cycle() {
+        let signals = Signals::default();
+        let mut capture = [EdgeSample::default(); CAPTURE_CAPACITY];
+        let mut board = Board::new(&signals, &mut capture, DEFAULT_ENCODERS);
+        let state = DeviceState::new();
+        let mut queues = Queues::default();
+
+        let mut targets = [0.0; MAX_ENCODERS];
+        targets[5] = 5000.0;
+        board.run_cycle(&targets, &state, &mut queues);
+        assert_eq!(state.count(5), MAX_EDGES_PER_CYCLE as i32 / 4);
+        board.run_cycle(&targets, &state, &mut queues);
+        assert_eq!(state.count(5), 5000);
+    }
+}
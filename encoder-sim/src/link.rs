@@ -0,0 +1,124 @@
+// encoder-sim/src/link.rs
+
+use std::io::{self, Read as _};
+use std::time::Duration;
+
+use embedded_io_async::{ErrorType, Write};
+use serialport::{ClearBuffer, SerialPort, TTYPort};
+
+use crate::profile::Rng;
+
+/// The pseudo-terminal standing in for the board's UART. Host software opens the slave side
+/// by its path like a serial adapter.
+#[derive(Debug)]
+pub struct Pty {
+    master: TTYPort,
+    /// Held open so the master keeps working while no host is connected.
+    slave: TTYPort,
+}
+
+impl Pty {
+    pub fn open() -> serialport::Result<Self> {
+        let (mut master, slave) = TTYPort::pair()?;
+        master.set_timeout(Duration::ZERO)?;
+        Ok(Self { master, slave })
+    }
+
+    /// Path of the slave side, for example `/dev/pts/3`.
+    pub fn path(&self) -> Option<String> {
+        self.slave.name()
+    }
+
+    /// Reads whatever the host has sent, without waiting.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.master.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl io::Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.master.write(buf) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                // Nobody reads the other side. A UART loses its output in that case, so
+                // discard what the host has not picked up instead of stalling the stream.
+                self.slave.clear(ClearBuffer::Input)?;
+                self.master.write(buf)
+            }
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The transmit side of the link, dropping each byte with a fixed probability to exercise
+/// the host's handling of corrupted frames.
+#[derive(Debug)]
+pub struct LossyLink<W> {
+    inner: W,
+    drop_rate: f64,
+    rng: Rng,
+}
+
+impl<W: io::Write> LossyLink<W> {
+    pub fn new(inner: W, drop_rate: f64, rng: Rng) -> Self {
+        Self {
+            inner,
+            drop_rate,
+            rng,
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W> ErrorType for LossyLink<W> {
+    type Error = io::Error;
+}
+
+impl<W: io::Write> Write for LossyLink<W> {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.drop_rate <= 0.0 {
+            self.inner.write_all(buf)?;
+            return Ok(buf.len());
+        }
+        let kept: Vec<u8> = buf
+            .iter()
+            .copied()
+            .filter(|_| !self.rng.chance(self.drop_rate))
+            .collect();
+        self.inner.write_all(&kept)?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn test_lossy_link_drops_bytes_at_the_given_rate() {
+        let data = [b'x'; 10_000];
+
+        let mut lossless = LossyLink::new(Vec::new(), 0.0, Rng::new(1));
+        block_on(lossless.write_all(&data)).unwrap();
+        assert_eq!(lossless.inner_mut().len(), data.len());
+
+        let mut lossy = LossyLink::new(Vec::new(), 0.1, Rng::new(1));
+        block_on(lossy.write_all(&data)).unwrap();
+        let dropped = data.len() - lossy.inner_mut().len();
+        assert!((800..1200).contains(&dropped));
+    }
+}
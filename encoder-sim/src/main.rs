@@ -0,0 +1,426 @@
+//! Emulates the RP2040 encoder board on a pseudo-terminal, so host software can be developed
+//! and tested without a Pico attached.
+//!
+//! The simulator runs the firmware's own sampling, command handling and stream scheduling
+//! from `encoder-core`, fed with quadrature signals generated from scripted motion profiles,
+//! and streams on the PTY printed at startup just like the board does on its UART.
+//!
+//! Pseudo-terminals only exist on Unix. Elsewhere the binary only reports that, and the
+//! board logic is still built so the workspace compiles.
+
+#![cfg_attr(not(unix), allow(dead_code, unused_imports))]
+
+mod board;
+#[cfg(unix)]
+mod link;
+mod profile;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::pin::pin;
+use std::process::ExitCode;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use encoder_core::{
+    Action, BaudFallback, BaudLink, DeviceState, LineBuffer, LineOverflow, Snapshot, apply_command,
+    transmit_cycle,
+};
+use encoder_protocol::{
+    CAPTURE_CAPACITY, DEFAULT_ENCODERS, DeviceInfo, EdgeSample, LogLevel, MAX_ENCODERS,
+    PROTOCOL_VERSION, Packet, ResetReason, SENSOR_PERIOD_MS, parse_packet,
+};
+
+use crate::board::{Board, Queues, Signals};
+#[cfg(unix)]
+use crate::link::{LossyLink, Pty};
+use crate::profile::{Profile, Rng};
+
+const USAGE: &str = "\
+Usage: encoder-sim [OPTIONS] [<CHANNEL>=<PROFILE>...]
+
+Emulates the encoder board on a pseudo-terminal and prints its path.
+
+Profiles are terms joined by '+', for example 0=const:200 1=sine:400:2+noise:1.5:
+  const:<counts/s>              constant speed
+  sine:<amplitude>:<period s>   swinging around zero
+  walk:<max counts/s>           random walk
+  noise:<amplitude>             random jitter
+  idle                          standing still (the default)
+
+Options:
+  --encoders <N>  Number of channels on the board, up to 12 (default 8)
+  --link <PATH>   Also make the PTY available as a symlink at PATH
+  --drop <RATE>   Drop each transmitted byte with probability RATE (0 to 1)
+  --seed <SEED>   Seed for the random terms, session ID and dropped bytes
+  --help          Show this message";
+
+/// Serial number reported in the heartbeat, "SIM" in ASCII.
+const SIM_SERIAL_NUMBER: u64 = 0x0053_494D;
+
+#[derive(Debug, Default)]
+struct Options {
+    encoders: usize,
+    profiles: [Profile; MAX_ENCODERS],
+    link: Option<PathBuf>,
+    drop_rate: f64,
+    seed: Option<u64>,
+}
+
+/// Parses the command line; `Ok(None)` asks for the usage.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        encoders: DEFAULT_ENCODERS,
+        ..Options::default()
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(None),
+            "--encoders" => {
+                let encoders = value("--encoders")?;
+                options.encoders = encoders
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=MAX_ENCODERS).contains(n))
+                    .ok_or_else(|| format!("invalid number of encoders {encoders:?}"))?;
+            }
+            "--link" => options.link = Some(value("--link")?.into()),
+            "--drop" => {
+                let rate = value("--drop")?;
+                options.drop_rate = rate
+                    .parse()
+                    .ok()
+                    .filter(|r| (0.0..=1.0).contains(r))
+                    .ok_or_else(|| format!("invalid drop rate {rate:?}"))?;
+            }
+            "--seed" => {
+                let seed = value("--seed")?;
+                options.seed = Some(seed.parse().map_err(|_| format!("invalid seed {seed:?}"))?);
+            }
+            _ => {
+                let (channel, profile) = arg
+                    .split_once('=')
+                    .ok_or_else(|| format!("unexpected argument {arg:?}"))?;
+                let slot = channel
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| options.profiles.get_mut(i))
+                    .ok_or_else(|| format!("invalid channel {channel:?}"))?;
+                *slot = profile.parse()?;
+            }
+        }
+    }
+    if let Some(channel) = options.profiles[options.encoders..]
+        .iter()
+        .position(|p| *p != Profile::default())
+    {
+        return Err(format!(
+            "channel {} is not on a board with {} encoders",
+            options.encoders + channel,
+            options.encoders
+        ));
+    }
+    Ok(Some(options))
+}
+
+/// Runs a future that never waits, as writing to the PTY completes immediately.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() -> ExitCode {
+    eprintln!("encoder-sim needs a Unix pseudo-terminal and does not run on this platform");
+    ExitCode::FAILURE
+}
+
+#[cfg(unix)]
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let pty = match Pty::open() {
+        Ok(pty) => pty,
+        Err(e) => {
+            eprintln!("Failed to open a pseudo-terminal: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let path = pty.path().unwrap_or_default();
+    if let Some(link) = &options.link {
+        // Replace the link left behind by a previous run.
+        let _ = std::fs::remove_file(link);
+        if let Err(e) = std::os::unix::fs::symlink(&path, link) {
+            eprintln!("Failed to link {} to {path}: {e}", link.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    println!("Simulating encoder board on {path}");
+
+    match run(options, pty) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("PTY failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the board until the PTY fails, one sensor period per main loop cycle like the
+/// firmware.
+#[cfg(unix)]
+fn run(mut options: Options, pty: Pty) -> io::Result<()> {
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |t| t.as_nanos() as u64)
+    });
+    let mut rng = Rng::new(seed);
+
+    let signals = Signals::default();
+    let mut capture = Box::new([EdgeSample::default(); CAPTURE_CAPACITY]);
+    let mut board = Board::new(&signals, &mut capture, options.encoders);
+    let state = DeviceState::with_encoders(options.encoders);
+    state.set_index_inputs(board.index_inputs());
+
+    let device_info = DeviceInfo {
+        session_id: rng.next_u32(),
+        reset_reason: ResetReason::PowerOn,
+        protocol_version: PROTOCOL_VERSION,
+        counts_restored: false,
+        serial_number: SIM_SERIAL_NUMBER,
+        encoder_count: options.encoders as u8,
+        index_inputs: board.index_inputs(),
+    };
+    let mut link = LossyLink::new(pty, options.drop_rate, Rng::new(rng.next_u64()));
+
+    let mut queues = Queues::default();
+    let mut line = LineBuffer::new();
+    let mut baud = BaudLink::new();
+
+    let period = Duration::from_millis(SENSOR_PERIOD_MS.into());
+    let start = Instant::now();
+    let mut previous_counts = [0; MAX_ENCODERS];
+    let mut previous = None;
+
+    for cycle in 0u64.. {
+        let now_ms = cycle * u64::from(SENSOR_PERIOD_MS);
+        let mut buf = [0; 64];
+        loop {
+            let n = link.inner_mut().read_available(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            for &byte in &buf[..n] {
+                match line.push(byte) {
+                    Ok(Some(line)) => match parse_packet(&line) {
+                        Some(packet) => {
+                            if baud.frame_seen(now_ms) {
+                                log(
+                                    &state,
+                                    &mut queues,
+                                    LogLevel::Info,
+                                    format_args!("Baud rate {} confirmed by host", baud.baud()),
+                                );
+                            }
+                            handle_command(
+                                packet,
+                                &state,
+                                &mut board,
+                                &mut queues,
+                                &mut baud,
+                                now_ms,
+                            )
+                        }
+                        None => log(
+                            &state,
+                            &mut queues,
+                            LogLevel::Warn,
+                            format_args!("Discarding malformed frame: {line}"),
+                        ),
+                    },
+                    Ok(None) => {}
+                    Err(LineOverflow) => log(
+                        &state,
+                        &mut queues,
+                        LogLevel::Warn,
+                        format_args!("RX line overflow, discarding"),
+                    ),
+                }
+            }
+        }
+
+        check_baud(&mut baud, now_ms, &state, &mut queues);
+
+        let time = cycle as f64 * period.as_secs_f64();
+        let targets = options
+            .profiles
+            .each_mut()
+            .map(|p| p.position(time, period.as_secs_f64(), &mut rng));
+        board.run_cycle(&targets, &state, &mut queues);
+
+        let sequence = cycle as u32;
+        let counts = state.counts();
+        state.update_velocities(&previous_counts, &counts);
+        previous_counts = counts;
+        let snapshot = Snapshot { sequence, counts };
+        block_on(transmit_cycle(
+            &mut link,
+            previous,
+            snapshot,
+            &device_info,
+            &state,
+            &mut queues,
+        ))?;
+        previous = Some(sequence);
+
+        let next = start + Duration::from_millis(u64::from(SENSOR_PERIOD_MS) * (cycle + 1));
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+    Ok(())
+}
+
+/// Applies a single command received from the host and carries out what is left for the
+/// firmware to do.
+fn handle_command(
+    packet: Packet,
+    state: &DeviceState,
+    board: &mut Board<'_>,
+    queues: &mut Queues,
+    baud: &mut BaudLink,
+    now_ms: u64,
+) {
+    match apply_command(state, packet) {
+        Action::None => {}
+        Action::Reply(reply) => queues.reply(reply),
+        Action::EnterBootloader => log(
+            state,
+            queues,
+            LogLevel::Warn,
+            format_args!("Ignoring bootloader request, there is no flash to update"),
+        ),
+        Action::SetBaud(requested) => {
+            // The PTY has no line rate, so the switch always succeeds, but the acknowledgement
+            // and the fallbacks follow the firmware's rules.
+            let new_baud = baud.resolve(requested);
+            queues.reply(Packet::BaudAck { baud: new_baud });
+            if new_baud != baud.baud() {
+                baud.switch(new_baud, now_ms);
+            }
+        }
+        Action::StartCapture(command) => board.start_capture(command),
+        Action::SetCompare(command) => board.configure_compare(command, state, queues),
+        Action::Ignored => log(
+            state,
+            queues,
+            LogLevel::Warn,
+            format_args!("Ignoring unexpected packet from host"),
+        ),
+    }
+}
+
+/// Returns to the default baud rate when the host missed its deadline, like the firmware's
+/// transmitter.
+fn check_baud(baud: &mut BaudLink, now_ms: u64, state: &DeviceState, queues: &mut Queues) {
+    let rate = baud.baud();
+    match baud.check(now_ms) {
+        None => {}
+        Some(BaudFallback::Unconfirmed) => log(
+            state,
+            queues,
+            LogLevel::Warn,
+            format_args!("No frame from host at {rate} baud, falling back"),
+        ),
+        Some(BaudFallback::HostSilent) => log(
+            state,
+            queues,
+            LogLevel::Warn,
+            format_args!("Host silent at {rate} baud, falling back"),
+        ),
+    }
+}
+
+/// Prints a message and forwards it to the host like the firmware does, if its level is
+/// enabled.
+fn log(state: &DeviceState, queues: &mut Queues, level: LogLevel, args: fmt::Arguments<'_>) {
+    eprintln!("{args}");
+    if let Some(message) = state.log_message(level, args) {
+        queues.log(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoder_core::EventSource;
+    use encoder_protocol::DEFAULT_BAUD;
+
+    fn args(line: &str) -> Result<Option<Options>, String> {
+        parse_args(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = args("--drop 0.01 0=const:200 7=sine:50:1+noise:1 --seed 3")
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.drop_rate, 0.01);
+        assert_eq!(options.seed, Some(3));
+        assert_eq!(options.profiles[0], "const:200".parse().unwrap());
+        assert_eq!(options.profiles[1], Profile::default());
+        assert_eq!(options.profiles[7], "sine:50:1+noise:1".parse().unwrap());
+
+        assert!(args("--help").unwrap().is_none());
+        assert!(args("8=const:1").is_err());
+        let options = args("11=const:1 --encoders 12").unwrap().unwrap();
+        assert_eq!(options.encoders, 12);
+        assert!(args("12=const:1 --encoders 12").is_err());
+        assert!(args("--encoders 13").is_err());
+        assert!(args("--drop 2").is_err());
+        assert!(args("--link").is_err());
+        assert!(args("0=spin").is_err());
+    }
+
+    #[test]
+    fn test_silent_host_returns_board_to_default_baud() {
+        let signals = Signals::default();
+        let mut capture = Box::new([EdgeSample::default(); CAPTURE_CAPACITY]);
+        let mut board = Board::new(&signals, &mut capture, DEFAULT_ENCODERS);
+        let state = DeviceState::new();
+        let mut queues = Queues::default();
+        let mut baud = BaudLink::new();
+
+        let propose = Packet::SetBaud { baud: 921_600 };
+        handle_command(propose, &state, &mut board, &mut queues, &mut baud, 0);
+        assert_eq!(queues.next_event(), Some(Packet::BaudAck { baud: 921_600 }));
+        assert!(baud.frame_seen(20));
+        for now_ms in (1000..10_000).step_by(1000) {
+            baud.frame_seen(now_ms);
+            check_baud(&mut baud, now_ms + 900, &state, &mut queues);
+        }
+        assert_eq!(baud.baud(), 921_600);
+
+        check_baud(&mut baud, 13_999, &state, &mut queues);
+        assert_eq!(baud.baud(), 921_600);
+        check_baud(&mut baud, 14_000, &state, &mut queues);
+        assert_eq!(baud.baud(), DEFAULT_BAUD);
+    }
+}
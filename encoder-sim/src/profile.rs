@@ -0,0 +1,170 @@
+// encoder-sim/src/profile.rs
+
+use std::f64::consts::TAU;
+use std::str::FromStr;
+
+/// Small xorshift generator, so runs can be repeated from a seed without extra dependencies.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Self(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniformly distributed in `[-amplitude, amplitude)`.
+    pub fn symmetric(&mut self, amplitude: f64) -> f64 {
+        (self.unit() * 2.0 - 1.0) * amplitude
+    }
+
+    /// True with the given probability.
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+}
+
+/// One component of a motion profile, in counts.
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    /// Turning at a fixed speed in counts per second.
+    Constant { speed: f64 },
+    /// Swinging around zero with the given amplitude and period in seconds.
+    Sine { amplitude: f64, period: f64 },
+    /// Wandering with a speed that is drawn anew every cycle, up to the given maximum.
+    Walk { max_speed: f64, position: f64 },
+    /// Jittering around the rest of the profile, drawn anew every cycle.
+    Noise { amplitude: f64 },
+}
+
+/// Scripted motion of one channel, the sum of its terms. The default profile stands still.
+///
+/// Profiles are written as terms joined by `+`, e.g. `sine:400:2+noise:1.5`:
+///
+/// - `const:<counts/s>`
+/// - `sine:<amplitude>:<period s>`
+/// - `walk:<max counts/s>`
+/// - `noise:<amplitude>`
+/// - `idle`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    terms: Vec<Term>,
+}
+
+impl Profile {
+    /// Position in counts at `time` seconds, `dt` seconds after the previous call.
+    pub fn position(&mut self, time: f64, dt: f64, rng: &mut Rng) -> f64 {
+        self.terms
+            .iter_mut()
+            .map(|term| match term {
+                Term::Constant { speed } => *speed * time,
+                Term::Sine { amplitude, period } => *amplitude * (TAU * time / *period).sin(),
+                Term::Walk {
+                    max_speed,
+                    position,
+                } => {
+                    *position += rng.symmetric(*max_speed) * dt;
+                    *position
+                }
+                Term::Noise { amplitude } => rng.symmetric(*amplitude),
+            })
+            .sum()
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut terms = Vec::new();
+        for term in s.split('+') {
+            let mut fields = term.split(':');
+            let kind = fields.next().unwrap_or_default();
+            let values = fields
+                .map(|f| {
+                    f.parse::<f64>()
+                        .ok()
+                        .filter(|v| v.is_finite())
+                        .ok_or_else(|| format!("invalid number {f:?} in {term:?}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let term = match (kind, values.as_slice()) {
+                ("idle", &[]) => continue,
+                ("const", &[speed]) => Term::Constant { speed },
+                ("sine", &[amplitude, period]) if period > 0.0 => Term::Sine { amplitude, period },
+                ("walk", &[max_speed]) => Term::Walk {
+                    max_speed,
+                    position: 0.0,
+                },
+                ("noise", &[amplitude]) => Term::Noise { amplitude },
+                _ => return Err(format!("invalid motion term {term:?}")),
+            };
+            terms.push(term);
+        }
+        Ok(Self { terms })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        assert_eq!("idle".parse(), Ok(Profile::default()));
+        let profile: Profile = "sine:400:2+noise:1.5".parse().unwrap();
+        assert_eq!(
+            profile.terms,
+            [
+                Term::Sine {
+                    amplitude: 400.0,
+                    period: 2.0
+                },
+                Term::Noise { amplitude: 1.5 },
+            ]
+        );
+        assert!("sine:400".parse::<Profile>().is_err());
+        assert!("sine:400:0".parse::<Profile>().is_err());
+        assert!("const:fast".parse::<Profile>().is_err());
+        assert!("spin:10".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn test_positions_follow_the_terms() {
+        let mut rng = Rng::new(7);
+        let mut constant: Profile = "const:-250".parse().unwrap();
+        assert_eq!(constant.position(2.0, 0.01, &mut rng), -500.0);
+
+        let mut sine: Profile = "sine:100:4".parse().unwrap();
+        assert!((sine.position(1.0, 0.01, &mut rng) - 100.0).abs() < 1e-9);
+        assert!(sine.position(2.0, 0.01, &mut rng).abs() < 1e-9);
+
+        // Noise stays within its amplitude, and a walk moves at most its speed per cycle.
+        let mut noisy: Profile = "const:100+noise:2".parse().unwrap();
+        let mut walk: Profile = "walk:50".parse().unwrap();
+        let mut previous = 0.0;
+        for i in 1..1000 {
+            let time = f64::from(i) * 0.01;
+            assert!((noisy.position(time, 0.01, &mut rng) - 100.0 * time).abs() <= 2.0);
+            let position = walk.position(time, 0.01, &mut rng);
+            assert!((position - previous).abs() <= 0.5);
+            previous = position;
+        }
+    }
+}
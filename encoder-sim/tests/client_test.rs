@@ -0,0 +1,92 @@
+// The simulator only runs on Unix, where pseudo-terminals exist.
+#![cfg(unix)]
+
+use encoder_client::{ChannelConfig, EncoderClient};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The simulator process, killed when the test ends.
+struct Simulator {
+    child: Child,
+    link: PathBuf,
+}
+
+impl Simulator {
+    fn start(args: &[&str]) -> Self {
+        let link = std::env::temp_dir().join(format!("encoder-sim-{}", std::process::id()));
+        let child = Command::new(env!("CARGO_BIN_EXE_encoder-sim"))
+            .arg("--link")
+            .arg(&link)
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start the simulator");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !link.exists() {
+            assert!(
+                Instant::now() < deadline,
+                "Simulator did not create its PTY"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        Self { child, link }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.link);
+    }
+}
+
+#[test]
+fn test_client_streams_from_simulator() {
+    let sim = Simulator::start(&["--seed", "1", "0=const:100", "3=const:-50"]);
+    let client = EncoderClient::spawn(sim.link.to_str().unwrap()).unwrap();
+
+    let info = client
+        .wait_for_device_info(Duration::from_secs(2))
+        .expect("No heartbeat from the simulator");
+    assert_eq!(info.serial_number, 0x0053_494D);
+
+    thread::sleep(Duration::from_millis(500));
+    let counts = client.get_counts();
+    assert!(counts[0] > 0, "Channel 0 did not move: {counts:?}");
+    assert!(counts[3] < 0, "Channel 3 did not move: {counts:?}");
+    assert_eq!(counts[1], 0);
+    assert!(client.get_sequence() > 0);
+
+    // Commands go through the firmware's command handling.
+    client.set_count(1, 5000).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.get_counts()[1], 5000);
+
+    // Channel 0 has moved more than one revolution of 64 counts by now.
+    client.set_modulo(0, 64).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert!(client.get_revolutions()[0] > 0);
+    assert!((0..64).contains(&client.get_counts()[0]));
+    assert!(client.get_turns(0).unwrap() > 1.0);
+
+    // Inverting channel 3 turns its motion round, and disabling it freezes its count.
+    let mut config = ChannelConfig::new(3);
+    config.inverted = true;
+    config.set_name("Reitstock ë");
+    client.set_channel_config(config).unwrap();
+    let before = client.get_counts()[3];
+    thread::sleep(Duration::from_millis(200));
+    assert!(client.get_counts()[3] > before);
+    assert_eq!(client.read_channel_configs().unwrap()[3], config);
+
+    config.enabled = false;
+    client.set_channel_config(config).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let frozen = client.get_counts()[3];
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.get_counts()[3], frozen);
+}
@@ -0,0 +1,181 @@
+// shared/src/button.rs
+
+use crate::types::{ButtonEvent, ButtonGesture};
+
+/// Time in milliseconds a switch reading must stay unchanged before it is accepted.
+pub const DEBOUNCE_MS: u32 = 10;
+
+/// Time in milliseconds a switch must be held before a long press is reported.
+pub const LONG_PRESS_MS: u32 = 800;
+
+/// Maximum gap in milliseconds between a release and the next press for a double click.
+pub const DOUBLE_CLICK_MS: u32 = 300;
+
+/// Time-based debouncer for a single push switch input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Debouncer {
+    stable: bool,
+    candidate: bool,
+    since: u32,
+}
+
+impl Debouncer {
+    pub fn new() -> Self {
+        Self {
+            stable: false,
+            candidate: false,
+            since: 0,
+        }
+    }
+
+    /// Feeds a raw reading and returns the debounced state.
+    pub fn update(&mut self, raw: bool, now_ms: u32) -> bool {
+        if raw != self.candidate {
+            self.candidate = raw;
+            self.since = now_ms;
+        } else if raw != self.stable && now_ms.wrapping_sub(self.since) >= DEBOUNCE_MS {
+            self.stable = raw;
+        }
+        self.stable
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.stable
+    }
+}
+
+impl Default for Debouncer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turns a debounced switch state into press, release, long-press and double-click events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonClassifier {
+    encoder_id: u8,
+    pressed: bool,
+    pressed_at: u32,
+    long_press_sent: bool,
+    /// Whether the current press completed a double click.
+    double_click: bool,
+    /// Release time of the last plain click, which may start a double click.
+    last_click_at: Option<u32>,
+}
+
+impl ButtonClassifier {
+    pub fn new(encoder_id: u8) -> Self {
+        Self {
+            encoder_id,
+            pressed: false,
+            pressed_at: 0,
+            long_press_sent: false,
+            double_click: false,
+            last_click_at: None,
+        }
+    }
+
+    /// Feeds the debounced switch state and returns an event if one occurred.
+    pub fn update(&mut self, pressed: bool, now_ms: u32) -> Option<ButtonEvent> {
+        if pressed && !self.pressed {
+            self.pressed = true;
+            self.pressed_at = now_ms;
+            self.long_press_sent = false;
+
+            let gesture = match self.last_click_at.take() {
+                Some(t) if now_ms.wrapping_sub(t) <= DOUBLE_CLICK_MS => ButtonGesture::DoubleClick,
+                _ => ButtonGesture::None,
+            };
+            self.double_click = gesture == ButtonGesture::DoubleClick;
+            return Some(self.event(true, now_ms, gesture));
+        }
+
+        if !pressed && self.pressed {
+            self.pressed = false;
+            // A long press or the second half of a double click never starts a new double click.
+            self.last_click_at = if self.long_press_sent || self.double_click {
+                None
+            } else {
+                Some(now_ms)
+            };
+            return Some(self.event(false, now_ms, ButtonGesture::None));
+        }
+
+        if self.pressed
+            && !self.long_press_sent
+            && now_ms.wrapping_sub(self.pressed_at) >= LONG_PRESS_MS
+        {
+            self.long_press_sent = true;
+            return Some(self.event(true, now_ms, ButtonGesture::LongPress));
+        }
+
+        None
+    }
+
+    fn event(&self, pressed: bool, timestamp: u32, gesture: ButtonGesture) -> ButtonEvent {
+        ButtonEvent {
+            encoder_id: self.encoder_id,
+            pressed,
+            timestamp,
+            gesture,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer_ignores_short_glitches() {
+        let mut d = Debouncer::new();
+        assert!(!d.update(true, 0));
+        assert!(!d.update(false, 3));
+        assert!(!d.update(false, 20));
+        assert!(!d.update(true, 21));
+        assert!(d.update(true, 21 + DEBOUNCE_MS));
+    }
+
+    #[test]
+    fn test_click_and_release() {
+        let mut c = ButtonClassifier::new(2);
+        let press = c.update(true, 100).unwrap();
+        assert_eq!(
+            (press.encoder_id, press.pressed, press.gesture),
+            (2, true, ButtonGesture::None)
+        );
+        assert!(c.update(true, 150).is_none());
+        let release = c.update(false, 200).unwrap();
+        assert!(!release.pressed);
+        assert_eq!(release.timestamp, 200);
+    }
+
+    #[test]
+    fn test_long_press_fires_once() {
+        let mut c = ButtonClassifier::new(0);
+        c.update(true, 0);
+        assert!(c.update(true, LONG_PRESS_MS - 1).is_none());
+        let long = c.update(true, LONG_PRESS_MS).unwrap();
+        assert_eq!(long.gesture, ButtonGesture::LongPress);
+        assert!(c.update(true, LONG_PRESS_MS * 2).is_none());
+
+        // Pressing again right after a long press is not a double click.
+        c.update(false, LONG_PRESS_MS * 2);
+        let press = c.update(true, LONG_PRESS_MS * 2 + 50).unwrap();
+        assert_eq!(press.gesture, ButtonGesture::None);
+    }
+
+    #[test]
+    fn test_double_click() {
+        let mut c = ButtonClassifier::new(0);
+        c.update(true, 0);
+        c.update(false, 80);
+        let second = c.update(true, 80 + DOUBLE_CLICK_MS).unwrap();
+        assert_eq!(second.gesture, ButtonGesture::DoubleClick);
+
+        // A third quick press starts over instead of chaining double clicks.
+        c.update(false, 500);
+        let third = c.update(true, 550).unwrap();
+        assert_eq!(third.gesture, ButtonGesture::None);
+    }
+}
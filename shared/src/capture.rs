@@ -0,0 +1,179 @@
+// shared/src/capture.rs
+
+use crate::types::{CAPTURE_CHUNK_SAMPLES, CaptureChunk, CaptureCommand, EdgeSample};
+
+/// Number of steps the firmware can hold in one capture.
+pub const CAPTURE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaptureState {
+    Idle,
+    Recording {
+        start_us: u32,
+        /// Recording time in microseconds, if limited.
+        duration_us: Option<u32>,
+        limit: usize,
+    },
+    Sending {
+        next: usize,
+    },
+}
+
+/// Records every step into a buffer, then hands the capture out in chunks.
+///
+/// Recording stops at the sample limit, the time limit or when the buffer is full. A capture
+/// started while another one is still being sent replaces it.
+#[derive(Debug)]
+pub struct EdgeRecorder<'a> {
+    buffer: &'a mut [EdgeSample],
+    len: usize,
+    state: CaptureState,
+}
+
+impl<'a> EdgeRecorder<'a> {
+    pub fn new(buffer: &'a mut [EdgeSample]) -> Self {
+        Self {
+            buffer,
+            len: 0,
+            state: CaptureState::Idle,
+        }
+    }
+
+    pub fn start(&mut self, command: CaptureCommand, now_us: u32) {
+        // Chunk offsets are 16 bits wide.
+        let capacity = self.buffer.len().min(usize::from(u16::MAX));
+        let limit = match usize::try_from(command.max_samples) {
+            Ok(0) | Err(_) => capacity,
+            Ok(max) => max.min(capacity),
+        };
+        self.len = 0;
+        self.state = CaptureState::Recording {
+            start_us: now_us,
+            duration_us: (command.duration_ms > 0)
+                .then(|| command.duration_ms.saturating_mul(1000)),
+            limit,
+        };
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.state, CaptureState::Recording { .. })
+    }
+
+    /// Records one step, if a capture is running.
+    pub fn record(&mut self, encoder_id: u8, clockwise: bool, now_us: u32) {
+        if let CaptureState::Recording {
+            start_us, limit, ..
+        } = self.state
+            && self.len < limit
+        {
+            self.buffer[self.len] = EdgeSample {
+                encoder_id,
+                clockwise,
+                time_us: now_us.wrapping_sub(start_us),
+            };
+            self.len += 1;
+        }
+    }
+
+    /// Ends the recording once a limit is reached. Call once per sampling pass, after the
+    /// steps of the pass were recorded.
+    pub fn poll(&mut self, now_us: u32) {
+        if let CaptureState::Recording {
+            start_us,
+            duration_us,
+            limit,
+        } = self.state
+        {
+            let expired = duration_us.is_some_and(|d| now_us.wrapping_sub(start_us) >= d);
+            if expired || self.len >= limit {
+                self.state = CaptureState::Sending { next: 0 };
+            }
+        }
+    }
+
+    /// Returns the next chunk of a finished capture. An empty capture yields a single empty
+    /// chunk so the host still learns that it ended.
+    pub fn next_chunk(&mut self) -> Option<CaptureChunk> {
+        let CaptureState::Sending { next } = self.state else {
+            return None;
+        };
+        let end = (next + CAPTURE_CHUNK_SAMPLES).min(self.len);
+        let mut chunk = CaptureChunk {
+            offset: next as u16,
+            total: self.len as u16,
+            len: (end - next) as u8,
+            samples: [EdgeSample::default(); CAPTURE_CHUNK_SAMPLES],
+        };
+        chunk.samples[..end - next].copy_from_slice(&self.buffer[next..end]);
+        self.state = if end >= self.len {
+            CaptureState::Idle
+        } else {
+            CaptureState::Sending { next: end }
+        };
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_by_sample_count() {
+        let mut buffer = [EdgeSample::default(); 32];
+        let mut recorder = EdgeRecorder::new(&mut buffer);
+        recorder.record(0, true, 5);
+        assert_eq!(recorder.next_chunk(), None);
+
+        recorder.start(
+            CaptureCommand {
+                max_samples: 10,
+                duration_ms: 0,
+            },
+            1000,
+        );
+        for i in 0..12u32 {
+            recorder.record((i % 3) as u8, i % 2 == 0, 1000 + i * 7);
+            recorder.poll(1000 + i * 7);
+        }
+        assert!(!recorder.is_recording());
+
+        let first = recorder.next_chunk().unwrap();
+        assert_eq!((first.offset, first.total, first.len), (0, 10, 8));
+        assert_eq!(
+            first.samples[1],
+            EdgeSample {
+                encoder_id: 1,
+                clockwise: false,
+                time_us: 7,
+            }
+        );
+        assert!(!first.is_last());
+        let second = recorder.next_chunk().unwrap();
+        assert_eq!((second.offset, second.len), (8, 2));
+        assert!(second.is_last());
+        assert_eq!(recorder.next_chunk(), None);
+    }
+
+    #[test]
+    fn test_capture_by_duration() {
+        let mut buffer = [EdgeSample::default(); 32];
+        let mut recorder = EdgeRecorder::new(&mut buffer);
+        recorder.start(
+            CaptureCommand {
+                max_samples: 0,
+                duration_ms: 2,
+            },
+            u32::MAX - 500,
+        );
+        recorder.poll(1000);
+        assert!(recorder.is_recording());
+        recorder.poll(1500);
+        assert!(!recorder.is_recording());
+
+        let chunk = recorder.next_chunk().unwrap();
+        assert_eq!((chunk.total, chunk.len), (0, 0));
+        assert!(chunk.is_last());
+        assert_eq!(recorder.next_chunk(), None);
+    }
+}
@@ -0,0 +1,143 @@
+// shared/src/compare.rs
+
+use crate::types::{CompareCommand, CompareEvent};
+
+/// Number of compare outputs driven by the firmware.
+pub const COMPARE_OUTPUTS: usize = 4;
+
+/// Output number that disables a compare register.
+pub const COMPARE_DISABLED: u8 = 255;
+
+/// Compare register of one encoder, asserting its output while the count lies in a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparator {
+    encoder_id: u8,
+    config: Option<CompareCommand>,
+    active: bool,
+}
+
+impl Comparator {
+    pub fn new(encoder_id: u8) -> Self {
+        Self {
+            encoder_id,
+            config: None,
+            active: false,
+        }
+    }
+
+    /// Applies a new configuration. The comparator starts inactive and reports its state at
+    /// the next update. Returns the release of the output it asserted until now, if any.
+    pub fn configure(
+        &mut self,
+        command: CompareCommand,
+        count: i32,
+        timestamp_us: u32,
+    ) -> Option<CompareEvent> {
+        let released = self.asserted_output().map(|output| CompareEvent {
+            encoder_id: self.encoder_id,
+            output,
+            active: false,
+            count,
+            timestamp_us,
+        });
+        self.config = (!command.is_disabled()).then_some(command);
+        self.active = false;
+        released
+    }
+
+    /// Feeds the current count and returns an event when the output changes.
+    pub fn update(&mut self, count: i32, timestamp_us: u32) -> Option<CompareEvent> {
+        let config = self.config?;
+        let active = (config.low..=config.high).contains(&count);
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(CompareEvent {
+            encoder_id: self.encoder_id,
+            output: config.output,
+            active,
+            count,
+            timestamp_us,
+        })
+    }
+
+    /// The output this comparator asserts right now, if any.
+    pub fn asserted_output(&self) -> Option<u8> {
+        self.config
+            .filter(|_| self.active)
+            .map(|config| config.output)
+    }
+}
+
+/// Combines the comparators into output levels. An output is high while any comparator
+/// routed to it is active.
+pub fn compare_output_levels(comparators: &[Comparator]) -> [bool; COMPARE_OUTPUTS] {
+    let mut levels = [false; COMPARE_OUTPUTS];
+    for output in comparators.iter().filter_map(Comparator::asserted_output) {
+        if let Some(level) = levels.get_mut(usize::from(output)) {
+            *level = true;
+        }
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_crossing() {
+        let mut comparator = Comparator::new(2);
+        assert_eq!(comparator.update(500, 0), None);
+
+        assert_eq!(
+            comparator.configure(CompareCommand::at_or_above(2, 1, 1000), 500, 0),
+            None
+        );
+        assert_eq!(comparator.update(999, 1), None);
+        let event = comparator.update(1000, 2).unwrap();
+        assert!(event.active);
+        assert_eq!((event.encoder_id, event.output, event.count), (2, 1, 1000));
+        assert_eq!(comparator.update(1500, 3), None);
+        assert!(!comparator.update(998, 4).unwrap().active);
+    }
+
+    #[test]
+    fn test_window_and_output_levels() {
+        let mut comparators = [Comparator::new(0), Comparator::new(1)];
+        comparators[0].configure(CompareCommand::window(0, 3, -10, 10), 0, 0);
+        comparators[1].configure(CompareCommand::window(1, 3, 100, 200), 0, 0);
+
+        assert!(comparators[0].update(0, 0).is_some());
+        assert!(comparators[1].update(0, 0).is_none());
+        assert_eq!(
+            compare_output_levels(&comparators),
+            [false, false, false, true]
+        );
+
+        assert!(comparators[0].update(11, 1).is_some());
+        assert_eq!(
+            compare_output_levels(&comparators),
+            [false; COMPARE_OUTPUTS]
+        );
+
+        comparators[0].update(5, 2);
+        let released = comparators[0].configure(CompareCommand::disable(0), 5, 3);
+        assert_eq!(
+            released,
+            Some(CompareEvent {
+                encoder_id: 0,
+                output: 3,
+                active: false,
+                count: 5,
+                timestamp_us: 3,
+            })
+        );
+        assert_eq!(comparators[0].update(5, 4), None);
+        assert_eq!(
+            compare_output_levels(&comparators),
+            [false; COMPARE_OUTPUTS]
+        );
+    }
+}
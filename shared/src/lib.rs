@@ -3,16 +3,51 @@
 //! Contains data structures and serialization logic used by both the firmware and host client.
 
 #![cfg_attr(not(feature = "std"), no_std)]
+pub mod button;
+pub mod capture;
+pub mod compare;
+pub mod modbus;
+pub mod quadrature;
+pub mod registers;
+pub mod snapshot;
 pub mod types;
 pub mod uart_protocol;
 
+pub use button::*;
+pub use capture::*;
+pub use compare::*;
+pub use modbus::*;
+pub use quadrature::*;
+pub use registers::*;
+pub use snapshot::*;
 pub use types::*;
 pub use uart_protocol::*;
 
 pub const PACKET_SIZE: usize = 64;
-pub const MAX_ENCODERS: usize = 8;
 
-pub const PROTOCOL_VERSION: u8 = 1;
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Interval in milliseconds between two sensor data packets.
+pub const SENSOR_PERIOD_MS: u32 = 10;
+
+/// UART baud rate used at boot and whenever a negotiated rate fails.
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+/// Baud rates a host may propose with a `BAUD` packet.
+pub const SUPPORTED_BAUD_RATES: [u32; 7] = [
+    115_200, 230_400, 460_800, 921_600, 1_000_000, 2_000_000, 3_000_000,
+];
+
+/// Time in milliseconds either side waits for a valid frame after switching to a negotiated
+/// baud rate before falling back to `DEFAULT_BAUD`.
+pub const BAUD_CONFIRM_TIMEOUT_MS: u32 = 1000;
+
+/// Time in milliseconds a device keeps a confirmed baud rate without a valid frame from the
+/// host before returning to `DEFAULT_BAUD`. Hosts ping well within it to keep the rate.
+pub const HOST_SILENCE_TIMEOUT_MS: u32 = 5000;
+
+/// Least severe level of log messages the firmware forwards until a host sets another.
+pub const DEFAULT_LOG_LEVEL: LogLevel = LogLevel::Warn;
 
 #[cfg(test)]
 mod tests {
@@ -22,11 +57,30 @@ mod tests {
     fn test_packet_serialization() {
         let original = SensorDataPacket {
             seq: 42,
-            encoders: [1, -2, 3, -4, 5, -6, 7, -8],
+            encoders: [1, -2, 3, -4, 5, -6, 7, -8, 0, 0, 0, 0],
+            encoder_count: 8,
+            revolutions: None,
+            enabled: [true; MAX_ENCODERS],
         };
         let packet = Packet::SensorData(original);
 
         let serialized = serialize_packet(&packet);
         assert_eq!(serialized.as_str(), "$42:1,-2,3,-4,5,-6,7,-8*18\n");
     }
+
+    #[test]
+    fn test_total_movement_does_not_overflow() {
+        let packet = SensorDataPacket::new(1, &[i32::MIN, i32::MAX, 0, 0, 0, 0, 0, -1]);
+        assert_eq!(packet.total_movement(), (1 << 31) + (1 << 31) - 1 + 1);
+    }
+
+    #[test]
+    fn test_max_safe_edge_rate() {
+        let stats = SamplingStats {
+            loops_per_second: 200_000,
+            max_loop_us: 10,
+        };
+        assert_eq!(stats.max_safe_edge_rate(), 50_000);
+        assert_eq!(SamplingStats::default().max_safe_edge_rate(), 0);
+    }
 }
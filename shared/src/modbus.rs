@@ -0,0 +1,419 @@
+// shared/src/modbus.rs
+
+//! Modbus RTU server logic used by the firmware's `modbus` mode.
+//!
+//! Register numbers are zero-based protocol addresses. 32-bit values occupy two consecutive
+//! registers, high word first. Only the first `REGISTER_ENCODERS` encoders are mapped.
+//!
+//! | Table             | Address | Contents                                          |
+//! | ----------------- | ------- | ------------------------------------------------- |
+//! | Input registers   | 0-15    | Counts, `i32` per encoder                         |
+//! | Input registers   | 16-31   | Velocities in counts per second, `i32` per encoder |
+//! | Holding registers | 0-15    | Counts, writing presets the counter               |
+//! | Holding registers | 16      | Server address (1-247)                            |
+//! | Coils             | 0-7     | Writing ON resets encoder `n`, always read OFF    |
+//! | Coils             | 8       | Writing ON resets all encoders, always read OFF   |
+
+use crate::REGISTER_ENCODERS;
+
+/// Server address used until a different one is written to `HOLDING_ADDRESS`.
+pub const MODBUS_DEFAULT_ADDRESS: u8 = 1;
+
+/// Maximum size of an RTU frame, including address and CRC.
+pub const MODBUS_MAX_FRAME: usize = 256;
+
+pub const INPUT_COUNTS: u16 = 0;
+pub const INPUT_VELOCITIES: u16 = 16;
+const INPUT_REGISTERS: u16 = 32;
+
+pub const HOLDING_COUNTS: u16 = 0;
+pub const HOLDING_ADDRESS: u16 = 16;
+const HOLDING_REGISTERS: u16 = 17;
+
+pub const COIL_RESET_ALL: u16 = REGISTER_ENCODERS as u16;
+const COILS: u16 = COIL_RESET_ALL + 1;
+
+const READ_COILS: u8 = 0x01;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+const BROADCAST_ADDRESS: u8 = 0;
+
+/// Modbus exception codes returned for rejected requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModbusException {
+    IllegalFunction = 0x01,
+    IllegalDataAddress = 0x02,
+    IllegalDataValue = 0x03,
+}
+
+/// Access to the device state served over Modbus.
+pub trait ModbusDevice {
+    fn count(&self, encoder: usize) -> i32;
+    fn velocity(&self, encoder: usize) -> i32;
+    fn set_count(&mut self, encoder: usize, count: i32);
+    fn address(&self) -> u8;
+    fn set_address(&mut self, address: u8);
+}
+
+/// Computes the Modbus CRC-16 (polynomial 0xA001, initial value 0xFFFF).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Handles one RTU frame and writes the reply into `response`.
+///
+/// Returns the reply length, or `None` if the frame must be ignored: a bad CRC, another
+/// server's address, or a broadcast, which is executed without a reply.
+pub fn handle_frame(
+    device: &mut impl ModbusDevice,
+    frame: &[u8],
+    response: &mut [u8; MODBUS_MAX_FRAME],
+) -> Option<usize> {
+    if frame.len() < 4 {
+        return None;
+    }
+    let (body, crc) = frame.split_at(frame.len() - 2);
+    if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return None;
+    }
+    let address = body[0];
+    if address != device.address() && address != BROADCAST_ADDRESS {
+        return None;
+    }
+
+    response[0] = device.address();
+    response[1] = body[1];
+    let len = match handle_pdu(device, body[1], &body[2..], &mut response[2..]) {
+        Ok(len) => len + 2,
+        Err(exception) => {
+            response[1] |= 0x80;
+            response[2] = exception as u8;
+            3
+        }
+    };
+    if address == BROADCAST_ADDRESS {
+        return None;
+    }
+
+    let crc = crc16(&response[..len]);
+    response[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+    Some(len + 2)
+}
+
+/// Executes one request and writes the response data after the function code.
+fn handle_pdu(
+    device: &mut impl ModbusDevice,
+    function: u8,
+    data: &[u8],
+    out: &mut [u8],
+) -> Result<usize, ModbusException> {
+    let word = |i: usize| -> Result<u16, ModbusException> {
+        data.get(i..i + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or(ModbusException::IllegalDataValue)
+    };
+
+    match function {
+        READ_COILS => {
+            let (start, quantity) = (word(0)?, word(2)?);
+            check_range(start, quantity, COILS, 2000)?;
+            // Coils are write-only triggers, so every bit reads as OFF.
+            let bytes = quantity.div_ceil(8) as usize;
+            out[0] = bytes as u8;
+            out[1..=bytes].fill(0);
+            Ok(1 + bytes)
+        }
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            let (start, quantity) = (word(0)?, word(2)?);
+            let limit = if function == READ_INPUT_REGISTERS {
+                INPUT_REGISTERS
+            } else {
+                HOLDING_REGISTERS
+            };
+            check_range(start, quantity, limit, 125)?;
+            out[0] = (quantity * 2) as u8;
+            for (i, register) in (start..start + quantity).enumerate() {
+                let value = if function == READ_INPUT_REGISTERS {
+                    read_input(device, register)
+                } else {
+                    read_holding(device, register)
+                };
+                out[1 + i * 2..3 + i * 2].copy_from_slice(&value.to_be_bytes());
+            }
+            Ok(1 + usize::from(quantity) * 2)
+        }
+        WRITE_SINGLE_COIL => {
+            let (coil, value) = (word(0)?, word(2)?);
+            check_range(coil, 1, COILS, 1)?;
+            match value {
+                0xFF00 => write_coil(device, coil),
+                0x0000 => {}
+                _ => return Err(ModbusException::IllegalDataValue),
+            }
+            out[..4].copy_from_slice(&data[..4]);
+            Ok(4)
+        }
+        WRITE_SINGLE_REGISTER => {
+            let (register, value) = (word(0)?, word(2)?);
+            check_range(register, 1, HOLDING_REGISTERS, 1)?;
+            write_holding(device, register, value)?;
+            out[..4].copy_from_slice(&data[..4]);
+            Ok(4)
+        }
+        WRITE_MULTIPLE_REGISTERS => {
+            let (start, quantity) = (word(0)?, word(2)?);
+            check_range(start, quantity, HOLDING_REGISTERS, 123)?;
+            if data.get(4).copied() != Some((quantity * 2) as u8) {
+                return Err(ModbusException::IllegalDataValue);
+            }
+            let values = &data[5..];
+            if values.len() != usize::from(quantity) * 2 {
+                return Err(ModbusException::IllegalDataValue);
+            }
+
+            // A count spanning two registers is applied once both halves are known, so a
+            // counter never briefly holds a mix of old and new words.
+            let mut register = start;
+            let mut i = 0;
+            while register < start + quantity {
+                let value = u16::from_be_bytes([values[i], values[i + 1]]);
+                let is_pair = register < HOLDING_ADDRESS
+                    && register.is_multiple_of(2)
+                    && register + 1 < start + quantity;
+                if is_pair {
+                    let low = u16::from_be_bytes([values[i + 2], values[i + 3]]);
+                    let count = (u32::from(value) << 16 | u32::from(low)) as i32;
+                    device.set_count(usize::from(register / 2), count);
+                    register += 2;
+                    i += 4;
+                } else {
+                    write_holding(device, register, value)?;
+                    register += 1;
+                    i += 2;
+                }
+            }
+            out[..4].copy_from_slice(&data[..4]);
+            Ok(4)
+        }
+        _ => Err(ModbusException::IllegalFunction),
+    }
+}
+
+fn check_range(start: u16, quantity: u16, size: u16, max: u16) -> Result<(), ModbusException> {
+    if quantity == 0 || quantity > max {
+        return Err(ModbusException::IllegalDataValue);
+    }
+    match start.checked_add(quantity) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(ModbusException::IllegalDataAddress),
+    }
+}
+
+/// Returns the high or low word of a 32-bit value stored at a register pair.
+fn half(value: i32, register: u16) -> u16 {
+    if register.is_multiple_of(2) {
+        (value as u32 >> 16) as u16
+    } else {
+        value as u16
+    }
+}
+
+fn read_input(device: &impl ModbusDevice, register: u16) -> u16 {
+    if register < INPUT_VELOCITIES {
+        half(device.count(usize::from(register / 2)), register)
+    } else {
+        let offset = register - INPUT_VELOCITIES;
+        half(device.velocity(usize::from(offset / 2)), offset)
+    }
+}
+
+fn read_holding(device: &impl ModbusDevice, register: u16) -> u16 {
+    if register == HOLDING_ADDRESS {
+        device.address().into()
+    } else {
+        half(device.count(usize::from(register / 2)), register)
+    }
+}
+
+fn write_holding(
+    device: &mut impl ModbusDevice,
+    register: u16,
+    value: u16,
+) -> Result<(), ModbusException> {
+    if register == HOLDING_ADDRESS {
+        let address = u8::try_from(value)
+            .ok()
+            .filter(|a| (1..=247).contains(a))
+            .ok_or(ModbusException::IllegalDataValue)?;
+        device.set_address(address);
+        return Ok(());
+    }
+
+    // Writing a single half keeps the other half of the current count.
+    let encoder = usize::from(register / 2);
+    let current = device.count(encoder) as u32;
+    let count = if register.is_multiple_of(2) {
+        (u32::from(value) << 16) | (current & 0xFFFF)
+    } else {
+        (current & 0xFFFF_0000) | u32::from(value)
+    };
+    device.set_count(encoder, count as i32);
+    Ok(())
+}
+
+fn write_coil(device: &mut impl ModbusDevice, coil: u16) {
+    if coil == COIL_RESET_ALL {
+        for encoder in 0..REGISTER_ENCODERS {
+            device.set_count(encoder, 0);
+        }
+    } else {
+        device.set_count(usize::from(coil), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDevice {
+        counts: [i32; REGISTER_ENCODERS],
+        address: u8,
+    }
+
+    impl ModbusDevice for TestDevice {
+        fn count(&self, encoder: usize) -> i32 {
+            self.counts[encoder]
+        }
+        fn velocity(&self, encoder: usize) -> i32 {
+            -self.counts[encoder]
+        }
+        fn set_count(&mut self, encoder: usize, count: i32) {
+            self.counts[encoder] = count;
+        }
+        fn address(&self) -> u8 {
+            self.address
+        }
+        fn set_address(&mut self, address: u8) {
+            self.address = address;
+        }
+    }
+
+    fn device() -> TestDevice {
+        TestDevice {
+            counts: [0, -2, 0x0001_0002, 0, 0, 0, 0, 0],
+            address: MODBUS_DEFAULT_ADDRESS,
+        }
+    }
+
+    fn request(pdu: &[u8]) -> heapless::Vec<u8, MODBUS_MAX_FRAME> {
+        let mut frame = heapless::Vec::new();
+        frame.extend_from_slice(pdu).unwrap();
+        let crc = crc16(pdu);
+        frame.extend_from_slice(&crc.to_le_bytes()).unwrap();
+        frame
+    }
+
+    fn exchange(
+        device: &mut TestDevice,
+        pdu: &[u8],
+    ) -> Option<heapless::Vec<u8, MODBUS_MAX_FRAME>> {
+        let mut response = [0; MODBUS_MAX_FRAME];
+        let len = handle_frame(device, &request(pdu), &mut response)?;
+        assert_eq!(
+            crc16(&response[..len - 2]).to_le_bytes(),
+            response[len - 2..len]
+        );
+        heapless::Vec::from_slice(&response[..len - 2]).ok()
+    }
+
+    #[test]
+    fn test_crc16_reference_value() {
+        // Read holding registers 0-1 from server 1, as captured from a reference master.
+        assert_eq!(
+            crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]).to_le_bytes(),
+            [0xC4, 0x0B]
+        );
+    }
+
+    #[test]
+    fn test_read_input_register_pairs() {
+        let mut dev = device();
+        let reply = exchange(&mut dev, &[1, 0x04, 0, 2, 0, 4]).unwrap();
+        assert_eq!(
+            reply.as_slice(),
+            &[1, 0x04, 8, 0xFF, 0xFF, 0xFF, 0xFE, 0x00, 0x01, 0x00, 0x02]
+        );
+
+        let reply = exchange(&mut dev, &[1, 0x04, 0, 18, 0, 2]).unwrap();
+        assert_eq!(reply.as_slice(), &[1, 0x04, 4, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_write_count_pair_and_address() {
+        let mut dev = device();
+        let reply = exchange(&mut dev, &[1, 0x10, 0, 0, 0, 2, 4, 0xFF, 0xFF, 0xFF, 0xF6]).unwrap();
+        assert_eq!(reply.as_slice(), &[1, 0x10, 0, 0, 0, 2]);
+        assert_eq!(dev.counts[0], -10);
+
+        exchange(&mut dev, &[1, 0x06, 0, 5, 0, 7]).unwrap();
+        assert_eq!(dev.counts[2], 0x0001_0007);
+
+        exchange(&mut dev, &[1, 0x06, 0, 16, 0, 9]).unwrap();
+        assert_eq!(dev.address, 9);
+        assert!(exchange(&mut dev, &[1, 0x03, 0, 16, 0, 1]).is_none());
+        let reply = exchange(&mut dev, &[9, 0x03, 0, 16, 0, 1]).unwrap();
+        assert_eq!(reply.as_slice(), &[9, 0x03, 2, 0, 9]);
+    }
+
+    #[test]
+    fn test_reset_coils() {
+        let mut dev = device();
+        exchange(&mut dev, &[1, 0x05, 0, 1, 0xFF, 0x00]).unwrap();
+        assert_eq!(dev.counts[1], 0);
+        assert_eq!(dev.counts[2], 0x0001_0002);
+
+        // Broadcasts are executed without a reply.
+        assert!(exchange(&mut dev, &[0, 0x05, 0, 8, 0xFF, 0x00]).is_none());
+        assert_eq!(dev.counts, [0; REGISTER_ENCODERS]);
+    }
+
+    #[test]
+    fn test_exceptions_and_bad_crc() {
+        let mut dev = device();
+        assert_eq!(
+            exchange(&mut dev, &[1, 0x2B, 0, 0]).unwrap().as_slice(),
+            &[1, 0xAB, 1]
+        );
+        assert_eq!(
+            exchange(&mut dev, &[1, 0x04, 0, 30, 0, 4])
+                .unwrap()
+                .as_slice(),
+            &[1, 0x84, 2]
+        );
+        assert_eq!(
+            exchange(&mut dev, &[1, 0x06, 0, 16, 0, 0])
+                .unwrap()
+                .as_slice(),
+            &[1, 0x86, 3]
+        );
+
+        let mut frame = request(&[1, 0x04, 0, 0, 0, 2]);
+        frame[3] ^= 1;
+        assert!(handle_frame(&mut dev, &frame, &mut [0; MODBUS_MAX_FRAME]).is_none());
+    }
+}
@@ -0,0 +1,102 @@
+// shared/src/quadrature.rs
+
+/// A reversal back to the previous A/B state within this many microseconds counts as a glitch.
+pub const GLITCH_WINDOW_US: u32 = 100;
+
+/// Classification of a change in the sampled A/B state of one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// Neither input changed since the previous sample.
+    None,
+    /// A single input changed, as expected for quadrature motion.
+    Step,
+    /// Both inputs changed between two samples, so the step direction was lost.
+    Illegal,
+    /// A single input toggled and returned to its previous state within the glitch window.
+    Glitch,
+}
+
+/// Watches raw A/B samples of one channel for transitions the decoder silently drops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransitionMonitor {
+    state: u8,
+    previous_state: u8,
+    changed_at: u32,
+}
+
+impl TransitionMonitor {
+    pub fn new(a: bool, b: bool) -> Self {
+        let state = Self::encode(a, b);
+        Self {
+            state,
+            previous_state: state,
+            changed_at: 0,
+        }
+    }
+
+    /// Feeds one sample of both inputs and classifies the change since the last sample.
+    pub fn update(&mut self, a: bool, b: bool, now_us: u32) -> Transition {
+        let state = Self::encode(a, b);
+        if state == self.state {
+            return Transition::None;
+        }
+
+        let transition = if state ^ self.state == 0b11 {
+            Transition::Illegal
+        } else if state == self.previous_state
+            && now_us.wrapping_sub(self.changed_at) < GLITCH_WINDOW_US
+        {
+            Transition::Glitch
+        } else {
+            Transition::Step
+        };
+
+        self.previous_state = self.state;
+        self.state = state;
+        self.changed_at = now_us;
+        transition
+    }
+
+    fn encode(a: bool, b: bool) -> u8 {
+        (u8::from(a) << 1) | u8::from(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_quadrature_has_only_steps() {
+        let mut m = TransitionMonitor::new(false, false);
+        let sequence = [(true, false), (true, true), (false, true), (false, false)];
+        for (i, (a, b)) in sequence.into_iter().enumerate() {
+            assert_eq!(m.update(a, b, i as u32 * 1000), Transition::Step);
+        }
+        assert_eq!(m.update(false, false, 5000), Transition::None);
+    }
+
+    #[test]
+    fn test_both_inputs_changing_is_illegal() {
+        let mut m = TransitionMonitor::new(false, false);
+        assert_eq!(m.update(true, true, 10), Transition::Illegal);
+        assert_eq!(m.update(false, true, 2000), Transition::Step);
+    }
+
+    #[test]
+    fn test_fast_reversal_is_glitch() {
+        let mut m = TransitionMonitor::new(false, false);
+        assert_eq!(m.update(true, false, 1000), Transition::Step);
+        assert_eq!(
+            m.update(false, false, 1000 + GLITCH_WINDOW_US - 1),
+            Transition::Glitch
+        );
+
+        // A slow reversal is genuine motion back by one step.
+        assert_eq!(m.update(true, false, 5000), Transition::Step);
+        assert_eq!(
+            m.update(false, false, 5000 + GLITCH_WINDOW_US),
+            Transition::Step
+        );
+    }
+}
@@ -0,0 +1,187 @@
+// shared/src/registers.rs
+
+//! Register map exposed by the firmware's I2C target interface.
+//!
+//! A transaction starts with a one-byte register address; subsequent bytes are read from
+//! or written to consecutive addresses. Multi-byte values are little-endian. Only the first
+//! `REGISTER_ENCODERS` encoders are mapped, and that is the number the map reports.
+//!
+//! | Address     | Access | Contents                                             |
+//! | ----------- | ------ | ---------------------------------------------------- |
+//! | 0x00 - 0x1F | R      | Live counts, `i32` per encoder                       |
+//! | 0x20 - 0x3F | R      | Velocities in counts per second, `i32` per encoder   |
+//! | 0x40        | R      | Status flags, see `STATUS_*`                         |
+//! | 0x41        | R      | Protocol version                                     |
+//! | 0x42        | R      | Number of encoders                                   |
+//! | 0x44 - 0x47 | R      | Uptime in milliseconds, `u32`                        |
+//! | 0x48        | W      | Command register, see `CMD_*`                        |
+//! | 0x50 - 0x6F | R      | Latched snapshot counts, `i32` per encoder           |
+//! | 0x70 - 0x73 | R      | Uptime in milliseconds when the snapshot was latched |
+
+use crate::REGISTER_ENCODERS;
+
+/// Default 7-bit bus address of the I2C target.
+pub const I2C_TARGET_ADDRESS: u8 = 0x42;
+
+pub const REG_COUNTS: u8 = 0x00;
+pub const REG_VELOCITIES: u8 = 0x20;
+pub const REG_STATUS: u8 = 0x40;
+pub const REG_PROTOCOL_VERSION: u8 = 0x41;
+pub const REG_ENCODER_COUNT: u8 = 0x42;
+pub const REG_UPTIME: u8 = 0x44;
+pub const REG_COMMAND: u8 = 0x48;
+pub const REG_SNAPSHOT: u8 = 0x50;
+pub const REG_SNAPSHOT_TIME: u8 = 0x70;
+
+/// Total size of the register map in bytes.
+pub const REGISTER_MAP_SIZE: usize = 0x74;
+
+/// Status flag: the snapshot registers hold a latched sample.
+pub const STATUS_SNAPSHOT_VALID: u8 = 1 << 0;
+/// Status flag: counts were carried over a watchdog reset.
+pub const STATUS_COUNTS_RESTORED: u8 = 1 << 1;
+
+/// Command: latch all live counts and the uptime into the snapshot registers.
+pub const CMD_LATCH_SNAPSHOT: u8 = 0x01;
+/// Command: reset all counters to zero.
+pub const CMD_RESET_ALL: u8 = 0x02;
+/// Command base: `CMD_RESET_ONE | n` resets encoder `n` only.
+pub const CMD_RESET_ONE: u8 = 0x80;
+
+/// A command decoded from a write to the command register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterCommand {
+    LatchSnapshot,
+    ResetAll,
+    Reset(u8),
+}
+
+impl RegisterCommand {
+    /// Decodes a value written to `REG_COMMAND`.
+    pub fn from_byte(value: u8) -> Option<Self> {
+        match value {
+            CMD_LATCH_SNAPSHOT => Some(Self::LatchSnapshot),
+            CMD_RESET_ALL => Some(Self::ResetAll),
+            v if v & CMD_RESET_ONE != 0 && usize::from(v & !CMD_RESET_ONE) < REGISTER_ENCODERS => {
+                Some(Self::Reset(v & !CMD_RESET_ONE))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Byte image of the register map, refreshed before each read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterMap {
+    bytes: [u8; REGISTER_MAP_SIZE],
+}
+
+impl RegisterMap {
+    pub fn new(protocol_version: u8) -> Self {
+        let mut bytes = [0; REGISTER_MAP_SIZE];
+        bytes[usize::from(REG_PROTOCOL_VERSION)] = protocol_version;
+        bytes[usize::from(REG_ENCODER_COUNT)] = REGISTER_ENCODERS as u8;
+        Self { bytes }
+    }
+
+    /// Refreshes the live count, velocity and uptime registers.
+    pub fn update_live(
+        &mut self,
+        counts: &[i32; REGISTER_ENCODERS],
+        velocities: &[i32; REGISTER_ENCODERS],
+        uptime_ms: u32,
+    ) {
+        self.write_i32s(REG_COUNTS, counts);
+        self.write_i32s(REG_VELOCITIES, velocities);
+        self.write_u32(REG_UPTIME, uptime_ms);
+    }
+
+    /// Copies counts into the snapshot registers and marks the snapshot valid.
+    pub fn latch_snapshot(&mut self, counts: &[i32; REGISTER_ENCODERS], uptime_ms: u32) {
+        self.write_i32s(REG_SNAPSHOT, counts);
+        self.write_u32(REG_SNAPSHOT_TIME, uptime_ms);
+        self.set_status(STATUS_SNAPSHOT_VALID, true);
+    }
+
+    pub fn set_status(&mut self, flag: u8, enabled: bool) {
+        let status = &mut self.bytes[usize::from(REG_STATUS)];
+        if enabled {
+            *status |= flag;
+        } else {
+            *status &= !flag;
+        }
+    }
+
+    /// Returns the bytes from `address` to the end of the map; empty if out of range.
+    pub fn read_from(&self, address: u8) -> &[u8] {
+        self.bytes.get(usize::from(address)..).unwrap_or(&[])
+    }
+
+    fn write_i32s(&mut self, address: u8, values: &[i32; REGISTER_ENCODERS]) {
+        for (i, value) in values.iter().enumerate() {
+            let start = usize::from(address) + i * 4;
+            self.bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn write_u32(&mut self, address: u8, value: u32) {
+        let start = usize::from(address);
+        self.bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_live_registers_are_little_endian() {
+        let mut map = RegisterMap::new(1);
+        map.update_live(&[1, -1, 0, 0, 0, 0, 0, 0x0102_0304], &[0; 8], 0xAABB_CCDD);
+
+        assert_eq!(
+            &map.read_from(REG_COUNTS)[..8],
+            &[1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(&map.read_from(REG_COUNTS + 28)[..4], &[4, 3, 2, 1]);
+        assert_eq!(&map.read_from(REG_UPTIME)[..4], &[0xDD, 0xCC, 0xBB, 0xAA]);
+        assert_eq!(map.read_from(REG_PROTOCOL_VERSION)[0], 1);
+        assert_eq!(map.read_from(REG_ENCODER_COUNT)[0], 8);
+        assert!(map.read_from(REGISTER_MAP_SIZE as u8).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_sets_status() {
+        let mut map = RegisterMap::new(1);
+        assert_eq!(map.read_from(REG_STATUS)[0] & STATUS_SNAPSHOT_VALID, 0);
+
+        map.latch_snapshot(&[7; 8], 1000);
+        assert_eq!(
+            map.read_from(REG_STATUS)[0] & STATUS_SNAPSHOT_VALID,
+            STATUS_SNAPSHOT_VALID
+        );
+        assert_eq!(&map.read_from(REG_SNAPSHOT + 4)[..4], &[7, 0, 0, 0]);
+        assert_eq!(
+            &map.read_from(REG_SNAPSHOT_TIME)[..4],
+            &1000u32.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_command_decoding() {
+        assert_eq!(
+            RegisterCommand::from_byte(0x01),
+            Some(RegisterCommand::LatchSnapshot)
+        );
+        assert_eq!(
+            RegisterCommand::from_byte(0x02),
+            Some(RegisterCommand::ResetAll)
+        );
+        assert_eq!(
+            RegisterCommand::from_byte(0x83),
+            Some(RegisterCommand::Reset(3))
+        );
+        assert_eq!(RegisterCommand::from_byte(0x88), None);
+        assert_eq!(RegisterCommand::from_byte(0x00), None);
+    }
+}
@@ -0,0 +1,112 @@
+// shared/src/snapshot.rs
+
+//! Fixed-size binary frame clocked out by the firmware's SPI target interface.
+//!
+//! | Offset | Size | Contents                                         |
+//! | ------ | ---- | ------------------------------------------------ |
+//! | 0      | 1    | Sync byte `0xA5`                                 |
+//! | 1      | 1    | Protocol version                                 |
+//! | 2      | 2    | Reserved, zero                                   |
+//! | 4      | 4    | Snapshot sequence number, `u32`                  |
+//! | 8      | 4    | Microseconds since boot at chip select, `u32`    |
+//! | 12     | 32   | Counts, `i32` per encoder                        |
+//! | 44     | 1    | XOR of bytes 0-43                                |
+//!
+//! Multi-byte values are little-endian. Only the first `REGISTER_ENCODERS` encoders are
+//! included. The header does not depend on the snapshot, so the firmware can queue it before
+//! chip select is asserted.
+
+use crate::{PROTOCOL_VERSION, REGISTER_ENCODERS};
+
+/// First byte of every snapshot frame.
+pub const SNAPSHOT_SYNC: u8 = 0xA5;
+
+/// Number of leading bytes that are identical in every frame.
+pub const SNAPSHOT_HEADER_SIZE: usize = 4;
+
+/// Total size of a snapshot frame in bytes.
+pub const SNAPSHOT_FRAME_SIZE: usize = 45;
+
+/// Counts of all encoders latched at a single instant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotFrame {
+    /// Incremented for every latched snapshot.
+    pub seq: u32,
+    /// Time of the latch in microseconds since boot, wrapping.
+    pub timestamp_us: u32,
+    pub counts: [i32; REGISTER_ENCODERS],
+}
+
+impl SnapshotFrame {
+    /// The constant leading bytes of every frame.
+    pub const HEADER: [u8; SNAPSHOT_HEADER_SIZE] = [SNAPSHOT_SYNC, PROTOCOL_VERSION, 0, 0];
+
+    pub fn to_bytes(&self) -> [u8; SNAPSHOT_FRAME_SIZE] {
+        let mut bytes = [0u8; SNAPSHOT_FRAME_SIZE];
+        bytes[..SNAPSHOT_HEADER_SIZE].copy_from_slice(&Self::HEADER);
+        bytes[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.timestamp_us.to_le_bytes());
+        for (i, count) in self.counts.iter().enumerate() {
+            bytes[12 + i * 4..16 + i * 4].copy_from_slice(&count.to_le_bytes());
+        }
+        bytes[SNAPSHOT_FRAME_SIZE - 1] = checksum(&bytes[..SNAPSHOT_FRAME_SIZE - 1]);
+        bytes
+    }
+
+    /// Decodes a frame, rejecting a wrong length, sync byte, version or checksum.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != SNAPSHOT_FRAME_SIZE || bytes[..SNAPSHOT_HEADER_SIZE] != Self::HEADER {
+            return None;
+        }
+        let (body, check) = bytes.split_at(SNAPSHOT_FRAME_SIZE - 1);
+        if checksum(body) != check[0] {
+            return None;
+        }
+
+        let word = |offset: usize| -> [u8; 4] { bytes[offset..offset + 4].try_into().unwrap() };
+        Some(Self {
+            seq: u32::from_le_bytes(word(4)),
+            timestamp_us: u32::from_le_bytes(word(8)),
+            counts: core::array::from_fn(|i| i32::from_le_bytes(word(12 + i * 4))),
+        })
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let frame = SnapshotFrame {
+            seq: 7,
+            timestamp_us: 0xDEAD_BEEF,
+            counts: [1, -2, 3, -4, 5, -6, i32::MAX, i32::MIN],
+        };
+        let bytes = frame.to_bytes();
+        assert_eq!(&bytes[..SNAPSHOT_HEADER_SIZE], &SnapshotFrame::HEADER);
+        assert_eq!(&bytes[4..8], &[7, 0, 0, 0]);
+        assert_eq!(SnapshotFrame::from_bytes(&bytes), Some(frame));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_corruption() {
+        let mut bytes = SnapshotFrame {
+            seq: 1,
+            timestamp_us: 2,
+            counts: [3; REGISTER_ENCODERS],
+        }
+        .to_bytes();
+        assert!(SnapshotFrame::from_bytes(&bytes[..SNAPSHOT_FRAME_SIZE - 1]).is_none());
+
+        bytes[20] ^= 0x01;
+        assert!(SnapshotFrame::from_bytes(&bytes).is_none());
+
+        // A floating MISO line reads as all ones.
+        assert!(SnapshotFrame::from_bytes(&[0xFF; SNAPSHOT_FRAME_SIZE]).is_none());
+    }
+}
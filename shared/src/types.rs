@@ -1,25 +1,297 @@
 // shared/src/types.rs
 
-/// Maximum number of rotary encoders supported by the system.
-pub const MAX_ENCODERS: usize = 8;
+use crate::compare::COMPARE_DISABLED;
+
+/// Maximum number of rotary encoders the protocol can carry, as many as the largest firmware
+/// build tracks. Each device announces how many it has in [`DeviceInfo::encoder_count`].
+pub const MAX_ENCODERS: usize = 12;
+
+/// Number of encoders on a standard board.
+pub const DEFAULT_ENCODERS: usize = 8;
+
+/// Number of encoders covered by the fixed layouts of the I2C, SPI and Modbus interfaces.
+pub const REGISTER_ENCODERS: usize = 8;
 
 /// Maximum size in bytes for a serialized packet string payload.
-pub const BUFFER_SIZE: usize = 128;
+pub const BUFFER_SIZE: usize = 512;
 
 /// Represents an active reading of all encoder values.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SensorDataPacket {
     /// A monotonically increasing sequence number for this packet.
     pub seq: u32,
-    /// The accumulated array of 8 encoder values.
+    /// The accumulated encoder values, zero past `encoder_count`. Encoders counting modulo a
+    /// revolution report their position within the revolution.
     pub encoders: [i32; MAX_ENCODERS],
+    /// Number of encoders the device has; only their values are transmitted.
+    pub encoder_count: u8,
+    /// Whole revolutions of the encoders counting modulo a revolution, 0 for the others.
+    /// Only present while at least one encoder does.
+    pub revolutions: Option<[i32; MAX_ENCODERS]>,
+    /// Whether each encoder is enabled. Disabled encoders are sent as empty fields and
+    /// read back as 0.
+    pub enabled: [bool; MAX_ENCODERS],
 }
 
 /// Command to reset zero or more encoders on the device.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ResetCommand {
-    /// The target encoder ID (0-7), or 255 to mean "all".
+    /// The target encoder ID, or 255 to mean "all".
+    pub encoder_id: u8,
+}
+
+/// Classification attached to a push-button event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonGesture {
+    /// A plain press or release edge.
+    None,
+    /// The switch has been held longer than the long-press threshold.
+    LongPress,
+    /// The press followed a short click within the double-click window.
+    DoubleClick,
+}
+
+/// A debounced change of an encoder's integrated push switch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonEvent {
+    /// The encoder the switch belongs to.
     pub encoder_id: u8,
+    /// Whether the switch is held down.
+    pub pressed: bool,
+    /// Device uptime in milliseconds when the event was detected.
+    pub timestamp: u32,
+    /// Long-press / double-click classification of the event.
+    pub gesture: ButtonGesture,
+}
+
+/// Count latched on the rising edge of an encoder's index (Z) channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexEvent {
+    /// The encoder whose index pulse was seen.
+    pub encoder_id: u8,
+    /// The encoder count at the index edge, before any homing was applied.
+    pub count: i32,
+    /// Device uptime in milliseconds when the edge was detected.
+    pub timestamp: u32,
+    /// Whether the counter was zeroed at this edge by a pending home command.
+    pub homed: bool,
+}
+
+/// Command making an encoder report its count modulo a number of counts per revolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuloCommand {
+    /// The target encoder ID.
+    pub encoder_id: u8,
+    /// Counts per revolution, at most `i32::MAX`, or 0 to count linearly.
+    pub counts_per_rev: u32,
+}
+
+/// Maximum length in bytes of a channel name.
+pub const CHANNEL_NAME_LEN: usize = 16;
+
+/// Configuration of one encoder channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    /// The encoder ID.
+    pub encoder_id: u8,
+    /// Whether the channel is sampled and transmitted.
+    pub enabled: bool,
+    /// Whether the channel counts the other way round, as if A and B were swapped.
+    pub inverted: bool,
+    /// Number of valid bytes in `name`.
+    name_len: u8,
+    name: [u8; CHANNEL_NAME_LEN],
+}
+
+/// Command to zero an encoder exactly at its next index pulse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomeCommand {
+    /// The target encoder ID.
+    pub encoder_id: u8,
+}
+
+/// Edge of the external trigger input that latches the counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TriggerEdge {
+    /// Low-to-high transitions.
+    #[default]
+    Rising,
+    /// High-to-low transitions.
+    Falling,
+    /// Transitions in either direction.
+    Both,
+}
+
+/// Counts of all encoders latched at an edge of the external trigger input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatchEvent {
+    /// Incremented for every trigger edge, including latches dropped on a full FIFO.
+    pub seq: u16,
+    /// Device uptime in microseconds at the edge, wrapping.
+    pub timestamp_us: u32,
+    /// The counts of all encoders at the edge, zero past `encoder_count`.
+    pub counts: [i32; MAX_ENCODERS],
+    /// Number of encoders the device has; only their counts are transmitted.
+    pub encoder_count: u8,
+}
+
+/// Command configuring the compare register of one encoder.
+///
+/// The chosen output is asserted while the count lies within `low..=high`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompareCommand {
+    /// The target encoder ID.
+    pub encoder_id: u8,
+    /// The compare output to drive (0-3), or 255 to disable the register.
+    pub output: u8,
+    /// Lowest count of the window, inclusive.
+    pub low: i32,
+    /// Highest count of the window, inclusive.
+    pub high: i32,
+}
+
+/// Change of a compare output caused by an encoder count entering or leaving its window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompareEvent {
+    /// The encoder whose count was compared.
+    pub encoder_id: u8,
+    /// The compare output driven by the register.
+    pub output: u8,
+    /// Whether the count is now inside the window.
+    pub active: bool,
+    /// The count that changed the state.
+    pub count: i32,
+    /// Device uptime in microseconds at the change, wrapping.
+    pub timestamp_us: u32,
+}
+
+/// One quadrature step recorded by an edge capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EdgeSample {
+    /// The encoder that stepped.
+    pub encoder_id: u8,
+    /// Whether the step counted up.
+    pub clockwise: bool,
+    /// Microseconds since the capture started.
+    pub time_us: u32,
+}
+
+/// Command starting an edge capture, which ends at whichever limit is reached first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureCommand {
+    /// Number of steps to record, or 0 to fill the device buffer.
+    pub max_samples: u32,
+    /// Recording time in milliseconds, or 0 for no time limit.
+    pub duration_ms: u32,
+}
+
+/// Maximum number of samples carried by one capture chunk.
+pub const CAPTURE_CHUNK_SAMPLES: usize = 8;
+
+/// A slice of a finished edge capture, streamed to the host in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureChunk {
+    /// Index of the first sample of this chunk within the capture.
+    pub offset: u16,
+    /// Number of samples in the whole capture.
+    pub total: u16,
+    /// Number of valid entries in `samples`.
+    pub len: u8,
+    pub samples: [EdgeSample; CAPTURE_CHUNK_SAMPLES],
+}
+
+/// Signal-quality counters of one encoder channel, accumulated since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncoderDiagnostics {
+    /// The encoder these counters belong to.
+    pub encoder_id: u8,
+    /// Samples where both A and B changed at once, each one a lost count.
+    pub illegal_transitions: u32,
+    /// Single-input pulses shorter than the glitch window.
+    pub glitches: u32,
+}
+
+/// Core 1 sampling loop statistics over the last one-second window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SamplingStats {
+    /// Completed passes over all encoders in the last second.
+    pub loops_per_second: u32,
+    /// Longest time in microseconds between two consecutive samples of the same channel.
+    pub max_loop_us: u32,
+}
+
+/// Why the device last came out of reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    /// The cause could not be determined.
+    Unknown,
+    /// Power-on or brown-out reset.
+    PowerOn,
+    /// The RUN pin was pulled low.
+    RunPin,
+    /// Reset requested by an attached debugger.
+    Debugger,
+    /// The hardware watchdog expired.
+    WatchdogTimeout,
+    /// The firmware forced a reset through the watchdog.
+    WatchdogForced,
+}
+
+/// Identification sent at boot and then periodically as a heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceInfo {
+    /// Random value chosen at every boot, used by hosts to detect device resets.
+    pub session_id: u32,
+    /// Cause of the reset that started this session.
+    pub reset_reason: ResetReason,
+    /// Version of the wire protocol spoken by the firmware.
+    pub protocol_version: u8,
+    /// Whether the counts were carried over from before the reset by the firmware itself.
+    pub counts_restored: bool,
+    /// The 64-bit unique ID of the board's flash chip, serving as its serial number.
+    pub serial_number: u64,
+    /// Number of encoder channels the firmware samples, at most `MAX_ENCODERS`.
+    pub encoder_count: u8,
+    /// The channels with an index (Z) input, bit `n` for encoder `n`.
+    pub index_inputs: u16,
+}
+
+impl DeviceInfo {
+    /// Whether an encoder has an index input, and so can be homed.
+    pub fn has_index(&self, encoder_id: u8) -> bool {
+        encoder_id < self.encoder_count && self.index_inputs & (1 << encoder_id) != 0
+    }
+}
+
+/// Maximum length in bytes of the text of a forwarded log message, so the longest one still
+/// fits an addressed frame.
+pub const LOG_TEXT_LEN: usize = 96;
+
+/// Severity of a log message forwarded by the firmware, from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// A firmware log message forwarded over the link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogMessage {
+    pub level: LogLevel,
+    /// Number of valid bytes in `text`.
+    len: u8,
+    text: [u8; LOG_TEXT_LEN],
+}
+
+/// Command to overwrite the count of a single encoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetCountCommand {
+    /// The target encoder ID.
+    pub encoder_id: u8,
+    /// The new count value.
+    pub count: i32,
 }
 
 /// The top-level protocol message.
@@ -29,19 +301,134 @@ pub enum Packet {
     SensorData(SensorDataPacket),
     /// Command requesting to reset active counters.
     Reset(ResetCommand),
+    /// Push-button press, release or gesture.
+    Button(ButtonEvent),
+    /// Index pulse seen on an encoder's Z channel.
+    Index(IndexEvent),
+    /// Command arming a home-on-next-index for one encoder.
+    Home(HomeCommand),
+    /// Periodic signal-quality counters of one channel.
+    Diagnostics(EncoderDiagnostics),
+    /// Periodic Core 1 sampling rate report.
+    Sampling(SamplingStats),
+    /// Boot session identification and heartbeat.
+    DeviceInfo(DeviceInfo),
+    /// Command overwriting an encoder count.
+    SetCount(SetCountCommand),
+    /// Command rebooting the device into the RP2040 USB bootloader.
+    EnterBootloader,
     /// Diagnostic ping.
     Ping { timestamp: u32 },
     /// Diagnostic pong.
     Pong { timestamp: u32 },
+    /// Request from a multi-drop bus master for the addressed board's counts and events.
+    Poll,
+    /// Request for an immediate sensor data packet with the current counts.
+    GetCounts,
+    /// All counts latched by the external trigger input.
+    Latch(LatchEvent),
+    /// Command selecting the trigger edge that latches the counts.
+    SetTriggerEdge(TriggerEdge),
+    /// Command configuring an encoder's compare register.
+    SetCompare(CompareCommand),
+    /// A compare output was asserted or released.
+    Compare(CompareEvent),
+    /// Command starting an edge capture.
+    StartCapture(CaptureCommand),
+    /// Part of a finished edge capture.
+    CaptureData(CaptureChunk),
+    /// Command proposing a new UART baud rate.
+    SetBaud { baud: u32 },
+    /// Reply to a baud rate proposal with the rate the device switches to after sending it.
+    BaudAck { baud: u32 },
+    /// Log message forwarded by the firmware.
+    Log(LogMessage),
+    /// Command setting the least severe level of forwarded log messages, or `None` to
+    /// forward none.
+    SetLogLevel(Option<LogLevel>),
+    /// Command configuring an encoder's counts per revolution.
+    SetModulo(ModuloCommand),
+    /// Command replacing a channel's configuration.
+    SetChannel(ChannelConfig),
+    /// Request for a channel's configuration.
+    GetChannel { encoder_id: u8 },
+    /// A channel's configuration, in reply to a request.
+    Channel(ChannelConfig),
 }
 
 impl SensorDataPacket {
-    pub fn new(seq: u32, encoders: [i32; MAX_ENCODERS]) -> Self {
-        Self { seq, encoders }
+    /// Builds a packet for a device with as many encoders as `counts` holds, at most
+    /// `MAX_ENCODERS`.
+    pub fn new(seq: u32, counts: &[i32]) -> Self {
+        let encoder_count = counts.len().min(MAX_ENCODERS);
+        let mut encoders = [0; MAX_ENCODERS];
+        encoders[..encoder_count].copy_from_slice(&counts[..encoder_count]);
+        Self {
+            seq,
+            encoders,
+            encoder_count: encoder_count as u8,
+            revolutions: None,
+            enabled: [true; MAX_ENCODERS],
+        }
+    }
+
+    /// The values of the device's encoders.
+    pub fn counts(&self) -> &[i32] {
+        &self.encoders[..usize::from(self.encoder_count)]
     }
 
-    pub fn total_movement(&self) -> i32 {
-        self.encoders.iter().map(|&x| x.abs()).sum()
+    /// Marks an encoder as disabled and clears its values, as they are not transmitted.
+    pub fn disable(&mut self, encoder: usize) {
+        self.enabled[encoder] = false;
+        self.encoders[encoder] = 0;
+        if let Some(revolutions) = &mut self.revolutions {
+            revolutions[encoder] = 0;
+        }
+    }
+
+    /// Builds a packet from the total counts, splitting those of encoders with a nonzero
+    /// entry in `counts_per_rev` into the position within the revolution and whole
+    /// revolutions.
+    pub fn with_modulo(seq: u32, counts: &[i32], counts_per_rev: &[u32]) -> Self {
+        let mut data = Self::new(seq, counts);
+        let encoder_count = usize::from(data.encoder_count);
+        if counts_per_rev[..encoder_count.min(counts_per_rev.len())]
+            .iter()
+            .all(|&cpr| cpr == 0)
+        {
+            return data;
+        }
+        let mut revolutions = [0; MAX_ENCODERS];
+        for (i, &cpr) in counts_per_rev.iter().enumerate().take(encoder_count) {
+            if let Ok(cpr @ 1..) = i32::try_from(cpr) {
+                revolutions[i] = data.encoders[i].div_euclid(cpr);
+                data.encoders[i] = data.encoders[i].rem_euclid(cpr);
+            }
+        }
+        data.revolutions = Some(revolutions);
+        data
+    }
+
+    /// The total counts, with the revolutions of encoders counting modulo a revolution
+    /// added back on. Wraps like the device's counters.
+    pub fn total_counts(&self, counts_per_rev: &[u32]) -> [i32; MAX_ENCODERS] {
+        let Some(revolutions) = self.revolutions else {
+            return self.encoders;
+        };
+        core::array::from_fn(|i| {
+            let counts_per_rev = counts_per_rev.get(i).copied().unwrap_or(0);
+            (revolutions[i] as u32)
+                .wrapping_mul(counts_per_rev)
+                .wrapping_add(self.encoders[i] as u32) as i32
+        })
+    }
+
+    /// Sum of the distances of all counts from zero. Wide enough that it cannot overflow.
+    pub fn total_movement(&self) -> u64 {
+        self.encoders
+            .iter()
+            .map(|&x| u64::from(x.unsigned_abs()))
+            .sum()
     }
 
     pub fn has_movement(&self, previous: &SensorDataPacket) -> bool {
@@ -65,3 +452,214 @@ impl ResetCommand {
         self.encoder_id == 255
     }
 }
+
+impl CompareCommand {
+    /// Asserts `output` while the count is at or above `target`.
+    pub fn at_or_above(encoder_id: u8, output: u8, target: i32) -> Self {
+        Self::window(encoder_id, output, target, i32::MAX)
+    }
+
+    /// Asserts `output` while the count is at or below `target`.
+    pub fn at_or_below(encoder_id: u8, output: u8, target: i32) -> Self {
+        Self::window(encoder_id, output, i32::MIN, target)
+    }
+
+    /// Asserts `output` while the count lies within `low..=high`.
+    pub fn window(encoder_id: u8, output: u8, low: i32, high: i32) -> Self {
+        Self {
+            encoder_id,
+            output,
+            low,
+            high,
+        }
+    }
+
+    /// Disables the compare register of an encoder.
+    pub fn disable(encoder_id: u8) -> Self {
+        Self::window(encoder_id, COMPARE_DISABLED, 0, 0)
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.output == COMPARE_DISABLED
+    }
+}
+
+impl CaptureChunk {
+    /// The valid samples of this chunk.
+    pub fn samples(&self) -> &[EdgeSample] {
+        &self.samples[..usize::from(self.len).min(CAPTURE_CHUNK_SAMPLES)]
+    }
+
+    /// Whether this is the last chunk of its capture.
+    pub fn is_last(&self) -> bool {
+        usize::from(self.offset) + usize::from(self.len) >= usize::from(self.total)
+    }
+}
+
+impl SamplingStats {
+    /// Highest per-channel edge rate in Hz that can be tracked without missing steps.
+    ///
+    /// Every edge must be sampled at least once, so edges may arrive no faster than the
+    /// worst-case loop time; half of that bound is kept as margin.
+    pub fn max_safe_edge_rate(&self) -> u32 {
+        if self.max_loop_us == 0 {
+            return 0;
+        }
+        1_000_000 / self.max_loop_us / 2
+    }
+}
+
+impl ButtonGesture {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::LongPress => 1,
+            Self::DoubleClick => 2,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::None),
+            1 => Some(Self::LongPress),
+            2 => Some(Self::DoubleClick),
+            _ => None,
+        }
+    }
+}
+
+impl TriggerEdge {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Rising => 0,
+            Self::Falling => 1,
+            Self::Both => 2,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Rising),
+            1 => Some(Self::Falling),
+            2 => Some(Self::Both),
+            _ => None,
+        }
+    }
+
+    /// Whether a change of the input from `previous` to `level` is a trigger edge.
+    pub fn matches(&self, previous: bool, level: bool) -> bool {
+        match self {
+            Self::Rising => level && !previous,
+            Self::Falling => !level && previous,
+            Self::Both => level != previous,
+        }
+    }
+}
+
+impl LogLevel {
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::Error => 1,
+            Self::Warn => 2,
+            Self::Info => 3,
+            Self::Debug => 4,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl LogMessage {
+    /// An empty message, filled in with `write!`.
+    pub fn new(level: LogLevel) -> Self {
+        Self {
+            level,
+            len: 0,
+            text: [0; LOG_TEXT_LEN],
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        core::str::from_utf8(&self.text[..usize::from(self.len)]).unwrap_or_default()
+    }
+}
+
+impl core::fmt::Write for LogMessage {
+    /// Appends text, cutting it off at `LOG_TEXT_LEN` bytes. Characters that would break the
+    /// framing are replaced by spaces.
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        append_text(&mut self.text, &mut self.len, s);
+        Ok(())
+    }
+}
+
+impl ChannelConfig {
+    /// An enabled, non-inverted channel without a name, as every channel starts out.
+    pub const fn new(encoder_id: u8) -> Self {
+        Self {
+            encoder_id,
+            enabled: true,
+            inverted: false,
+            name_len: 0,
+            name: [0; CHANNEL_NAME_LEN],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..usize::from(self.name_len)]).unwrap_or_default()
+    }
+
+    /// Replaces the name, cutting it off at `CHANNEL_NAME_LEN` bytes. Characters that would
+    /// break the framing are replaced by spaces.
+    pub fn set_name(&mut self, name: &str) {
+        self.name_len = 0;
+        append_text(&mut self.name, &mut self.name_len, name);
+    }
+}
+
+/// Appends text to a fixed buffer with `len` valid bytes, stopping at the last character
+/// that fits and replacing characters that would break the framing by spaces.
+fn append_text(buf: &mut [u8], len: &mut u8, s: &str) {
+    for c in s.chars() {
+        let c = if c == '*' || c.is_control() { ' ' } else { c };
+        let start = usize::from(*len);
+        let Some(slot) = buf.get_mut(start..start + c.len_utf8()) else {
+            break;
+        };
+        c.encode_utf8(slot);
+        *len += c.len_utf8() as u8;
+    }
+}
+
+impl ResetReason {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Unknown => 0,
+            Self::PowerOn => 1,
+            Self::RunPin => 2,
+            Self::Debugger => 3,
+            Self::WatchdogTimeout => 4,
+            Self::WatchdogForced => 5,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Unknown),
+            1 => Some(Self::PowerOn),
+            2 => Some(Self::RunPin),
+            3 => Some(Self::Debugger),
+            4 => Some(Self::WatchdogTimeout),
+            5 => Some(Self::WatchdogForced),
+            _ => None,
+        }
+    }
+}
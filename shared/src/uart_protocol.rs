@@ -1,5 +1,12 @@
-use crate::types::{BUFFER_SIZE, Packet};
+use crate::types::{
+    BUFFER_SIZE, ButtonEvent, ButtonGesture, CAPTURE_CHUNK_SAMPLES, CHANNEL_NAME_LEN, CaptureChunk,
+    CaptureCommand, ChannelConfig, CompareCommand, CompareEvent, DeviceInfo, EdgeSample,
+    EncoderDiagnostics, HomeCommand, IndexEvent, LatchEvent, LogLevel, LogMessage, MAX_ENCODERS,
+    ModuloCommand, Packet, ResetCommand, ResetReason, SamplingStats, SensorDataPacket,
+    SetCountCommand, TriggerEdge,
+};
 use core::fmt::Write;
+use core::str::FromStr;
 use heapless::String;
 
 /// Computes an XOR checksum of the ASCII payload string.
@@ -7,27 +14,99 @@ pub fn compute_checksum(payload: &str) -> u8 {
     payload.bytes().fold(0, |acc, b| acc ^ b)
 }
 
+/// Address that every board on a multi-drop bus accepts commands from.
+pub const BUS_BROADCAST_ADDRESS: u8 = 0;
+
 /// Serializes a Packet enum into a heapless NMEA-framed string.
 pub fn serialize_packet(packet: &Packet) -> String<BUFFER_SIZE> {
+    frame_payload(&packet_payload(packet))
+}
+
+/// Serializes a packet to or from a board on a multi-drop bus, prefixing the payload with
+/// `@<address>:` so the checksum covers the address.
+pub fn serialize_addressed_packet(address: u8, packet: &Packet) -> String<BUFFER_SIZE> {
+    let mut payload: String<BUFFER_SIZE> = String::new();
+    let _ = write!(&mut payload, "@{}:{}", address, packet_payload(packet));
+    frame_payload(&payload)
+}
+
+/// Wraps a payload in `$` and `*` with its checksum and a trailing newline.
+fn frame_payload(payload: &str) -> String<BUFFER_SIZE> {
+    let checksum = compute_checksum(payload);
+
+    let mut buf: String<BUFFER_SIZE> = String::new();
+    let _ = writeln!(&mut buf, "${}*{:02X}", payload, checksum);
+    buf
+}
+
+/// Formats the payload of a packet, without framing.
+fn packet_payload(packet: &Packet) -> String<BUFFER_SIZE> {
     let mut payload: String<BUFFER_SIZE> = String::new();
     match packet {
         Packet::SensorData(data) => {
+            let _ = write!(&mut payload, "{}:", data.seq);
+            write_values(&mut payload, data.counts(), &data.enabled);
+            if let Some(revolutions) = data.revolutions {
+                let _ = payload.push(';');
+                let count = usize::from(data.encoder_count);
+                write_values(&mut payload, &revolutions[..count], &data.enabled);
+            }
+        }
+        Packet::Reset(cmd) => {
+            let _ = write!(&mut payload, "RST:{}", cmd.encoder_id);
+        }
+        Packet::Button(event) => {
             let _ = write!(
                 &mut payload,
-                "{}:{},{},{},{},{},{},{},{}",
-                data.seq,
-                data.encoders[0],
-                data.encoders[1],
-                data.encoders[2],
-                data.encoders[3],
-                data.encoders[4],
-                data.encoders[5],
-                data.encoders[6],
-                data.encoders[7],
+                "BTN:{},{},{},{}",
+                event.encoder_id,
+                event.pressed as u8,
+                event.timestamp,
+                event.gesture.code(),
             );
         }
-        Packet::Reset(cmd) => {
-            let _ = write!(&mut payload, "RST:{}", cmd.encoder_id);
+        Packet::Index(event) => {
+            let _ = write!(
+                &mut payload,
+                "IDX:{},{},{},{}",
+                event.encoder_id, event.count, event.timestamp, event.homed as u8,
+            );
+        }
+        Packet::Home(cmd) => {
+            let _ = write!(&mut payload, "HOME:{}", cmd.encoder_id);
+        }
+        Packet::Diagnostics(diag) => {
+            let _ = write!(
+                &mut payload,
+                "DIAG:{},{},{}",
+                diag.encoder_id, diag.illegal_transitions, diag.glitches,
+            );
+        }
+        Packet::Sampling(stats) => {
+            let _ = write!(
+                &mut payload,
+                "RATE:{},{}",
+                stats.loops_per_second, stats.max_loop_us
+            );
+        }
+        Packet::DeviceInfo(info) => {
+            let _ = write!(
+                &mut payload,
+                "INFO:{},{},{},{},{:016X},{},{}",
+                info.session_id,
+                info.reset_reason.code(),
+                info.protocol_version,
+                info.counts_restored as u8,
+                info.serial_number,
+                info.encoder_count,
+                info.index_inputs,
+            );
+        }
+        Packet::SetCount(cmd) => {
+            let _ = write!(&mut payload, "SET:{},{}", cmd.encoder_id, cmd.count);
+        }
+        Packet::EnterBootloader => {
+            let _ = write!(&mut payload, "BOOT");
         }
         Packet::Ping { timestamp } => {
             let _ = write!(&mut payload, "PING:{}", timestamp);
@@ -35,21 +114,378 @@ pub fn serialize_packet(packet: &Packet) -> String<BUFFER_SIZE> {
         Packet::Pong { timestamp } => {
             let _ = write!(&mut payload, "PONG:{}", timestamp);
         }
+        Packet::Poll => {
+            let _ = write!(&mut payload, "POLL");
+        }
+        Packet::GetCounts => {
+            let _ = write!(&mut payload, "GET");
+        }
+        Packet::Latch(event) => {
+            let _ = write!(&mut payload, "LAT:{},{}", event.seq, event.timestamp_us);
+            for count in &event.counts[..usize::from(event.encoder_count)] {
+                let _ = write!(&mut payload, ",{}", count);
+            }
+        }
+        Packet::SetTriggerEdge(edge) => {
+            let _ = write!(&mut payload, "TRIG:{}", edge.code());
+        }
+        Packet::SetChannel(config) => write_channel_config(&mut payload, "CHSET", config),
+        Packet::GetChannel { encoder_id } => {
+            let _ = write!(&mut payload, "CHGET:{}", encoder_id);
+        }
+        Packet::Channel(config) => write_channel_config(&mut payload, "CH", config),
+        Packet::SetModulo(cmd) => {
+            let _ = write!(
+                &mut payload,
+                "MOD:{},{}",
+                cmd.encoder_id, cmd.counts_per_rev
+            );
+        }
+        Packet::SetCompare(cmd) => {
+            let _ = write!(
+                &mut payload,
+                "CMP:{},{},{},{}",
+                cmd.encoder_id, cmd.output, cmd.low, cmd.high,
+            );
+        }
+        Packet::StartCapture(cmd) => {
+            let _ = write!(&mut payload, "CAP:{},{}", cmd.max_samples, cmd.duration_ms);
+        }
+        Packet::CaptureData(chunk) => {
+            // Samples are packed as `<encoder><+|-><time hex>` to fit eight per frame.
+            let _ = write!(&mut payload, "CAPD:{},{}", chunk.offset, chunk.total);
+            for sample in chunk.samples() {
+                let direction = if sample.clockwise { '+' } else { '-' };
+                let _ = write!(
+                    &mut payload,
+                    ",{}{}{:X}",
+                    sample.encoder_id, direction, sample.time_us
+                );
+            }
+        }
+        Packet::SetBaud { baud } => {
+            let _ = write!(&mut payload, "BAUD:{}", baud);
+        }
+        Packet::BaudAck { baud } => {
+            let _ = write!(&mut payload, "BAUDOK:{}", baud);
+        }
+        Packet::Compare(event) => {
+            let _ = write!(
+                &mut payload,
+                "CEV:{},{},{},{},{}",
+                event.encoder_id, event.output, event.active as u8, event.count, event.timestamp_us,
+            );
+        }
+        Packet::Log(message) => {
+            // The text goes last and unescaped, so it may contain commas.
+            let _ = write!(
+                &mut payload,
+                "LOG:{},{}",
+                message.level.code(),
+                message.text()
+            );
+        }
+        Packet::SetLogLevel(level) => {
+            let _ = write!(&mut payload, "LOGL:{}", level.map_or(0, |l| l.code()));
+        }
+    }
+    payload
+}
+
+/// Formats the values of the device's encoders, leaving the fields of disabled ones empty.
+fn write_values(payload: &mut String<BUFFER_SIZE>, values: &[i32], enabled: &[bool]) {
+    for (i, (value, enabled)) in values.iter().zip(enabled).enumerate() {
+        if i > 0 {
+            let _ = payload.push(',');
+        }
+        if *enabled {
+            let _ = write!(payload, "{}", value);
+        }
     }
+}
 
-    let checksum = compute_checksum(&payload);
+/// Formats a channel configuration, with the name last so it may contain commas.
+fn write_channel_config(payload: &mut String<BUFFER_SIZE>, tag: &str, config: &ChannelConfig) {
+    let _ = write!(
+        payload,
+        "{}:{},{},{},{}",
+        tag,
+        config.encoder_id,
+        u8::from(config.enabled),
+        u8::from(config.inverted),
+        config.name()
+    );
+}
 
-    let mut buf: String<BUFFER_SIZE> = String::new();
-    let _ = writeln!(&mut buf, "${}*{:02X}", payload, checksum);
-    buf
+/// Parses a single NMEA-framed line back into a Packet, validating its checksum.
+///
+/// Leading noise before the `$` and a trailing newline are ignored, as is a bus address.
+pub fn parse_packet(line: &str) -> Option<Packet> {
+    parse_addressed_packet(line).map(|(_, packet)| packet)
+}
+
+/// Parses a frame together with its bus address, which is `None` for unaddressed frames.
+pub fn parse_addressed_packet(line: &str) -> Option<(Option<u8>, Packet)> {
+    let start_idx = line.find('$')?;
+    let slice = &line[start_idx + 1..];
+
+    let star_idx = slice.find('*')?;
+    let payload = &slice[..star_idx];
+
+    let checksum_hex = slice.get(star_idx + 1..star_idx + 3)?;
+    let expected_checksum = u8::from_str_radix(checksum_hex, 16).ok()?;
+    if compute_checksum(payload) != expected_checksum {
+        return None;
+    }
+
+    match payload.strip_prefix('@') {
+        Some(addressed) => {
+            let (address, payload) = addressed.split_once(':')?;
+            Some((Some(address.parse().ok()?), parse_payload(payload)?))
+        }
+        None => Some((None, parse_payload(payload)?)),
+    }
+}
+
+/// Decodes the checksum-free payload between `$` and `*`.
+fn parse_payload(payload: &str) -> Option<Packet> {
+    match payload {
+        "BOOT" => return Some(Packet::EnterBootloader),
+        "POLL" => return Some(Packet::Poll),
+        "GET" => return Some(Packet::GetCounts),
+        _ => {}
+    }
+
+    let (tag, body) = payload.split_once(':')?;
+    if tag == "LOG" {
+        let (level, text) = body.split_once(',')?;
+        let mut message = LogMessage::new(LogLevel::from_code(level.parse().ok()?)?);
+        message.write_str(text).ok()?;
+        return Some(Packet::Log(message));
+    }
+    if tag == "CHSET" || tag == "CH" {
+        let mut fields = body.splitn(4, ',');
+        let mut config = ChannelConfig::new(next_field(&mut fields)?);
+        config.enabled = next_field::<u8>(&mut fields)? != 0;
+        config.inverted = next_field::<u8>(&mut fields)? != 0;
+        let name = fields.next()?;
+        if name.len() > CHANNEL_NAME_LEN {
+            return None;
+        }
+        config.set_name(name);
+        return Some(if tag == "CH" {
+            Packet::Channel(config)
+        } else {
+            Packet::SetChannel(config)
+        });
+    }
+    // Only sensor data carries a second list, the revolutions.
+    let (body, revolutions) = match body.split_once(';') {
+        Some((body, revolutions)) => (body, Some(revolutions)),
+        None => (body, None),
+    };
+    let mut fields = body.split(',');
+
+    let packet = match tag {
+        "RST" => Packet::Reset(ResetCommand {
+            encoder_id: next_field(&mut fields)?,
+        }),
+        "IDX" => Packet::Index(IndexEvent {
+            encoder_id: next_field(&mut fields)?,
+            count: next_field(&mut fields)?,
+            timestamp: next_field(&mut fields)?,
+            homed: next_field::<u8>(&mut fields)? != 0,
+        }),
+        "HOME" => Packet::Home(HomeCommand {
+            encoder_id: next_field(&mut fields)?,
+        }),
+        "DIAG" => Packet::Diagnostics(EncoderDiagnostics {
+            encoder_id: next_field(&mut fields)?,
+            illegal_transitions: next_field(&mut fields)?,
+            glitches: next_field(&mut fields)?,
+        }),
+        "RATE" => Packet::Sampling(SamplingStats {
+            loops_per_second: next_field(&mut fields)?,
+            max_loop_us: next_field(&mut fields)?,
+        }),
+        "INFO" => Packet::DeviceInfo(DeviceInfo {
+            session_id: next_field(&mut fields)?,
+            reset_reason: ResetReason::from_code(next_field(&mut fields)?)?,
+            protocol_version: next_field(&mut fields)?,
+            counts_restored: next_field::<u8>(&mut fields)? != 0,
+            serial_number: u64::from_str_radix(fields.next()?, 16).ok()?,
+            encoder_count: next_field(&mut fields).filter(|&n| usize::from(n) <= MAX_ENCODERS)?,
+            index_inputs: next_field(&mut fields)?,
+        }),
+        "SET" => Packet::SetCount(SetCountCommand {
+            encoder_id: next_field(&mut fields)?,
+            count: next_field(&mut fields)?,
+        }),
+        "PING" => Packet::Ping {
+            timestamp: next_field(&mut fields)?,
+        },
+        "PONG" => Packet::Pong {
+            timestamp: next_field(&mut fields)?,
+        },
+        "BTN" => Packet::Button(ButtonEvent {
+            encoder_id: next_field(&mut fields)?,
+            pressed: next_field::<u8>(&mut fields)? != 0,
+            timestamp: next_field(&mut fields)?,
+            gesture: ButtonGesture::from_code(next_field(&mut fields)?)?,
+        }),
+        "LAT" => {
+            let seq = next_field(&mut fields)?;
+            let timestamp_us = next_field(&mut fields)?;
+            let mut counts = [0i32; MAX_ENCODERS];
+            let mut encoder_count = 0;
+            for field in fields.by_ref() {
+                *counts.get_mut(encoder_count)? = field.parse().ok()?;
+                encoder_count += 1;
+            }
+            if encoder_count == 0 {
+                return None;
+            }
+            Packet::Latch(LatchEvent {
+                seq,
+                timestamp_us,
+                counts,
+                encoder_count: encoder_count as u8,
+            })
+        }
+        "CMP" => Packet::SetCompare(CompareCommand {
+            encoder_id: next_field(&mut fields)?,
+            output: next_field(&mut fields)?,
+            low: next_field(&mut fields)?,
+            high: next_field(&mut fields)?,
+        }),
+        "CEV" => Packet::Compare(CompareEvent {
+            encoder_id: next_field(&mut fields)?,
+            output: next_field(&mut fields)?,
+            active: next_field::<u8>(&mut fields)? != 0,
+            count: next_field(&mut fields)?,
+            timestamp_us: next_field(&mut fields)?,
+        }),
+        "CAP" => Packet::StartCapture(CaptureCommand {
+            max_samples: next_field(&mut fields)?,
+            duration_ms: next_field(&mut fields)?,
+        }),
+        "CAPD" => {
+            let mut chunk = CaptureChunk {
+                offset: next_field(&mut fields)?,
+                total: next_field(&mut fields)?,
+                len: 0,
+                samples: [EdgeSample::default(); CAPTURE_CHUNK_SAMPLES],
+            };
+            for field in fields.by_ref() {
+                let slot = chunk.samples.get_mut(usize::from(chunk.len))?;
+                *slot = parse_edge_sample(field)?;
+                chunk.len += 1;
+            }
+            Packet::CaptureData(chunk)
+        }
+        "BAUD" => Packet::SetBaud {
+            baud: next_field(&mut fields)?,
+        },
+        "BAUDOK" => Packet::BaudAck {
+            baud: next_field(&mut fields)?,
+        },
+        "TRIG" => Packet::SetTriggerEdge(TriggerEdge::from_code(next_field(&mut fields)?)?),
+        "LOGL" => match next_field(&mut fields)? {
+            0 => Packet::SetLogLevel(None),
+            code => Packet::SetLogLevel(Some(LogLevel::from_code(code)?)),
+        },
+        "CHGET" => Packet::GetChannel {
+            encoder_id: next_field(&mut fields)?,
+        },
+        "MOD" => Packet::SetModulo(ModuloCommand {
+            encoder_id: next_field(&mut fields)?,
+            counts_per_rev: next_field(&mut fields)?,
+        }),
+        _ => {
+            let seq = tag.parse().ok()?;
+            let mut enabled = [true; MAX_ENCODERS];
+            let mut encoders = [0i32; MAX_ENCODERS];
+            let mut encoder_count = 0;
+            for field in fields.by_ref() {
+                let value = encoders.get_mut(encoder_count)?;
+                match field {
+                    "" => enabled[encoder_count] = false,
+                    field => *value = field.parse().ok()?,
+                }
+                encoder_count += 1;
+            }
+            let revolutions = match revolutions {
+                Some(list) => {
+                    let mut list_fields = list.split(',');
+                    let mut revolutions = [0i32; MAX_ENCODERS];
+                    for (value, enabled) in revolutions.iter_mut().zip(enabled).take(encoder_count)
+                    {
+                        let field = list_fields.next()?;
+                        if enabled {
+                            *value = field.parse().ok()?;
+                        } else if !field.is_empty() {
+                            return None;
+                        }
+                    }
+                    if list_fields.next().is_some() {
+                        return None;
+                    }
+                    Some(revolutions)
+                }
+                None => None,
+            };
+            Packet::SensorData(SensorDataPacket {
+                seq,
+                encoders,
+                encoder_count: encoder_count as u8,
+                revolutions,
+                enabled,
+            })
+        }
+    };
+    if revolutions.is_some() && !matches!(packet, Packet::SensorData(_)) {
+        return None;
+    }
+
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(packet)
+}
+
+/// Parses a packed `<encoder><+|-><time hex>` capture sample.
+fn parse_edge_sample(field: &str) -> Option<EdgeSample> {
+    let split = field.find(['+', '-'])?;
+    let (encoder_id, rest) = field.split_at(split);
+    let (direction, time_us) = rest.split_at(1);
+    Some(EdgeSample {
+        encoder_id: encoder_id.parse().ok()?,
+        clockwise: direction == "+",
+        time_us: u32::from_str_radix(time_us, 16).ok()?,
+    })
+}
+
+/// Parses the next comma-separated field of a payload body.
+fn next_field<'a, T: FromStr>(fields: &mut impl Iterator<Item = &'a str>) -> Option<T> {
+    fields.next()?.parse().ok()
 }
 
 /// Utility to quickly mint a new SensorData packet.
-pub fn create_sensor_packet(seq: u32, encoders: [i32; 8]) -> Packet {
+pub fn create_sensor_packet(seq: u32, encoders: &[i32]) -> Packet {
     use crate::types::SensorDataPacket;
     Packet::SensorData(SensorDataPacket::new(seq, encoders))
 }
 
+/// Utility to quickly mint a new HomeCommand packet.
+pub fn create_home_packet(encoder_id: u8) -> Packet {
+    Packet::Home(HomeCommand { encoder_id })
+}
+
+/// Utility to quickly mint a new SetCountCommand packet.
+pub fn create_set_count_packet(encoder_id: u8, count: i32) -> Packet {
+    Packet::SetCount(SetCountCommand { encoder_id, count })
+}
+
 /// Utility to quickly mint a new ResetCommand packet.
 pub fn create_reset_packet(encoder_id: u8) -> Packet {
     use crate::types::ResetCommand;
@@ -69,7 +505,7 @@ mod tests {
 
     #[test]
     fn test_serialize_sensor_data() {
-        let original = SensorDataPacket::new(123, [1, -2, 3, -4, 5, -6, 7, -8]);
+        let original = SensorDataPacket::new(123, &[1, -2, 3, -4, 5, -6, 7, -8]);
         let packet = Packet::SensorData(original);
 
         let serialized = serialize_packet(&packet);
@@ -83,4 +519,278 @@ mod tests {
         let serialized = serialize_packet(&packet);
         assert_eq!(serialized.as_str(), "$RST:3*5C\n");
     }
+
+    #[test]
+    fn test_parse_roundtrip() {
+        let packets = [
+            Packet::SensorData(SensorDataPacket::new(
+                7,
+                &[0, 1, -1, 100, -100, 5, 6, i32::MIN],
+            )),
+            Packet::SensorData(SensorDataPacket::new(
+                8,
+                &[-3, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            )),
+            Packet::SensorData(SensorDataPacket::new(9, &[42])),
+            Packet::Reset(ResetCommand::all()),
+            Packet::Ping { timestamp: 99 },
+            Packet::Pong {
+                timestamp: u32::MAX,
+            },
+            Packet::Button(ButtonEvent {
+                encoder_id: 4,
+                pressed: true,
+                timestamp: 123_456,
+                gesture: ButtonGesture::DoubleClick,
+            }),
+            Packet::Index(IndexEvent {
+                encoder_id: 1,
+                count: -4096,
+                timestamp: 10,
+                homed: true,
+            }),
+            Packet::Home(HomeCommand { encoder_id: 7 }),
+            Packet::Diagnostics(EncoderDiagnostics {
+                encoder_id: 3,
+                illegal_transitions: 12,
+                glitches: u32::MAX,
+            }),
+            Packet::Sampling(SamplingStats {
+                loops_per_second: 250_000,
+                max_loop_us: 9,
+            }),
+            Packet::DeviceInfo(DeviceInfo {
+                session_id: 0xDEAD_BEEF,
+                reset_reason: ResetReason::WatchdogTimeout,
+                protocol_version: 2,
+                counts_restored: true,
+                serial_number: 0xE661_4103_E765_4321,
+                encoder_count: 12,
+                index_inputs: 0b11,
+            }),
+            Packet::SetCount(SetCountCommand {
+                encoder_id: 0,
+                count: -123_456,
+            }),
+            Packet::EnterBootloader,
+            Packet::Poll,
+            Packet::GetCounts,
+            Packet::Latch(LatchEvent {
+                seq: 3,
+                timestamp_us: 1_000_001,
+                counts: [1, -2, 3, -4, 5, -6, 7, -8, 0, 0, 0, 0],
+                encoder_count: 8,
+            }),
+            Packet::SetTriggerEdge(TriggerEdge::Falling),
+            Packet::SetBaud { baud: 921_600 },
+            Packet::BaudAck { baud: 3_000_000 },
+            Packet::SetCompare(CompareCommand::window(5, 2, -100, i32::MAX)),
+            Packet::StartCapture(CaptureCommand {
+                max_samples: 4096,
+                duration_ms: 0,
+            }),
+            Packet::Compare(CompareEvent {
+                encoder_id: 5,
+                output: 2,
+                active: true,
+                count: -100,
+                timestamp_us: 42,
+            }),
+        ];
+
+        for packet in packets {
+            let serialized = serialize_packet(&packet);
+            assert_eq!(parse_packet(&serialized), Some(packet));
+        }
+    }
+
+    #[test]
+    fn test_addressed_frames() {
+        let packet = Packet::SensorData(SensorDataPacket::new(u32::MAX, &[i32::MIN; MAX_ENCODERS]));
+        let serialized = serialize_addressed_packet(247, &packet);
+        assert!(serialized.starts_with("$@247:4294967295:"));
+        assert_eq!(
+            parse_addressed_packet(&serialized),
+            Some((Some(247), packet))
+        );
+        assert_eq!(parse_packet(&serialized), Some(packet));
+
+        assert_eq!(
+            serialize_addressed_packet(3, &Packet::Poll).as_str(),
+            "$@3:POLL*56\n"
+        );
+        assert_eq!(
+            parse_addressed_packet(&serialize_packet(&Packet::Poll)),
+            Some((None, Packet::Poll))
+        );
+        assert_eq!(parse_addressed_packet("$@x:POLL*1D"), None);
+    }
+
+    #[test]
+    fn test_longest_latch_fits_addressed_frame() {
+        let packet = Packet::Latch(LatchEvent {
+            seq: u16::MAX,
+            timestamp_us: u32::MAX,
+            counts: [i32::MIN; MAX_ENCODERS],
+            encoder_count: MAX_ENCODERS as u8,
+        });
+        let serialized = serialize_addressed_packet(247, &packet);
+        assert!(serialized.ends_with('\n'));
+        assert_eq!(parse_packet(&serialized), Some(packet));
+    }
+
+    #[test]
+    fn test_log_messages() {
+        let mut message = LogMessage::new(LogLevel::Warn);
+        write!(&mut message, "Host proposes {} baud, 2*2\r\n", 9600).unwrap();
+        assert_eq!(message.text(), "Host proposes 9600 baud, 2 2  ");
+        let packet = Packet::Log(message);
+        assert_eq!(parse_packet(&serialize_packet(&packet)), Some(packet));
+
+        // The longest message is cut off on a character boundary and still fits a frame.
+        let mut long = LogMessage::new(LogLevel::Debug);
+        for _ in 0..LOG_TEXT_LEN {
+            long.write_str("é").unwrap();
+        }
+        assert_eq!(long.text().len(), LOG_TEXT_LEN);
+        let packet = Packet::Log(long);
+        let serialized = serialize_addressed_packet(247, &packet);
+        assert!(serialized.ends_with('\n'));
+        assert_eq!(parse_packet(&serialized), Some(packet));
+
+        for level in [None, Some(LogLevel::Error), Some(LogLevel::Debug)] {
+            let packet = Packet::SetLogLevel(level);
+            assert_eq!(parse_packet(&serialize_packet(&packet)), Some(packet));
+        }
+        assert!(LogLevel::Error < LogLevel::Warn);
+    }
+
+    #[test]
+    fn test_modulo_counts() {
+        let mut counts_per_rev = [0; MAX_ENCODERS];
+        counts_per_rev[1] = 4096;
+        counts_per_rev[2] = 4096;
+        let counts = [-5, 4096 * 3 + 10, -1, 0, 0, 0, 0, 0];
+        let data = SensorDataPacket::with_modulo(9, &counts, &counts_per_rev);
+        assert_eq!(data.counts(), [-5, 10, 4095, 0, 0, 0, 0, 0]);
+        assert_eq!(data.revolutions.unwrap()[..8], [0, 3, -1, 0, 0, 0, 0, 0]);
+        assert_eq!(data.total_counts(&counts_per_rev)[..8], counts);
+
+        let serialized = serialize_packet(&Packet::SensorData(data));
+        assert!(serialized.starts_with("$9:-5,10,4095,0,0,0,0,0;0,3,-1,0,0,0,0,0*"));
+        assert_eq!(parse_packet(&serialized), Some(Packet::SensorData(data)));
+
+        // Without a modulo axis the frame stays as it was.
+        let linear = SensorDataPacket::with_modulo(9, &counts, &[0; MAX_ENCODERS]);
+        assert_eq!(linear, SensorDataPacket::new(9, &counts));
+        assert_eq!(linear.total_counts(&counts_per_rev)[..8], counts);
+        // Nor does a modulo set for an encoder the device does not have.
+        let short = SensorDataPacket::with_modulo(9, &counts[..2], &counts_per_rev);
+        assert_eq!(short.revolutions.unwrap()[..2], [0, 3]);
+        assert_eq!(
+            SensorDataPacket::with_modulo(9, &counts[..1], &counts_per_rev).revolutions,
+            None
+        );
+
+        // The longest frame still fits.
+        let mut extreme = [i32::MAX; MAX_ENCODERS];
+        extreme[7] = i32::MIN;
+        let data = SensorDataPacket::with_modulo(u32::MAX, &extreme, &[1; MAX_ENCODERS]);
+        let packet = Packet::SensorData(SensorDataPacket {
+            revolutions: Some([i32::MIN; MAX_ENCODERS]),
+            encoders: [i32::MIN; MAX_ENCODERS],
+            ..data
+        });
+        let serialized = serialize_addressed_packet(247, &packet);
+        assert!(serialized.ends_with('\n'));
+        assert_eq!(parse_packet(&serialized), Some(packet));
+
+        let command = Packet::SetModulo(ModuloCommand {
+            encoder_id: 3,
+            counts_per_rev: 36_000,
+        });
+        assert_eq!(serialize_packet(&command).as_str(), "$MOD:3,36000*56\n");
+        assert_eq!(parse_packet(&serialize_packet(&command)), Some(command));
+        assert!(parse_packet("$MOD:3,36000;1*5C").is_none());
+    }
+
+    #[test]
+    fn test_channel_configs() {
+        let mut data =
+            SensorDataPacket::with_modulo(4, &[1, 2, 3, 4, 5, 6, 7, 8], &[8; MAX_ENCODERS]);
+        data.disable(1);
+        data.disable(7);
+        let serialized = serialize_packet(&Packet::SensorData(data));
+        assert!(serialized.starts_with("$4:1,,3,4,5,6,7,;0,,0,0,0,0,0,*"));
+        assert_eq!(parse_packet(&serialized), Some(Packet::SensorData(data)));
+
+        let mut config = ChannelConfig::new(3);
+        config.inverted = true;
+        config.set_name("X axis, left");
+        for packet in [
+            Packet::SetChannel(config),
+            Packet::Channel(config),
+            Packet::GetChannel { encoder_id: 3 },
+        ] {
+            assert_eq!(parse_packet(&serialize_packet(&packet)), Some(packet));
+        }
+        assert!(serialize_packet(&Packet::Channel(config)).starts_with("$CH:3,1,1,X axis, left*"));
+
+        // Names are cut off on a character boundary and cannot break the framing.
+        config.set_name("Spindle*\nhead ëëëëë");
+        assert_eq!(config.name(), "Spindle  head ë");
+        config.enabled = false;
+        let packet = Packet::SetChannel(config);
+        assert_eq!(parse_packet(&serialize_packet(&packet)), Some(packet));
+        assert_eq!(ChannelConfig::new(0).name(), "");
+    }
+
+    #[test]
+    fn test_capture_chunks() {
+        let mut chunk = CaptureChunk {
+            offset: u16::MAX - 8,
+            total: u16::MAX,
+            len: CAPTURE_CHUNK_SAMPLES as u8,
+            samples: [EdgeSample::default(); CAPTURE_CHUNK_SAMPLES],
+        };
+        for (i, sample) in chunk.samples.iter_mut().enumerate() {
+            *sample = EdgeSample {
+                encoder_id: i as u8,
+                clockwise: i % 2 == 0,
+                time_us: u32::MAX - i as u32,
+            };
+        }
+        let packet = Packet::CaptureData(chunk);
+        let serialized = serialize_addressed_packet(247, &packet);
+        assert!(serialized.ends_with('\n'));
+        assert_eq!(parse_packet(&serialized), Some(packet));
+
+        let empty = Packet::CaptureData(CaptureChunk {
+            offset: 0,
+            total: 0,
+            len: 0,
+            samples: [EdgeSample::default(); CAPTURE_CHUNK_SAMPLES],
+        });
+        assert_eq!(parse_packet(&serialize_packet(&empty)), Some(empty));
+        // A sample without a direction.
+        assert_eq!(parse_packet("$CAPD:0,1,3*1E"), None);
+    }
+
+    #[test]
+    fn test_trigger_edges() {
+        assert!(TriggerEdge::Rising.matches(false, true));
+        assert!(!TriggerEdge::Rising.matches(true, false));
+        assert!(TriggerEdge::Falling.matches(true, false));
+        assert!(!TriggerEdge::Falling.matches(true, true));
+        assert!(TriggerEdge::Both.matches(true, false));
+        assert!(TriggerEdge::Both.matches(false, true));
+    }
+
+    #[test]
+    fn test_parse_rejects_corrupt_frames() {
+        assert_eq!(parse_packet("$RST:3*5D\n"), None);
+        assert_eq!(parse_packet("$RST:3"), None);
+        assert_eq!(parse_packet("RST:3*5C"), None);
+        assert_eq!(parse_packet("$1:1,2,3*00"), None);
+    }
 }